@@ -0,0 +1,284 @@
+use digest::Digest;
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+use crate::{
+    BeaconRng, DelayFunction, Membership, SeedCommitment, Unicorn, UnicornError, VdfResult,
+};
+
+/// Finalized round of the beacon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeaconRecord<I> {
+    /// Index of the round, starting from zero.
+    pub round: u64,
+
+    /// Randomness of the previous round mixed into the seed of this one.
+    pub previous_randomness: Option<Vec<u8>>,
+
+    /// Participants the round was restricted to, `None` if anyone could take part.
+    pub members: Option<Vec<I>>,
+
+    /// Stake of every participant the quorums were weighed with,
+    /// `None` if every vote counted the same.
+    pub weights: Option<BTreeMap<I, u64>>,
+
+    /// Commitments the seed was revealed from, sorted by participant.
+    pub commitments: Vec<(I, Vec<u8>)>,
+
+    pub seed: Vec<u8>,
+
+    /// Output of the delay function together with its proof, agreed on by the quorum.
+    pub vdf_result: Vec<u8>,
+
+    pub randomness: Vec<u8>,
+}
+
+impl<I> BeaconRecord<I> {
+    /// Deterministic generator seeded with the randomness of this round.
+    pub fn rng(&self) -> BeaconRng {
+        BeaconRng::from_randomness(&self.randomness)
+    }
+}
+
+/// Produces a chain of randomness where every round depends on the output of the previous one.
+pub struct Beacon<I, C, R, D, F>
+where
+    I: Hash + Eq + Ord,
+    C: SeedCommitment<I>,
+    R: VdfResult<I>,
+    D: Digest,
+    F: DelayFunction,
+{
+    round: u64,
+    unicorn: Unicorn<I, C, R, D, F>,
+    membership: Option<Membership<I>>,
+    records: Vec<BeaconRecord<I>>,
+}
+
+impl<I, C, R, D, F> Beacon<I, C, R, D, F>
+where
+    I: Hash + Eq + Ord + Clone,
+    C: SeedCommitment<I>,
+    R: VdfResult<I>,
+    D: Digest,
+    F: DelayFunction,
+{
+    pub fn new(threshold: usize, delay: F) -> Self {
+        Self::with_unicorn(Unicorn::new(threshold, delay))
+    }
+
+    /// Starts the beacon from a preconfigured state machine, e.g. one with keys attached.
+    pub fn with_unicorn(unicorn: Unicorn<I, C, R, D, F>) -> Self {
+        Beacon {
+            round: 0,
+            unicorn,
+            membership: None,
+            records: vec![],
+        }
+    }
+
+    /// Runs every round with the participants of the `membership`,
+    /// thresholds follow the number of participants.
+    pub fn with_membership(mut self, membership: Membership<I>) -> Self {
+        self.unicorn
+            .set_members(*membership.policy(), membership.members());
+        self.membership = Some(membership);
+        self
+    }
+
+    /// Participants of the rounds, changes are scheduled here.
+    pub fn membership_mut(&mut self) -> Option<&mut Membership<I>> {
+        self.membership.as_mut()
+    }
+
+    /// Index of the round in progress.
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    /// State machine of the round in progress.
+    pub fn unicorn(&self) -> &Unicorn<I, C, R, D, F> {
+        &self.unicorn
+    }
+
+    pub fn unicorn_mut(&mut self) -> &mut Unicorn<I, C, R, D, F> {
+        &mut self.unicorn
+    }
+
+    /// Records the finished round and starts the next one, chained to its randomness.
+    pub fn next_round(&mut self) -> Result<&BeaconRecord<I>, UnicornError> {
+        let record = self
+            .unicorn
+            .record(self.round)
+            .ok_or(UnicornError::RandomnessNotReady)?;
+        let randomness = record.randomness.clone();
+        self.records.push(record);
+
+        self.round += 1;
+        self.unicorn.restart(Some(randomness));
+
+        if let Some(membership) = self.membership.as_mut() {
+            membership.advance();
+            self.unicorn
+                .set_members(*membership.policy(), membership.members());
+        }
+
+        Ok(self.records.last().unwrap())
+    }
+
+    /// All finished rounds, oldest first.
+    pub fn records(&self) -> &[BeaconRecord<I>] {
+        &self.records
+    }
+
+    pub fn latest(&self) -> Option<&BeaconRecord<I>> {
+        self.records.last()
+    }
+}
+
+/// Checks that the records form a continuous chain: round numbers go one after another
+/// and every round is seeded with the randomness of the previous one.
+pub fn verify_chain<I>(records: &[BeaconRecord<I>]) -> bool {
+    let first_is_genesis = match records.first() {
+        Some(first) => first.round != 0 || first.previous_randomness.is_none(),
+        None => true,
+    };
+
+    first_is_genesis
+        && records.windows(2).all(|pair| {
+            pair[1].round == pair[0].round + 1
+                && pair[1].previous_randomness.as_ref() == Some(&pair[0].randomness)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::{Change, PietrzakVerifier, VdfVerifier};
+    use sha2::Sha256;
+
+    type SimpleBeacon =
+        Beacon<u64, SimpleSeedCommitment, SimpleVdfResult, Sha256, PietrzakVerifier>;
+
+    fn run_round(beacon: &mut SimpleBeacon, values: &[u8]) {
+        let reveals = values
+            .iter()
+            .enumerate()
+            .map(|(id, value)| SimpleSeedReveal {
+                id: id as u64,
+                value: vec![*value; 3],
+            })
+            .collect::<Vec<_>>();
+        let ids = (0..values.len() as u64).collect::<Vec<_>>();
+
+        seed_unicorn_with(beacon.unicorn_mut(), reveals).unwrap();
+        beacon.unicorn_mut().finalize_seed().unwrap();
+        solve_unicorn_with(beacon.unicorn_mut(), &ids).unwrap();
+    }
+
+    #[test]
+    pub fn test_chained_rounds() {
+        let mut beacon = SimpleBeacon::new(3, PietrzakVerifier::new(VDF_PARAMS, VDF_DIFFICULTY));
+
+        // Round can't be closed before it produced randomness
+        assert_eq!(beacon.next_round(), Err(UnicornError::RandomnessNotReady));
+
+        // Same reveals in both rounds still produce different seeds
+        for round in 0..2 {
+            assert_eq!(beacon.round(), round);
+            run_round(&mut beacon, &[0, 1, 2]);
+            assert_eq!(beacon.next_round().unwrap().round, round);
+        }
+
+        let records = beacon.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].previous_randomness, None);
+        assert_eq!(
+            hex::encode(&records[0].seed),
+            "4333ddceb169e2f1741ae48779c9b647154fd69affc8b61f050de97a87945ba3"
+        );
+        assert_eq!(
+            records[1].previous_randomness.as_ref(),
+            Some(&records[0].randomness)
+        );
+        assert_ne!(records[0].seed, records[1].seed);
+        assert_ne!(records[0].randomness, records[1].randomness);
+
+        // Records carry enough to check the rounds again
+        let vdf = PietrzakVerifier::new(VDF_PARAMS, VDF_DIFFICULTY);
+        assert_eq!(records[0].commitments.len(), 3);
+        assert!(vdf.verify(&records[0].seed, &records[0].vdf_result));
+
+        assert!(verify_chain(records));
+    }
+
+    #[test]
+    pub fn test_membership_changes() {
+        let membership = Membership::new(vec![0, 1, 2]);
+        let unicorn = new_unicorn(3).with_weights(vec![(0, 5), (3, 2)]);
+        let mut beacon = SimpleBeacon::with_unicorn(unicorn).with_membership(membership);
+        assert_eq!(beacon.unicorn().threshold(), Ok(2));
+
+        let membership = beacon.membership_mut().unwrap();
+        membership.schedule_next(3, Change::Join);
+        membership.schedule_next(4, Change::Join);
+
+        // Newcomers can't take part before their round
+        let early = SimpleSeedCommitment {
+            id: 3,
+            value: vec![3],
+        };
+        assert_eq!(
+            beacon.unicorn_mut().add_seed_commitment(early),
+            Err(UnicornError::NotAMember)
+        );
+
+        run_round(&mut beacon, &[0, 1, 2]);
+        beacon.next_round().unwrap();
+        assert_eq!(beacon.unicorn().threshold(), Ok(4));
+
+        run_round(&mut beacon, &[0, 1, 2, 3, 4]);
+        beacon.next_round().unwrap();
+
+        let members = beacon
+            .records()
+            .iter()
+            .map(|record| record.members.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(members, vec![vec![0, 1, 2], vec![0, 1, 2, 3, 4]]);
+
+        // Weights only count under the stake policy, but are recorded anyway
+        let weights = beacon.records()[1].weights.clone().unwrap();
+        assert_eq!(
+            weights.into_iter().collect::<Vec<_>>(),
+            vec![(0, 5), (3, 2)]
+        );
+    }
+
+    #[test]
+    pub fn test_broken_chain() {
+        let record = |round: u64, previous: Option<u8>, randomness: u8| BeaconRecord::<u64> {
+            round,
+            previous_randomness: previous.map(|p| vec![p]),
+            members: None,
+            weights: None,
+            commitments: vec![],
+            seed: vec![],
+            vdf_result: vec![],
+            randomness: vec![randomness],
+        };
+
+        assert!(verify_chain::<u64>(&[]));
+        assert!(verify_chain(&[record(0, None, 1), record(1, Some(1), 2)]));
+
+        // Skipped round
+        assert!(!verify_chain(&[record(0, None, 1), record(2, Some(1), 2)]));
+
+        // Round isn't seeded with the previous randomness
+        assert!(!verify_chain(&[record(0, None, 1), record(1, Some(3), 2)]));
+
+        // Genesis round can't have a predecessor
+        assert!(!verify_chain(&[record(0, Some(1), 1)]));
+    }
+}
@@ -0,0 +1,149 @@
+//! Byzantine peers of the simulation.
+//!
+//! A byzantine peer runs the honest `PeerProtocol`, but its `Adversary` rewrites what the
+//! protocol sends before the messages reach the network.
+
+use sha2::{Digest, Sha256};
+
+use unicorn_p2p_rng::identity::SigningKey;
+use unicorn_p2p_rng::Change;
+
+use crate::config::Behavior;
+use crate::network::*;
+use crate::protocol::PeerId;
+
+/// Misbehavior of a single peer, `Behavior::Honest` leaves its messages intact.
+pub struct Adversary {
+    pub behavior: Behavior,
+    id: PeerId,
+    num_peers: u32,
+    key: SigningKey,
+
+    /// Messages of other peers received so far.
+    seen: Vec<NetworkMessage>,
+    /// Number of `seen` messages already replayed.
+    replayed: usize,
+}
+
+impl Adversary {
+    pub fn new(behavior: Behavior, id: PeerId, num_peers: u32, key: SigningKey) -> Self {
+        Adversary {
+            behavior,
+            id,
+            num_peers,
+            key,
+            seen: vec![],
+            replayed: 0,
+        }
+    }
+
+    /// The peer received the message.
+    pub fn observe(&mut self, msg: &NetworkMessage) {
+        if self.behavior == Behavior::Replay && msg.id_from() != self.id {
+            self.seen.push(msg.clone());
+        }
+    }
+
+    /// Messages the peer sends to whom, when the protocol broadcasts the `msg`.
+    pub fn broadcast(&mut self, msg: NetworkMessage) -> Vec<(PeerId, NetworkMessage)> {
+        let id = self.id;
+        let everyone = 0..self.num_peers;
+        let others = everyone.clone().filter(move |to| *to != id);
+
+        match (self.behavior, msg) {
+            (Behavior::WithholdCommitment, msg @ NetworkMessage::Commitment(_)) => {
+                vec![(self.id, msg)]
+            }
+
+            (Behavior::Equivocate, NetworkMessage::Commitment(commitment)) => {
+                let mut sent = vec![(self.id, commitment.into())];
+
+                // Nobody but the peer itself knows a secret behind the other commitments
+                for to in others {
+                    let value = fake_value(&commitment.value, to);
+                    let fake = Commitment::new(self.id, commitment.round, value, &self.key);
+                    sent.push((to, fake.into()));
+                }
+
+                sent
+            }
+
+            (Behavior::BogusVdf, NetworkMessage::VdfResult(vdf_result)) => {
+                let mut result = vdf_result.result;
+                for byte in result.iter_mut() {
+                    *byte ^= 0xff;
+                }
+
+                let bogus = VdfResult::new(
+                    self.id,
+                    vdf_result.round,
+                    vdf_result.scheme,
+                    vdf_result.seed,
+                    result,
+                    &self.key,
+                );
+                everyone.map(|to| (to, bogus.clone().into())).collect()
+            }
+
+            (Behavior::Replay, msg) => {
+                let stale = self.seen[self.replayed..].to_vec();
+                self.replayed = self.seen.len();
+
+                let mut sent = everyone
+                    .clone()
+                    .map(|to| (to, msg.clone()))
+                    .collect::<Vec<_>>();
+                for old in stale {
+                    sent.extend(others.clone().map(|to| (to, old.clone())));
+                }
+
+                sent
+            }
+
+            (Behavior::Spoof, msg) => {
+                let victim = (self.id + 1) % self.num_peers;
+                let spoofed = spoof(&msg, victim, &self.key);
+
+                let mut sent = everyone.map(|to| (to, msg.clone())).collect::<Vec<_>>();
+                sent.extend(others.map(|to| (to, spoofed.clone())));
+
+                sent
+            }
+
+            (_, msg) => everyone.map(|to| (to, msg.clone())).collect(),
+        }
+    }
+}
+
+/// Value that differs for every recipient, but is the same in every run.
+fn fake_value(value: &[u8], to: PeerId) -> [u8; 32] {
+    let mut hash = Sha256::new();
+    hash.input(value);
+    hash.input(to.to_be_bytes());
+
+    let mut fake = [0u8; 32];
+    fake.copy_from_slice(&hash.result());
+    fake
+}
+
+/// Message claiming to come from the `victim`, signed by the `key` of the sender.
+fn spoof(msg: &NetworkMessage, victim: PeerId, key: &SigningKey) -> NetworkMessage {
+    match msg {
+        NetworkMessage::Commitment(msg) => {
+            Commitment::new(victim, msg.round, fake_value(&msg.value, victim), key).into()
+        }
+
+        NetworkMessage::Reveal(msg) => {
+            Reveal::new(victim, msg.round, fake_value(&msg.value, victim), key).into()
+        }
+
+        NetworkMessage::VdfResult(msg) => {
+            let result = fake_value(&msg.result, victim).to_vec();
+            VdfResult::new(victim, msg.round, msg.scheme, msg.seed.clone(), result, key).into()
+        }
+
+        NetworkMessage::Membership(msg) => {
+            MembershipChange::new(victim, msg.round, Change::Leave, key).into()
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! State of a round in progress that survives a restart of the participant.
+//!
+//! A checkpoint keeps the messages collected so far rather than the derived state, so
+//! restoring replays them through the usual checks: signatures, commitments and VDF proofs
+//! of a checkpoint are verified just like the ones received from the network.
+
+use digest::Digest;
+use std::hash::Hash;
+
+use crate::wire::{self, get_list, get_option, put_list, put_option};
+use crate::wire::{Reader, WireError, WireId, WireMessage, Writer};
+use crate::{DelayFunction, SeedCommitment, Unicorn, UnicornError, UnicornState, VdfResult};
+
+/// Messages a round collected before the checkpoint, see `Unicorn::checkpoint`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnicornCheckpoint<I, C, R> {
+    pub state: UnicornState,
+    pub previous_randomness: Option<Vec<u8>>,
+    pub commitments: Vec<C>,
+    /// Revealed values, sorted by participant.
+    pub reveals: Vec<(I, Vec<u8>)>,
+    pub seed: Option<Vec<u8>>,
+    pub vdf_results: Vec<R>,
+}
+
+impl<I, C, R, D, F> Unicorn<I, C, R, D, F>
+where
+    I: Hash + Eq + Ord + Clone,
+    C: SeedCommitment<I> + Clone,
+    R: VdfResult<I>,
+    D: Digest,
+    F: DelayFunction,
+{
+    pub fn checkpoint(&self) -> UnicornCheckpoint<I, C, R> {
+        let mut commitments = self.seed_commitments.iter().collect::<Vec<_>>();
+        commitments.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        let mut reveals = self.reveals.clone().into_iter().collect::<Vec<_>>();
+        reveals.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut vdf_results = self.vdf_results.iter().collect::<Vec<_>>();
+        vdf_results.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        UnicornCheckpoint {
+            state: self.state,
+            previous_randomness: self.previous_randomness.clone(),
+            commitments: commitments.into_iter().map(|(_, c)| c.clone()).collect(),
+            reveals,
+            seed: self.seed.clone(),
+            vdf_results: vdf_results.into_iter().map(|(_, r)| r.clone()).collect(),
+        }
+    }
+
+    /// Continues the round from the `checkpoint`, dropping everything collected so far.
+    ///
+    /// Participants, keys and the threshold are not part of the checkpoint, the round should
+    /// be set up the same way it was when the checkpoint was taken.
+    pub fn restore(&mut self, checkpoint: UnicornCheckpoint<I, C, R>) -> Result<(), UnicornError> {
+        self.restart(checkpoint.previous_randomness);
+
+        for commitment in checkpoint.commitments {
+            self.add_seed_commitment(commitment)?;
+        }
+
+        if checkpoint.state == UnicornState::CollectingCommitments {
+            return Ok(());
+        }
+        self.finalize_commitments()?;
+
+        for (id, value) in checkpoint.reveals {
+            self.insert_reveal(id, value)?;
+        }
+
+        if checkpoint.state == UnicornState::CollectingReveals {
+            return Ok(());
+        }
+        self.finalize_seed()?;
+
+        if self.seed != checkpoint.seed {
+            return Err(UnicornError::InvalidCheckpoint);
+        }
+
+        for vdf_result in checkpoint.vdf_results {
+            self.add_vdf_result(vdf_result)?;
+        }
+
+        if checkpoint.state == UnicornState::SeedReady {
+            return Ok(());
+        }
+        self.finalize_vdf_result()
+    }
+}
+
+fn state_id(state: UnicornState) -> u8 {
+    match state {
+        UnicornState::CollectingCommitments => 1,
+        UnicornState::CollectingReveals => 2,
+        UnicornState::SeedReady => 3,
+        UnicornState::RandomnessReady => 4,
+    }
+}
+
+fn state_from_id(id: u8) -> Option<UnicornState> {
+    match id {
+        1 => Some(UnicornState::CollectingCommitments),
+        2 => Some(UnicornState::CollectingReveals),
+        3 => Some(UnicornState::SeedReady),
+        4 => Some(UnicornState::RandomnessReady),
+        _ => None,
+    }
+}
+
+impl<I, C, R> WireMessage for UnicornCheckpoint<I, C, R>
+where
+    I: WireId,
+    C: WireMessage,
+    R: WireMessage,
+{
+    const KIND: u8 = 0x11;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u8(state_id(self.state));
+        put_option(writer, &self.previous_randomness, |r, w| w.put_bytes(r));
+        put_list(writer, &self.commitments, |c, w| {
+            w.put_bytes(&wire::encode(c))
+        });
+        put_list(writer, &self.reveals, |(id, value), w| {
+            id.put(w);
+            w.put_bytes(value);
+        });
+        put_option(writer, &self.seed, |seed, w| w.put_bytes(seed));
+        put_list(writer, &self.vdf_results, |r, w| {
+            w.put_bytes(&wire::encode(r))
+        });
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        let state = reader.get_u8()?;
+
+        Ok(UnicornCheckpoint {
+            state: state_from_id(state).ok_or(WireError::UnknownTag {
+                kind: Self::KIND,
+                tag: state,
+            })?,
+            previous_randomness: get_option(reader, |r| r.get_bytes())?,
+            commitments: get_list(reader, |r| wire::decode(&r.get_bytes()?))?,
+            reveals: get_list(reader, |r| Ok((I::get(r)?, r.get_bytes()?)))?,
+            seed: get_option(reader, |r| r.get_bytes())?,
+            vdf_results: get_list(reader, |r| wire::decode(&r.get_bytes()?))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn reveals() -> Vec<SimpleSeedReveal> {
+        (0..3)
+            .map(|id| SimpleSeedReveal {
+                id,
+                value: vec![id as u8; 3],
+            })
+            .collect()
+    }
+
+    /// Checkpoint as it's read back from disk.
+    fn reloaded(
+        unicorn: &SimpleUnicorn,
+    ) -> UnicornCheckpoint<u64, SimpleSeedCommitment, SimpleVdfResult> {
+        wire::decode(&wire::encode(&unicorn.checkpoint())).unwrap()
+    }
+
+    #[test]
+    pub fn test_restore_every_state() {
+        let mut unicorn = new_unicorn(3).with_previous_randomness(vec![7; 32]);
+        commit_unicorn_with(&mut unicorn, &reveals()).unwrap();
+
+        let mut restored = new_unicorn(3);
+        restored.restore(reloaded(&unicorn)).unwrap();
+        assert_eq!(restored.state(), UnicornState::CollectingCommitments);
+        assert_eq!(restored.commitments(), unicorn.commitments());
+
+        unicorn.finalize_commitments().unwrap();
+        for reveal in reveals().into_iter().take(2) {
+            unicorn.add_seed_reveal(reveal).unwrap();
+        }
+        restored.restore(reloaded(&unicorn)).unwrap();
+        assert_eq!(restored.state(), UnicornState::CollectingReveals);
+
+        // Restored round carries on where the original one stopped
+        for round in [&mut unicorn, &mut restored].iter_mut() {
+            round.add_seed_reveal(reveals().pop().unwrap()).unwrap();
+            round.finalize_seed().unwrap();
+        }
+        assert_eq!(restored.seed(), unicorn.seed());
+
+        solve_unicorn_with(&mut unicorn, &[0, 1, 2]).unwrap();
+        restored.restore(reloaded(&unicorn)).unwrap();
+        assert_eq!(restored.state(), UnicornState::RandomnessReady);
+        assert_eq!(restored.randomness(), unicorn.randomness());
+    }
+
+    #[test]
+    pub fn test_tampered_checkpoint() {
+        let mut unicorn = new_unicorn(3);
+        seed_unicorn_with(&mut unicorn, reveals()).unwrap();
+
+        // Revealed value doesn't match its commitment anymore
+        let mut checkpoint = unicorn.checkpoint();
+        checkpoint.reveals[0].1 = vec![9; 3];
+        assert_eq!(
+            new_unicorn(3).restore(checkpoint),
+            Err(UnicornError::RevealMismatch)
+        );
+
+        unicorn.finalize_seed().unwrap();
+        let mut checkpoint = unicorn.checkpoint();
+        checkpoint.seed = Some(vec![0; 32]);
+        assert_eq!(
+            new_unicorn(3).restore(checkpoint),
+            Err(UnicornError::InvalidCheckpoint)
+        );
+
+        // Not enough commitments for the round it was restored into
+        assert_eq!(
+            new_unicorn(4).restore(unicorn.checkpoint()),
+            Err(UnicornError::NotEnoughSeedCommitments)
+        );
+    }
+}
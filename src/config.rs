@@ -0,0 +1,556 @@
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use unicorn_p2p_rng::{SchemeVerifier, ThresholdPolicy, TranscriptFormat, VdfScheme};
+
+use crate::protocol::PeerId;
+
+/// Parameters of a run, read from a TOML file.
+///
+/// Every field is optional in the file and falls back to its default.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Number of peers that want to agree on a single verifiable random number
+    /// when all of them are simulated in a single process.
+    pub num_peers: u32,
+
+    /// Peers taking part from the first round, the others join in the next one.
+    /// Every peer takes part from the start if omitted
+    pub members: Option<Vec<PeerId>>,
+
+    /// Members that should agree on every step of a round, at least 2/3 of them by default
+    pub threshold: ThresholdPolicy,
+
+    /// Stake of the peers, weighing their votes under the stake threshold policy
+    pub weights: Vec<WeightConfig>,
+
+    /// Minimum delay (sec.) before peer send its commitment
+    pub commitments_delay_min: u64,
+
+    /// Timeout (sec.) in which peers are waiting for other peer's commitments
+    pub commitments_round_timeout: u64,
+
+    /// Timeout (sec.) in which peers are waiting for other peer's reveals of their commitments
+    pub reveals_round_timeout: u64,
+
+    /// Timeout (sec.) in which peers are waiting for other peer's VDF calculation results
+    /// after they calculated and sent its own VDF
+    pub vdf_gathering_timeout: u64,
+
+    /// Number of designated peers calculating VDF at once, the rest only verify their result.
+    /// Every peer calculates VDF if omitted
+    pub evaluators: Option<u32>,
+
+    /// Timeout (sec.) after which the next designated peers start calculating VDF
+    /// if they didn't receive a valid result yet
+    pub evaluator_timeout: u64,
+
+    /// VDF construction every peer calculates and verifies.
+    pub vdf_scheme: VdfScheme,
+
+    /// Size of the VDF class group discriminant in bits.
+    pub vdf_params: u16,
+
+    /// Difficulty of the VDF calculation.
+    /// VDF delay for the most CPU-powerful peer should be at least
+    /// two times more than timeout of commitments gathering
+    pub vdf_difficulty: u64,
+
+    /// Number of threads calculating VDF, one per CPU core if omitted
+    pub vdf_workers: Option<usize>,
+
+    /// Identity and peers of a node running over TCP.
+    pub node: Option<NodeConfig>,
+
+    /// Network and CPU model of the virtual-time simulation.
+    pub simulation: SimulationConfig,
+}
+
+/// `[node]` section of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub id: Option<PeerId>,
+    pub listen: Option<SocketAddr>,
+    /// Hex-encoded secret key of the node.
+    pub key: Option<String>,
+    #[serde(default)]
+    pub peers: Vec<PeerConfig>,
+    /// File finalized rounds are appended to, nothing is stored if omitted. The node
+    /// carries on after the last stored round.
+    pub history: Option<PathBuf>,
+    /// Directory transcripts of the finalized rounds are exported to, nothing is exported
+    /// if omitted.
+    pub transcripts: Option<PathBuf>,
+    /// Format of the exported transcripts, JSON by default.
+    pub transcript_format: Option<TranscriptFormat>,
+    /// File the round in progress is saved to, a restarted node carries on with it.
+    pub checkpoint: Option<PathBuf>,
+}
+
+/// `[[node.peers]]` entry of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    pub id: PeerId,
+    pub addr: SocketAddr,
+    /// Hex-encoded public key of the peer.
+    pub key: String,
+}
+
+/// `[[weights]]` entry: stake of the peer, peers that aren't listed hold none.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WeightConfig {
+    pub peer: PeerId,
+    pub weight: u64,
+}
+
+/// `[simulation]` section of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    /// Time a message takes to reach a peer
+    pub latency: Latency,
+
+    /// Probability that a message is lost
+    pub drop_rate: f64,
+
+    /// Probability that a message is delivered twice
+    pub duplicate_rate: f64,
+
+    /// Overrides of the network model for particular pairs of peers
+    pub links: Vec<LinkConfig>,
+
+    /// Periods when the network is split
+    pub partitions: Vec<PartitionConfig>,
+
+    /// Peers that deviate from the protocol
+    pub adversaries: Vec<AdversaryConfig>,
+
+    /// Peers that leave the protocol while it runs
+    pub leaves: Vec<LeaveConfig>,
+
+    /// Peers that go down and restart from their checkpoints, in virtual time only
+    pub crashes: Vec<CrashConfig>,
+
+    /// Bounds (ms.) of the time a peer spends calculating VDF
+    pub min_vdf_time_ms: u64,
+    pub max_vdf_time_ms: u64,
+
+    /// Simulation stops after this much (sec.) of virtual time
+    pub time_limit: u64,
+}
+
+/// Distribution of the time (ms.) a message takes to reach a peer.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "distribution", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Latency {
+    /// Any delay between the bounds is equally likely
+    Uniform { min_ms: u64, max_ms: u64 },
+    /// At least `min_ms` plus an exponentially distributed tail with mean `mean_ms`,
+    /// so a few messages are much slower than the rest
+    Exponential { min_ms: u64, mean_ms: u64 },
+}
+
+/// `[[simulation.links]]` entry, overrides the network model for messages from one peer to another.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkConfig {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub latency: Option<Latency>,
+    pub drop_rate: Option<f64>,
+    pub duplicate_rate: Option<f64>,
+}
+
+/// `[[simulation.partitions]]` entry: peers of different groups can't reach each other
+/// from `start_ms` until `heal_ms` of virtual time.
+///
+/// Peers that aren't listed in any group form one more group.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartitionConfig {
+    pub groups: Vec<Vec<PeerId>>,
+    #[serde(default)]
+    pub start_ms: u64,
+    /// Partition never heals if omitted
+    pub heal_ms: Option<u64>,
+}
+
+/// `[[simulation.adversaries]]` entry.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdversaryConfig {
+    pub peer: PeerId,
+    pub behavior: Behavior,
+}
+
+/// `[[simulation.leaves]]` entry: the peer leaves at `at_ms`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaveConfig {
+    pub peer: PeerId,
+    pub at_ms: u64,
+}
+
+/// `[[simulation.crashes]]` entry: the peer goes down at `at_ms` and restarts from its
+/// checkpoint `down_ms` later. Messages sent to it meanwhile are lost.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CrashConfig {
+    pub peer: PeerId,
+    pub at_ms: u64,
+    pub down_ms: u64,
+}
+
+/// How a byzantine peer deviates from the protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Behavior {
+    Honest,
+    /// Never sends its commitment to others
+    WithholdCommitment,
+    /// Sends a different commitment to every other peer
+    Equivocate,
+    /// Sends garbage instead of its VDF result
+    BogusVdf,
+    /// Sends messages of other peers it received in earlier rounds once again
+    Replay,
+    /// Sends conflicting messages on behalf of another peer
+    Spoof,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let num_peers = 6;
+
+        Config {
+            num_peers,
+            members: None,
+            threshold: ThresholdPolicy::default(),
+            weights: vec![],
+            commitments_delay_min: 1,
+            commitments_round_timeout: num_peers as u64,
+            reveals_round_timeout: num_peers as u64,
+            vdf_gathering_timeout: num_peers as u64,
+            evaluators: None,
+            evaluator_timeout: num_peers as u64,
+            vdf_scheme: VdfScheme::Pietrzak,
+            vdf_params: 1024,
+            vdf_difficulty: 100_000,
+            vdf_workers: None,
+            node: None,
+            simulation: SimulationConfig::default(),
+        }
+    }
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            latency: Latency::Uniform {
+                min_ms: 10,
+                max_ms: 200,
+            },
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+            links: vec![],
+            partitions: vec![],
+            adversaries: vec![],
+            leaves: vec![],
+            crashes: vec![],
+            min_vdf_time_ms: 2_000,
+            max_vdf_time_ms: 3_000,
+            time_limit: 600,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks the parameters a run can't go on with, should be called again once they change.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_peers == 0 {
+            return Err("there should be at least one peer".into());
+        }
+        self.vdf_scheme
+            .check_difficulty(self.vdf_params, self.vdf_difficulty)
+            .map_err(|_| {
+                format!(
+                    "{} VDF can't be calculated with difficulty {}",
+                    self.vdf_scheme, self.vdf_difficulty
+                )
+            })?;
+        if self.weights().is_some_and(|w| w.len() < self.weights.len()) {
+            return Err("every peer should have a single weight".into());
+        }
+        if let Some(members) = &self.members {
+            if members.is_empty() {
+                return Err("there should be at least one member".into());
+            }
+            if let Some(member) = members.iter().find(|&&member| member >= self.num_peers) {
+                return Err(format!(
+                    "peer {} can't be a member, there's no such peer",
+                    member
+                ));
+            }
+        }
+        let participants = match &self.members {
+            Some(members) => members.len(),
+            None => self.num_peers as usize,
+        };
+        self.threshold
+            .validate_for(participants)
+            .map_err(|e| format!("invalid threshold: {:?}", e))?;
+        let stake = matches!(self.threshold, ThresholdPolicy::Stake { .. });
+        if !self.weights.is_empty() && !stake {
+            return Err("weights only count under the stake threshold policy".into());
+        }
+        if self.weights.is_empty() && stake {
+            return Err("stake threshold policy needs the weights of the peers".into());
+        }
+        if self.evaluators == Some(0) {
+            return Err("there should be at least one evaluator".into());
+        }
+        if let Some(leave) = self
+            .simulation
+            .leaves
+            .iter()
+            .find(|leave| leave.peer >= self.num_peers)
+        {
+            return Err(format!(
+                "peer {} can't leave, there's no such peer",
+                leave.peer
+            ));
+        }
+        if let Some(crash) = self
+            .simulation
+            .crashes
+            .iter()
+            .find(|crash| crash.peer >= self.num_peers)
+        {
+            return Err(format!(
+                "peer {} can't crash, there's no such peer",
+                crash.peer
+            ));
+        }
+        self.simulation.validate()
+    }
+
+    /// Stake of every peer, `None` if votes of the peers aren't weighed.
+    pub fn weights(&self) -> Option<BTreeMap<PeerId, u64>> {
+        if self.weights.is_empty() {
+            return None;
+        }
+
+        Some(self.weights.iter().map(|w| (w.peer, w.weight)).collect())
+    }
+
+    /// Verifier of the VDF results calculated with these parameters.
+    pub fn vdf_verifier(&self) -> SchemeVerifier {
+        SchemeVerifier::new(self.vdf_scheme, self.vdf_params, self.vdf_difficulty)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+        Self::parse(&text).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+    }
+}
+
+impl SimulationConfig {
+    fn validate(&self) -> Result<(), String> {
+        let mut rates = vec![self.drop_rate, self.duplicate_rate];
+        for link in self.links.iter() {
+            rates.extend(link.drop_rate);
+            rates.extend(link.duplicate_rate);
+        }
+
+        if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+            return Err("drop_rate and duplicate_rate should be between 0 and 1".into());
+        }
+
+        let latencies = Some(&self.latency)
+            .into_iter()
+            .chain(self.links.iter().filter_map(|link| link.latency.as_ref()));
+
+        for latency in latencies {
+            if let Latency::Uniform { min_ms, max_ms } = latency {
+                if min_ms > max_ms {
+                    return Err("min_ms of latency should not exceed max_ms".into());
+                }
+            }
+        }
+
+        for partition in self.partitions.iter() {
+            if partition
+                .heal_ms
+                .is_some_and(|heal| heal < partition.start_ms)
+            {
+                return Err("partition should heal after it starts".into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_defaults() {
+        assert_eq!(Config::parse(""), Ok(Config::default()));
+
+        let config = Config::parse("vdf_difficulty = 1000").unwrap();
+        assert_eq!(config.vdf_difficulty, 1000);
+        assert_eq!(config.vdf_params, Config::default().vdf_params);
+
+        assert!(Config::parse("vdf_dificulty = 1000").is_err());
+        assert!(Config::parse("vdf_difficulty = 1001").is_err());
+        assert!(Config::parse("num_peers = 0").is_err());
+
+        let config = Config::parse("members = [0, 2]").unwrap();
+        assert_eq!(config.members, Some(vec![0, 2]));
+        assert!(Config::parse("members = []").is_err());
+        assert!(Config::parse("members = [0, 6]").is_err());
+
+        let config = Config::parse("vdf_scheme = \"wesolowski\"").unwrap();
+        assert_eq!(config.vdf_scheme, VdfScheme::Wesolowski);
+        assert!(Config::parse("vdf_scheme = \"sloth\"").is_err());
+
+        let config = Config::parse("evaluators = 2").unwrap();
+        assert_eq!(config.evaluators, Some(2));
+        assert!(Config::parse("evaluators = 0").is_err());
+
+        let config = Config::parse("threshold = { policy = \"faults\", faults = 1 }").unwrap();
+        assert_eq!(config.threshold, ThresholdPolicy::Faults { faults: 1 });
+        // 6 peers tolerate a single fault, 4 members of them too, but 3 tolerate none
+        assert!(Config::parse("threshold = { policy = \"faults\", faults = 2 }").is_err());
+        let members = "members = [0, 1, 2, 3]\n";
+        let faults = "threshold = { policy = \"faults\", faults = 1 }\n";
+        assert!(Config::parse(&format!("{}{}", members, faults)).is_ok());
+        assert!(Config::parse(&format!("members = [0, 1, 2]\n{}", faults)).is_err());
+        assert!(Config::parse("threshold = { policy = \"absolute\", count = 0 }").is_err());
+        assert!(Config::parse("threshold = { policy = \"fraction\", numerator = 1 }").is_err());
+    }
+
+    #[test]
+    pub fn test_weights() {
+        let stake = "threshold = { policy = \"stake\", numerator = 2, denominator = 3 }\n";
+        let weights = "[[weights]]\npeer = 0\nweight = 10\n[[weights]]\npeer = 2\nweight = 1\n";
+
+        let config = Config::parse(&format!("{}{}", stake, weights)).unwrap();
+        let table = config.weights().unwrap();
+        assert_eq!(table.into_iter().collect::<Vec<_>>(), vec![(0, 10), (2, 1)]);
+        assert_eq!(Config::default().weights(), None);
+
+        // Weights without the stake policy would be silently ignored
+        assert!(Config::parse(weights).is_err());
+
+        // And the stake policy without weights would count the peers instead
+        assert!(Config::parse(stake).is_err());
+
+        let twice = "[[weights]]\npeer = 0\nweight = 5\n";
+        assert!(Config::parse(&format!("{}{}{}", stake, weights, twice)).is_err());
+    }
+
+    #[test]
+    pub fn test_network_model() {
+        let config = Config::parse(
+            r#"
+            [simulation]
+            latency = { distribution = "exponential", min_ms = 5, mean_ms = 50 }
+            drop_rate = 0.1
+
+            [[simulation.links]]
+            from = 0
+            to = 1
+            duplicate_rate = 1.0
+
+            [[simulation.partitions]]
+            groups = [[0, 1], [2]]
+            heal_ms = 10000
+
+            [[simulation.adversaries]]
+            peer = 2
+            behavior = "bogus_vdf"
+
+            [[simulation.leaves]]
+            peer = 1
+            at_ms = 3000
+
+            [[simulation.crashes]]
+            peer = 0
+            at_ms = 2000
+            down_ms = 500
+            "#,
+        )
+        .unwrap();
+
+        let sim = config.simulation;
+        assert_eq!(
+            sim.latency,
+            Latency::Exponential {
+                min_ms: 5,
+                mean_ms: 50
+            }
+        );
+        assert_eq!(sim.links[0].duplicate_rate, Some(1.0));
+        assert_eq!(sim.links[0].latency, None);
+        assert_eq!(sim.partitions[0].start_ms, 0);
+        assert_eq!(sim.partitions[0].heal_ms, Some(10_000));
+        assert_eq!(sim.adversaries[0].behavior, Behavior::BogusVdf);
+        assert_eq!(
+            sim.leaves[0],
+            LeaveConfig {
+                peer: 1,
+                at_ms: 3000
+            }
+        );
+        assert!(Config::parse("[[simulation.leaves]]\npeer = 6\nat_ms = 0").is_err());
+        assert_eq!(
+            sim.crashes[0],
+            CrashConfig {
+                peer: 0,
+                at_ms: 2000,
+                down_ms: 500
+            }
+        );
+        assert!(Config::parse("[[simulation.crashes]]\npeer = 6\nat_ms = 0\ndown_ms = 1").is_err());
+
+        assert!(Config::parse("[simulation]\ndrop_rate = 1.5").is_err());
+        assert!(Config::parse(
+            "[simulation]\nlatency = { distribution = \"uniform\", min_ms = 2, max_ms = 1 }"
+        )
+        .is_err());
+        assert!(Config::parse(
+            "[simulation]\nlatency = { distribution = \"normal\", min_ms = 1, max_ms = 2 }"
+        )
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_example_config() {
+        let config = Config::parse(include_str!("../config.example.toml")).unwrap();
+        let node = config.node.unwrap();
+
+        assert_eq!(node.id, Some(0));
+        assert_eq!(node.peers.len(), 3);
+        assert_eq!(node.peers[0].addr, "127.0.0.1:7001".parse().unwrap());
+    }
+}
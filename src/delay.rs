@@ -0,0 +1,317 @@
+//! Delay functions the randomness is derived from.
+//!
+//! `Unicorn` only needs a function that is slow to evaluate and can be checked by everyone,
+//! so any construction can be plugged in by implementing `DelayFunction`. The VDFs of the
+//! `vdf` crate are implemented in `verifier`, `IteratedHash` is a stand-in for tests.
+
+use sha2::{Digest, Sha256};
+
+use crate::verifier::VdfVerifier;
+
+/// Public parameters of a delay function, the same for every participant of a round.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DelayParameters {
+    /// Name of the construction, e.g. `pietrzak`.
+    pub name: String,
+
+    /// Number of sequential steps the evaluation takes.
+    pub difficulty: u64,
+
+    /// Size of the group or the state the steps are performed in, in bits.
+    pub size_bits: u16,
+}
+
+/// Intermediate state of an evaluation, see `DelayFunction::resume`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DelayState {
+    /// Steps performed to reach the state.
+    pub steps: u64,
+    pub state: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DelayError {
+    /// Construction can't be evaluated with this number of steps.
+    InvalidDifficulty(u64),
+    /// Evaluation was cancelled before it finished.
+    Cancelled,
+}
+
+/// Sequential function with publicly verifiable output.
+pub trait DelayFunction: VdfVerifier {
+    /// Evaluates the function on the `seed`, returns the output together with its proof.
+    fn solve(&self, seed: &[u8]) -> Result<Vec<u8>, DelayError>;
+
+    /// Evaluates the function like `solve`, passing the number of steps performed so far
+    /// to `progress`. Evaluation is cancelled as soon as `progress` returns false.
+    ///
+    /// By default progress is reported only before and after the evaluation, which can't be
+    /// interrupted midway.
+    fn solve_with_progress(
+        &self,
+        seed: &[u8],
+        progress: &dyn Fn(u64) -> bool,
+    ) -> Result<Vec<u8>, DelayError> {
+        if !progress(0) {
+            return Err(DelayError::Cancelled);
+        }
+
+        let value = self.solve(seed)?;
+        progress(self.parameters().difficulty);
+
+        Ok(value)
+    }
+
+    /// Evaluates the function like `solve_with_progress`, passing intermediate states to `save`
+    /// now and then. Evaluation continues from the state saved earlier for the same `seed`,
+    /// if given one.
+    ///
+    /// By default evaluation can't be resumed: it always starts over and saves nothing.
+    fn resume(
+        &self,
+        seed: &[u8],
+        from: Option<&DelayState>,
+        progress: &dyn Fn(u64) -> bool,
+        save: &dyn Fn(DelayState),
+    ) -> Result<Vec<u8>, DelayError> {
+        let _ = (from, save);
+
+        self.solve_with_progress(seed, progress)
+    }
+
+    fn parameters(&self) -> DelayParameters;
+
+    /// Size of the output with its proof in bytes, `None` if it isn't known before the output
+    /// is calculated.
+    fn proof_size(&self) -> Option<usize>;
+}
+
+/// SHA-256 applied to the seed over and over again.
+///
+/// Not a VDF: checking the output takes as long as calculating it. Meant for tests and
+/// simulations where the delay itself doesn't matter.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IteratedHash {
+    pub iterations: u64,
+}
+
+/// Number of iterations between progress reports of `IteratedHash`.
+const PROGRESS_STEP: u64 = 1 << 16;
+
+impl IteratedHash {
+    pub fn new(iterations: u64) -> Self {
+        IteratedHash { iterations }
+    }
+
+    fn evaluate(&self, seed: &[u8]) -> Vec<u8> {
+        self.evaluate_from(seed, None, &|_| true, &|_| {}).unwrap()
+    }
+
+    fn evaluate_from(
+        &self,
+        seed: &[u8],
+        from: Option<&DelayState>,
+        progress: &dyn Fn(u64) -> bool,
+        save: &dyn Fn(DelayState),
+    ) -> Result<Vec<u8>, DelayError> {
+        // State of another function or of a longer evaluation is of no use
+        let from = from.filter(|from| from.state.len() == 32 && from.steps < self.iterations);
+
+        let (mut value, start) = match from {
+            Some(from) => (from.state.clone(), from.steps),
+            None => (Sha256::digest(seed).to_vec(), 1),
+        };
+
+        for step in start..self.iterations {
+            if step % PROGRESS_STEP == 0 {
+                save(DelayState {
+                    steps: step,
+                    state: value.clone(),
+                });
+
+                if !progress(step) {
+                    return Err(DelayError::Cancelled);
+                }
+            }
+
+            value = Sha256::digest(&value).to_vec();
+        }
+
+        Ok(value)
+    }
+}
+
+impl VdfVerifier for IteratedHash {
+    fn verify(&self, seed: &[u8], value: &[u8]) -> bool {
+        self.evaluate(seed) == value
+    }
+}
+
+impl DelayFunction for IteratedHash {
+    fn solve(&self, seed: &[u8]) -> Result<Vec<u8>, DelayError> {
+        if self.iterations == 0 {
+            return Err(DelayError::InvalidDifficulty(0));
+        }
+
+        Ok(self.evaluate(seed))
+    }
+
+    fn solve_with_progress(
+        &self,
+        seed: &[u8],
+        progress: &dyn Fn(u64) -> bool,
+    ) -> Result<Vec<u8>, DelayError> {
+        if self.iterations == 0 {
+            return Err(DelayError::InvalidDifficulty(0));
+        }
+
+        if !progress(0) {
+            return Err(DelayError::Cancelled);
+        }
+
+        let value = self.evaluate_from(seed, None, progress, &|_| {})?;
+        progress(self.iterations);
+
+        Ok(value)
+    }
+
+    fn resume(
+        &self,
+        seed: &[u8],
+        from: Option<&DelayState>,
+        progress: &dyn Fn(u64) -> bool,
+        save: &dyn Fn(DelayState),
+    ) -> Result<Vec<u8>, DelayError> {
+        if self.iterations == 0 {
+            return Err(DelayError::InvalidDifficulty(0));
+        }
+
+        if !progress(from.map_or(0, |from| from.steps)) {
+            return Err(DelayError::Cancelled);
+        }
+
+        let value = self.evaluate_from(seed, from, progress, save)?;
+        progress(self.iterations);
+
+        Ok(value)
+    }
+
+    fn parameters(&self) -> DelayParameters {
+        DelayParameters {
+            name: "iterated-sha256".into(),
+            difficulty: self.iterations,
+            size_bits: 256,
+        }
+    }
+
+    fn proof_size(&self) -> Option<usize> {
+        Some(32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::{PietrzakVerifier, WesolowskiVerifier};
+
+    #[test]
+    pub fn test_iterated_hash() {
+        let function = IteratedHash::new(1_000);
+
+        let value = function.solve(b"seed").unwrap();
+        assert_eq!(Some(value.len()), function.proof_size());
+        assert!(function.verify(b"seed", &value));
+        assert!(!function.verify(b"other seed", &value));
+        assert!(!IteratedHash::new(999).verify(b"seed", &value));
+
+        assert_eq!(
+            IteratedHash::new(0).solve(b"seed"),
+            Err(DelayError::InvalidDifficulty(0))
+        );
+    }
+
+    #[test]
+    pub fn test_progress() {
+        use std::cell::Cell;
+
+        let function = IteratedHash::new(10 * PROGRESS_STEP);
+
+        let reports = Cell::new(0);
+        let value = function.solve_with_progress(b"seed", &|steps| {
+            assert_eq!(steps, reports.get() * PROGRESS_STEP);
+            reports.set(reports.get() + 1);
+            true
+        });
+        assert_eq!(value, function.solve(b"seed"));
+        assert_eq!(reports.get(), 11);
+
+        let cancelled = function.solve_with_progress(b"seed", &|steps| steps < 3 * PROGRESS_STEP);
+        assert_eq!(cancelled, Err(DelayError::Cancelled));
+
+        // Evaluation of the vdf crate can't be interrupted, but is cancelled before it starts
+        let pietrzak = PietrzakVerifier::new(512, 100);
+        let last = Cell::new(0);
+        let value = pietrzak.solve_with_progress(b"seed", &|steps| {
+            last.set(steps);
+            true
+        });
+        assert!(pietrzak.verify(b"seed", &value.unwrap()));
+        assert_eq!(last.get(), 100);
+        assert_eq!(
+            pietrzak.solve_with_progress(b"seed", &|_| false),
+            Err(DelayError::Cancelled)
+        );
+    }
+
+    #[test]
+    pub fn test_resume() {
+        use std::cell::{Cell, RefCell};
+
+        let function = IteratedHash::new(10 * PROGRESS_STEP);
+        let saved = RefCell::new(None);
+
+        // Evaluation is interrupted after 3 progress reports
+        let cancelled = function.resume(
+            b"seed",
+            None,
+            &|steps| steps < 3 * PROGRESS_STEP,
+            &|state| *saved.borrow_mut() = Some(state),
+        );
+        assert_eq!(cancelled, Err(DelayError::Cancelled));
+
+        let saved = saved.into_inner().unwrap();
+        assert_eq!(saved.steps, 3 * PROGRESS_STEP);
+
+        let first = Cell::new(None);
+        let value = function.resume(
+            b"seed",
+            Some(&saved),
+            &|steps| {
+                first.set(first.get().or(Some(steps)));
+                true
+            },
+            &|_| {},
+        );
+        assert_eq!(value, function.solve(b"seed"));
+        assert_eq!(first.get(), Some(3 * PROGRESS_STEP));
+
+        // Functions that can't resume start over
+        let pietrzak = PietrzakVerifier::new(512, 100);
+        let value = pietrzak.resume(b"seed", Some(&saved), &|_| true, &|_| {});
+        assert_eq!(value, pietrzak.solve(b"seed"));
+    }
+
+    #[test]
+    pub fn test_proof_sizes() {
+        assert_eq!(IteratedHash::new(100).proof_size(), Some(32));
+
+        // `vdf` crate doesn't tell the size of its proofs, nobody should guess it
+        assert_eq!(PietrzakVerifier::new(512, 100).proof_size(), None);
+        assert_eq!(WesolowskiVerifier::new(512, 100).proof_size(), None);
+
+        assert_eq!(
+            PietrzakVerifier::new(512, 101).solve(b"seed"),
+            Err(DelayError::InvalidDifficulty(101))
+        );
+    }
+}
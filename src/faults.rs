@@ -0,0 +1,219 @@
+//! Network model of the simulation: latency, loss, duplication and partitions.
+
+use rand_core::RngCore;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::{Latency, SimulationConfig};
+use crate::protocol::PeerId;
+
+/// How messages travel from one peer to another.
+#[derive(Debug, Clone)]
+struct Link {
+    latency: Latency,
+    drop_rate: f64,
+    duplicate_rate: f64,
+}
+
+/// Period when peers of different groups can't reach each other.
+#[derive(Debug, Clone)]
+struct Partition {
+    /// Group of every listed peer, unlisted peers are in the group `groups.len()`
+    groups: HashMap<PeerId, usize>,
+    unlisted: usize,
+    start: Duration,
+    heal: Option<Duration>,
+}
+
+/// Decides when, and whether at all, a message reaches its recipient.
+#[derive(Debug, Clone)]
+pub struct NetworkModel {
+    default: Link,
+    links: HashMap<(PeerId, PeerId), Link>,
+    partitions: Vec<Partition>,
+}
+
+impl NetworkModel {
+    pub fn new(config: &SimulationConfig) -> Self {
+        let default = Link {
+            latency: config.latency.clone(),
+            drop_rate: config.drop_rate,
+            duplicate_rate: config.duplicate_rate,
+        };
+
+        let links = config
+            .links
+            .iter()
+            .map(|link| {
+                let model = Link {
+                    latency: link.latency.clone().unwrap_or(default.latency.clone()),
+                    drop_rate: link.drop_rate.unwrap_or(default.drop_rate),
+                    duplicate_rate: link.duplicate_rate.unwrap_or(default.duplicate_rate),
+                };
+
+                ((link.from, link.to), model)
+            })
+            .collect();
+
+        let partitions = config
+            .partitions
+            .iter()
+            .map(|partition| Partition {
+                groups: partition
+                    .groups
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(group, peers)| peers.iter().map(move |peer| (*peer, group)))
+                    .collect(),
+                unlisted: partition.groups.len(),
+                start: Duration::from_millis(partition.start_ms),
+                heal: partition.heal_ms.map(Duration::from_millis),
+            })
+            .collect();
+
+        NetworkModel {
+            default,
+            links,
+            partitions,
+        }
+    }
+
+    /// Whether a message sent at `now` can't get from one peer to another.
+    pub fn partitioned(&self, from: PeerId, to: PeerId, now: Duration) -> bool {
+        self.partitions.iter().any(|partition| {
+            let active = now >= partition.start && partition.heal.is_none_or(|heal| now < heal);
+            let group = |peer| *partition.groups.get(&peer).unwrap_or(&partition.unlisted);
+
+            active && group(from) != group(to)
+        })
+    }
+
+    /// Delays after which copies of a message sent at `now` reach the recipient,
+    /// none if the message is lost.
+    ///
+    /// A peer always receives its own messages exactly once.
+    pub fn deliveries<R: RngCore>(
+        &self,
+        from: PeerId,
+        to: PeerId,
+        now: Duration,
+        rng: &mut R,
+    ) -> Vec<Duration> {
+        let link = self.links.get(&(from, to)).unwrap_or(&self.default);
+
+        if from == to {
+            return vec![sample_latency(&link.latency, rng)];
+        }
+
+        if self.partitioned(from, to, now) || chance(link.drop_rate, rng) {
+            return vec![];
+        }
+
+        let mut deliveries = vec![sample_latency(&link.latency, rng)];
+        if chance(link.duplicate_rate, rng) {
+            deliveries.push(sample_latency(&link.latency, rng));
+        }
+
+        deliveries
+    }
+}
+
+/// Uniformly distributed number in `[0, 1)`.
+fn unit<R: RngCore>(rng: &mut R) -> f64 {
+    // 53 bits fit into the mantissa exactly
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn chance<R: RngCore>(probability: f64, rng: &mut R) -> bool {
+    // Fault-free links don't draw from the generator, so a run without faults
+    // doesn't depend on whether the fault model is there
+    probability > 0.0 && unit(rng) < probability
+}
+
+fn sample_latency<R: RngCore>(latency: &Latency, rng: &mut R) -> Duration {
+    match *latency {
+        Latency::Uniform { min_ms, max_ms } => {
+            let spread = max_ms.saturating_sub(min_ms);
+
+            Duration::from_millis(min_ms + rng.next_u64() % (spread + 1))
+        }
+
+        Latency::Exponential { min_ms, mean_ms } => {
+            let tail = -(mean_ms as f64) * (1.0 - unit(rng)).ln();
+
+            Duration::from_millis(min_ms) + Duration::from_secs_f64(tail / 1000.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LinkConfig, PartitionConfig};
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    pub fn test_partitions() {
+        let model = NetworkModel::new(&SimulationConfig {
+            partitions: vec![PartitionConfig {
+                groups: vec![vec![0, 1], vec![2]],
+                start_ms: 1_000,
+                heal_ms: Some(5_000),
+            }],
+            ..SimulationConfig::default()
+        });
+
+        let at = Duration::from_millis;
+        assert!(!model.partitioned(0, 2, at(999)));
+        assert!(model.partitioned(0, 2, at(1_000)));
+        assert!(!model.partitioned(0, 1, at(1_000)));
+        assert!(!model.partitioned(0, 2, at(5_000)));
+
+        // Unlisted peers are together, but apart from the listed ones
+        assert!(!model.partitioned(3, 4, at(2_000)));
+        assert!(model.partitioned(3, 2, at(2_000)));
+
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        assert!(model.deliveries(0, 2, at(2_000), &mut rng).is_empty());
+        assert_eq!(model.deliveries(2, 2, at(2_000), &mut rng).len(), 1);
+    }
+
+    #[test]
+    pub fn test_lossy_links() {
+        let model = NetworkModel::new(&SimulationConfig {
+            latency: Latency::Exponential {
+                min_ms: 10,
+                mean_ms: 100,
+            },
+            drop_rate: 0.25,
+            links: vec![LinkConfig {
+                from: 0,
+                to: 1,
+                latency: None,
+                drop_rate: Some(0.0),
+                duplicate_rate: Some(1.0),
+            }],
+            ..SimulationConfig::default()
+        });
+
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let mut sent = |from, to| {
+            (0..1_000)
+                .flat_map(|_| model.deliveries(from, to, Duration::default(), &mut rng))
+                .collect::<Vec<_>>()
+        };
+
+        let lossy = sent(1, 0);
+        assert!((700..800).contains(&lossy.len()), "{}", lossy.len());
+        assert!(lossy
+            .iter()
+            .all(|latency| *latency >= Duration::from_millis(10)));
+
+        let mean = lossy.iter().sum::<Duration>() / lossy.len() as u32;
+        assert!(mean > Duration::from_millis(90) && mean < Duration::from_millis(130));
+
+        assert_eq!(sent(0, 1).len(), 2_000);
+    }
+}
@@ -0,0 +1,451 @@
+//! Append-only store of finalized rounds on disk.
+//!
+//! Every round is stored as a wire frame followed by the SHA-256 digest of the frame:
+//!
+//! ```text
+//! frame with BeaconRecord | sha256(frame): [u8; 32]
+//! ```
+//!
+//! Frames carry the `VERSION` of the file rather than the one of the network protocol, so
+//! upgrading the protocol doesn't make stored rounds unreadable. Entries of another version
+//! make the store refuse to open and are left intact.
+//!
+//! Appends are synced to disk before they return. A crash in the middle of an append leaves
+//! an incomplete last entry behind, or zeroes and garbage in its place, which are dropped
+//! the next time the store is opened. Damage followed by intact entries can't come from
+//! a crash and makes the store refuse to open.
+
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use crate::wire::{self, get_list, get_option, put_list, put_option};
+use crate::wire::{Reader, WireError, WireId, WireMessage, Writer};
+use crate::BeaconRecord;
+
+/// Version of the entries in the file, bumped whenever `BeaconRecord` is encoded differently.
+///
+/// Independent of `wire::VERSION`, it starts where the protocol was when the file got
+/// a version of its own, so files written before still open.
+pub const VERSION: u8 = 3;
+
+/// Size of the digest following every frame.
+const CHECKSUM_LEN: usize = 32;
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Io(io::Error),
+    /// Stored entry can't be decoded, or was written with another `VERSION`.
+    Wire(WireError),
+    /// Entry at the offset is damaged, but isn't the last one.
+    Corrupted(u64),
+    /// Round isn't newer than the last stored one.
+    StaleRound(u64),
+}
+
+impl From<io::Error> for HistoryError {
+    fn from(e: io::Error) -> Self {
+        HistoryError::Io(e)
+    }
+}
+
+impl From<WireError> for HistoryError {
+    fn from(e: WireError) -> Self {
+        HistoryError::Wire(e)
+    }
+}
+
+/// Finalized rounds stored in a file, oldest first.
+///
+/// Only one process should open the file at a time.
+#[derive(Debug)]
+pub struct History<I> {
+    path: PathBuf,
+    file: File,
+    /// Offset of every stored round in the file.
+    offsets: BTreeMap<u64, u64>,
+    /// Size of the intact part of the file.
+    len: u64,
+
+    _id: PhantomData<I>,
+}
+
+impl<I: WireId + Ord> History<I> {
+    /// Opens the store, creating an empty one if the file doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, HistoryError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        let mut offsets = BTreeMap::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            match read_entry::<I>(&bytes[offset..]) {
+                Ok(Some((record, len))) => {
+                    offsets.insert(record.round, offset as u64);
+                    offset += len;
+                }
+
+                // Append didn't finish
+                Ok(None) => break,
+                Err(Damage::Checksum(len)) if offset + len == bytes.len() => break,
+                Err(Damage::Header) if !entry_follows::<I>(&bytes[offset + 1..]) => break,
+
+                Err(Damage::Checksum(_)) | Err(Damage::Header) => {
+                    return Err(HistoryError::Corrupted(offset as u64))
+                }
+                Err(Damage::Wire(e)) | Err(Damage::Version(e)) => return Err(e.into()),
+            }
+        }
+
+        let len = offset as u64;
+        if len < bytes.len() as u64 {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+
+        Ok(History {
+            path,
+            file,
+            offsets,
+            len,
+            _id: PhantomData,
+        })
+    }
+
+    /// Stores the round, it should be newer than every stored one.
+    pub fn append(&mut self, record: &BeaconRecord<I>) -> Result<(), HistoryError> {
+        if self.last_round().is_some_and(|last| record.round <= last) {
+            return Err(HistoryError::StaleRound(record.round));
+        }
+
+        let entry = encode_entry(record);
+        let written = self
+            .file
+            .write_all(&entry)
+            .and_then(|_| self.file.sync_data());
+
+        if let Err(e) = written {
+            // Leave no partial entry for the next appends to follow
+            let _ = self.file.set_len(self.len);
+            return Err(e.into());
+        }
+
+        self.offsets.insert(record.round, self.len);
+        self.len += entry.len() as u64;
+
+        Ok(())
+    }
+
+    /// Stored round, `None` if there's no such round.
+    pub fn get(&self, round: u64) -> Result<Option<BeaconRecord<I>>, HistoryError> {
+        let offset = match self.offsets.get(&round) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut header = [0u8; wire::HEADER_LEN];
+        file.read_exact(&mut header)?;
+        let payload_len = u32::from_be_bytes([header[6], header[7], header[8], header[9]]);
+
+        let mut entry = header.to_vec();
+        entry.resize(wire::HEADER_LEN + payload_len as usize + CHECKSUM_LEN, 0);
+        file.read_exact(&mut entry[wire::HEADER_LEN..])?;
+
+        match read_entry(&entry) {
+            Ok(Some((record, _))) => Ok(Some(record)),
+            Ok(None) | Err(Damage::Checksum(_)) | Err(Damage::Header) => {
+                Err(HistoryError::Corrupted(offset))
+            }
+            Err(Damage::Wire(e)) | Err(Damage::Version(e)) => Err(e.into()),
+        }
+    }
+
+    /// Every stored round, oldest first.
+    pub fn records(&self) -> Result<Vec<BeaconRecord<I>>, HistoryError> {
+        self.rounds()
+            .filter_map(|round| self.get(round).transpose())
+            .collect()
+    }
+
+    /// Indices of the stored rounds, oldest first.
+    pub fn rounds(&self) -> impl Iterator<Item = u64> + '_ {
+        self.offsets.keys().copied()
+    }
+
+    pub fn last_round(&self) -> Option<u64> {
+        self.offsets.keys().next_back().copied()
+    }
+
+    /// Most recently stored round, `None` if there's none yet.
+    pub fn last(&self) -> Result<Option<BeaconRecord<I>>, HistoryError> {
+        match self.last_round() {
+            Some(round) => self.get(round),
+            None => Ok(None),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+}
+
+/// Why an entry couldn't be read.
+enum Damage {
+    /// Entry of this length doesn't match its checksum.
+    Checksum(usize),
+    /// Entry doesn't start with a frame header, its length is unknown.
+    Header,
+    /// Entry was written with another version of the file, it's not damage of a crash.
+    Version(WireError),
+    /// Entry matches its checksum, but can't be decoded.
+    Wire(WireError),
+}
+
+fn encode_entry<I: WireId + Ord>(record: &BeaconRecord<I>) -> Vec<u8> {
+    let mut entry = wire::encode_with_version(record, VERSION);
+    let checksum = Sha256::digest(&entry);
+    entry.extend_from_slice(&checksum);
+
+    entry
+}
+
+/// Reads the entry at the beginning of the `bytes`, together with its length.
+///
+/// Returns `None` if the entry is incomplete.
+fn read_entry<I: WireId + Ord>(bytes: &[u8]) -> Result<Option<(BeaconRecord<I>, usize)>, Damage> {
+    let frame = match wire::split_frame_with_version(bytes, VERSION) {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return Ok(None),
+        Err(e @ WireError::UnsupportedVersion(_)) => return Err(Damage::Version(e)),
+        Err(_) => return Err(Damage::Header),
+    };
+
+    let len = frame.len + CHECKSUM_LEN;
+    if bytes.len() < len {
+        return Ok(None);
+    }
+
+    if Sha256::digest(&bytes[..frame.len])[..] != bytes[frame.len..len] {
+        return Err(Damage::Checksum(len));
+    }
+
+    let record = wire::decode_payload(frame.kind, frame.payload).map_err(Damage::Wire)?;
+
+    Ok(Some((record, len)))
+}
+
+/// Whether an intact entry starts anywhere in the `bytes`.
+fn entry_follows<I: WireId + Ord>(bytes: &[u8]) -> bool {
+    (0..bytes.len()).any(|start| {
+        bytes[start..].starts_with(&wire::MAGIC)
+            && matches!(read_entry::<I>(&bytes[start..]), Ok(Some(_)))
+    })
+}
+
+impl<I: WireId + Ord> WireMessage for BeaconRecord<I> {
+    const KIND: u8 = 0x10;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u64(self.round);
+        put_option(writer, &self.previous_randomness, |r, w| w.put_bytes(r));
+        put_option(writer, &self.members, |members, w| {
+            put_list(w, members, |id, w| id.put(w))
+        });
+        put_option(writer, &self.weights, |weights, w| {
+            w.put_u32(weights.len() as u32);
+            for (id, weight) in weights {
+                id.put(w);
+                w.put_u64(*weight);
+            }
+        });
+        put_list(writer, &self.commitments, |(id, value), w| {
+            id.put(w);
+            w.put_bytes(value);
+        });
+        writer.put_bytes(&self.seed);
+        writer.put_bytes(&self.vdf_result);
+        writer.put_bytes(&self.randomness);
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        Ok(BeaconRecord {
+            round: reader.get_u64()?,
+            previous_randomness: get_option(reader, |r| r.get_bytes())?,
+            members: get_option(reader, |r| get_list(r, I::get))?,
+            weights: get_option(reader, |r| {
+                let weights = get_list(r, |r| Ok((I::get(r)?, r.get_u64()?)))?;
+                Ok(weights.into_iter().collect())
+            })?,
+            commitments: get_list(reader, |r| Ok((I::get(r)?, r.get_bytes()?)))?,
+            seed: reader.get_bytes()?,
+            vdf_result: reader.get_bytes()?,
+            randomness: reader.get_bytes()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("unicorn-{}-{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn record(round: u64) -> BeaconRecord<u32> {
+        BeaconRecord {
+            round,
+            previous_randomness: round.checked_sub(1).map(|r| vec![r as u8; 32]),
+            members: Some(vec![0, 1, 2]),
+            weights: Some(vec![(0, 10), (2, 1)].into_iter().collect()),
+            commitments: vec![(0, vec![0xc0; 32]), (2, vec![0xc2; 32])],
+            seed: vec![0x5e; 32],
+            vdf_result: vec![round as u8; 100],
+            randomness: vec![round as u8; 32],
+        }
+    }
+
+    #[test]
+    pub fn test_append_and_reopen() {
+        let path = temp_path("history");
+
+        let mut history = History::<u32>::open(&path).unwrap();
+        assert!(history.is_empty());
+        for round in [0, 1, 3].iter() {
+            history.append(&record(*round)).unwrap();
+        }
+
+        // Rounds can't be overwritten or inserted into the past
+        assert!(matches!(
+            history.append(&record(2)),
+            Err(HistoryError::StaleRound(2))
+        ));
+
+        let history = History::<u32>::open(&path).unwrap();
+        assert_eq!(history.rounds().collect::<Vec<_>>(), vec![0, 1, 3]);
+        assert_eq!(history.get(3).unwrap(), Some(record(3)));
+        assert_eq!(history.get(2).unwrap(), None);
+        assert_eq!(history.last().unwrap(), Some(record(3)));
+        assert_eq!(
+            history.records().unwrap(),
+            vec![record(0), record(1), record(3)]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_torn_append() {
+        let path = temp_path("torn-history");
+
+        let mut history = History::<u32>::open(&path).unwrap();
+        history.append(&record(0)).unwrap();
+        history.append(&record(1)).unwrap();
+        drop(history);
+
+        // Crash in the middle of writing the last entry
+        let intact = fs::read(&path).unwrap();
+        let entry_len = encode_entry(&record(1)).len();
+        for cut in [1, CHECKSUM_LEN, entry_len - 1].iter() {
+            fs::write(&path, &intact[..intact.len() - cut]).unwrap();
+
+            let mut history = History::<u32>::open(&path).unwrap();
+            assert_eq!(history.last_round(), Some(0));
+            history.append(&record(1)).unwrap();
+            assert_eq!(history.get(1).unwrap(), Some(record(1)));
+        }
+
+        // Damage of the last entry looks the same as a torn write
+        let mut damaged = intact.clone();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 0xff;
+        fs::write(&path, &damaged).unwrap();
+        assert_eq!(History::<u32>::open(&path).unwrap().len(), 1);
+
+        // Nor do zeroes or garbage a crash may leave in place of the last entry
+        let tail_len = intact.len() - entry_len;
+        for garbage in [vec![0u8; 4096], vec![0xa5; 100], b"UNCN\x01".to_vec()].iter() {
+            let mut torn = intact[..tail_len].to_vec();
+            torn.extend_from_slice(garbage);
+            fs::write(&path, &torn).unwrap();
+
+            let mut history = History::<u32>::open(&path).unwrap();
+            assert_eq!(history.last_round(), Some(0));
+            assert_eq!(fs::metadata(&path).unwrap().len(), tail_len as u64);
+            history.append(&record(1)).unwrap();
+            assert_eq!(History::<u32>::open(&path).unwrap().len(), 2);
+        }
+
+        // But not damage of the entries before it
+        let mut damaged = intact.clone();
+        damaged[wire::HEADER_LEN] ^= 0xff;
+        fs::write(&path, &damaged).unwrap();
+        assert!(matches!(
+            History::<u32>::open(&path),
+            Err(HistoryError::Corrupted(0))
+        ));
+
+        // Even when the damage hides where the entry ends
+        let mut damaged = intact;
+        damaged[0] ^= 0xff;
+        fs::write(&path, &damaged).unwrap();
+        assert!(matches!(
+            History::<u32>::open(&path),
+            Err(HistoryError::Corrupted(0))
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_other_version() {
+        let path = temp_path("versioned-history");
+
+        // Written by an implementation encoding the rounds differently
+        let mut written = vec![];
+        for round in 0..2 {
+            let mut entry = wire::encode_with_version(&record(round), VERSION + 1);
+            let checksum = Sha256::digest(&entry);
+            entry.extend_from_slice(&checksum);
+            written.extend_from_slice(&entry);
+        }
+
+        // Rounds of the newer version aren't taken for a torn write, nor is the file truncated
+        fs::write(&path, &written).unwrap();
+        assert!(matches!(
+            History::<u32>::open(&path),
+            Err(HistoryError::Wire(WireError::UnsupportedVersion(v))) if v == VERSION + 1
+        ));
+        assert_eq!(fs::read(&path).unwrap(), written);
+
+        // Neither when they follow the rounds of this version
+        let mut mixed = encode_entry(&record(0));
+        mixed.extend_from_slice(&written);
+        fs::write(&path, &mixed).unwrap();
+        assert!(History::<u32>::open(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), mixed);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use ed25519_dalek::Verifier;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::wire::{WireId, Writer};
+use crate::UnicornError;
+
+pub use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+/// Message that can be signed by its sender.
+///
+/// Unsigned messages are only accepted by `Unicorn` when it has no `KeyRegistry` attached.
+pub trait Signed {
+    /// Bytes covered by the signature.
+    fn signed_bytes(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Ed25519 signature of the sender over `signed_bytes()`.
+    fn signature(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Public keys of the participants by their IDs.
+#[derive(Debug, Clone)]
+pub struct KeyRegistry<I: Hash + Eq> {
+    keys: HashMap<I, VerifyingKey>,
+}
+
+impl<I: Hash + Eq> KeyRegistry<I> {
+    pub fn new() -> Self {
+        KeyRegistry {
+            keys: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, id: I, key: VerifyingKey) {
+        self.keys.insert(id, key);
+    }
+
+    pub fn get(&self, id: &I) -> Option<&VerifyingKey> {
+        self.keys.get(id)
+    }
+
+    /// Number of participants with a registered key.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks that `msg` is signed by the key registered for `id`.
+    pub fn verify<M: Signed>(&self, id: &I, msg: &M) -> Result<(), UnicornError> {
+        let key = self.keys.get(id).ok_or(UnicornError::UnknownParticipant)?;
+
+        let signature = msg
+            .signature()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(UnicornError::InvalidSignature)?;
+
+        key.verify(&msg.signed_bytes(), &signature)
+            .map_err(|_| UnicornError::InvalidSignature)
+    }
+}
+
+impl<I: Hash + Eq> Default for KeyRegistry<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Kind of the commitment in `signed_bytes`.
+pub const COMMITMENT: &[u8] = b"commitment";
+
+/// Kind of the reveal in `signed_bytes`.
+pub const REVEAL: &[u8] = b"reveal";
+
+/// Kind of the VDF result in `signed_bytes`.
+pub const VDF_RESULT: &[u8] = b"vdf result";
+
+/// Kind of the membership change in `signed_bytes`.
+pub const MEMBERSHIP: &[u8] = b"membership";
+
+/// Bytes signed by the sender of a message: message kind, sender's ID, round and length-prefixed
+/// parts, so a signature can't be reused for another kind of message, sender or round.
+///
+/// Transcripts are checked against signatures over exactly these bytes.
+pub fn signed_bytes<I: WireId>(kind: &[u8], id: &I, round: u64, parts: &[&[u8]]) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer.put_fixed(kind);
+    id.put(&mut writer);
+    writer.put_u64(round);
+    for part in parts {
+        writer.put_bytes(part);
+    }
+
+    writer.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Message {
+        payload: Vec<u8>,
+        signature: Option<Vec<u8>>,
+    }
+
+    impl Signed for Message {
+        fn signed_bytes(&self) -> Vec<u8> {
+            self.payload.clone()
+        }
+
+        fn signature(&self) -> Option<Vec<u8>> {
+            self.signature.clone()
+        }
+    }
+
+    #[test]
+    pub fn test_signature_verification() {
+        let alice = SigningKey::from_bytes(&[1u8; 32]);
+        let mallory = SigningKey::from_bytes(&[2u8; 32]);
+
+        let mut keys = KeyRegistry::new();
+        keys.register(0u64, alice.verifying_key());
+        keys.register(1u64, mallory.verifying_key());
+
+        let signed_by = |key: &SigningKey, payload: &[u8]| Message {
+            payload: payload.to_vec(),
+            signature: Some(key.sign(payload).to_bytes().to_vec()),
+        };
+
+        assert_eq!(keys.verify(&0, &signed_by(&alice, b"hello")), Ok(()));
+
+        // Mallory can't speak on behalf of Alice
+        assert_eq!(
+            keys.verify(&0, &signed_by(&mallory, b"hello")),
+            Err(UnicornError::InvalidSignature)
+        );
+
+        // Signature doesn't cover modified payload
+        let mut tampered = signed_by(&alice, b"hello");
+        tampered.payload = b"goodbye".to_vec();
+        assert_eq!(
+            keys.verify(&0, &tampered),
+            Err(UnicornError::InvalidSignature)
+        );
+
+        assert_eq!(
+            keys.verify(
+                &0,
+                &Message {
+                    payload: b"hello".to_vec(),
+                    signature: None,
+                }
+            ),
+            Err(UnicornError::InvalidSignature)
+        );
+        assert_eq!(
+            keys.verify(&2, &signed_by(&alice, b"hello")),
+            Err(UnicornError::UnknownParticipant)
+        );
+    }
+}
@@ -1,142 +1,387 @@
 use digest::Digest;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::hash::Hash;
 use std::marker::PhantomData;
 
+pub mod beacon;
+pub mod checkpoint;
+pub mod delay;
+pub mod history;
+pub mod identity;
+pub mod membership;
+pub mod quorum;
+pub mod rng;
+pub mod threshold;
+pub mod transcript;
+pub mod verifier;
+pub mod wire;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use crate::beacon::{Beacon, BeaconRecord};
+pub use crate::checkpoint::UnicornCheckpoint;
+pub use crate::delay::{DelayError, DelayFunction, DelayParameters, DelayState, IteratedHash};
+pub use crate::history::{History, HistoryError};
+pub use crate::identity::{KeyRegistry, Signed};
+pub use crate::membership::{Change, Membership, MembershipError};
+pub use crate::quorum::Quorum;
+pub use crate::rng::BeaconRng;
+pub use crate::threshold::{ThresholdError, ThresholdPolicy};
+pub use crate::transcript::{
+    verify_round, NamedDigest, RoundReport, RoundRules, RoundTranscript, SignedValue,
+    SignedVdfResult, TranscriptError, TranscriptFormat,
+};
+pub use crate::verifier::{
+    CachedVerifier, PietrzakVerifier, SchemeVerifier, VdfScheme, VdfVerifier, WesolowskiVerifier,
+};
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum UnicornError {
     NotCollectingSeedCommitments,
+    /// Participant has already committed in this round.
+    DuplicateCommitment,
     NotEnoughSeedCommitments,
+    NotCollectingReveals,
+    /// Reveal came from a participant that didn't commit in this round.
+    UnknownCommitment,
+    /// Revealed value doesn't hash to the previously committed value.
+    RevealMismatch,
+    /// Some of the committed participants didn't reveal their values.
+    MissingReveals,
     NotCollectingVdfResults,
+    /// VDF result was calculated for a seed other than the finalized one.
+    VdfSeedMismatch,
+    /// VDF result was calculated with a scheme other than the one of the round.
+    VdfSchemeMismatch,
+    /// VDF result didn't pass verification.
+    InvalidVdfProof,
     NotEnoughVdfResults,
+    /// Different VDF results are supported by a quorum each.
+    Equivocation,
+    /// Delay function can't be evaluated before the seed is ready.
+    SeedNotReady,
+    /// Delay function doesn't support its configured difficulty.
+    InvalidDifficulty,
+    /// Evaluation of the delay function was cancelled.
+    Cancelled,
+    /// Round can't be closed before the randomness is ready.
+    RandomnessNotReady,
+    /// Message came from a participant with no registered key.
+    UnknownParticipant,
+    /// Message came from a participant that doesn't take part in the round.
+    NotAMember,
+    /// Message isn't signed by the key registered for its sender.
+    InvalidSignature,
+    /// Checkpoint doesn't match the round it's restored into.
+    InvalidCheckpoint,
+    /// Threshold policy isn't safe with the participants of the round, or there are
+    /// no participants to count for it.
+    UnsafeThreshold,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum UnicornState {
-    CollectingSeedCommitments,
+    CollectingCommitments,
+    CollectingReveals,
     SeedReady,
     RandomnessReady,
 }
 
-pub trait SeedCommitment<I: Hash + Eq + Ord> {
+/// Commitment of a participant to its secret seed share.
+///
+/// `value()` is the digest of the secret, see `Unicorn::commitment`.
+pub trait SeedCommitment<I: Hash + Eq + Ord>: Signed {
+    fn id(&self) -> I;
+    fn value(&self) -> Vec<u8>;
+}
+
+/// Secret seed share that is revealed after the commitment phase is over.
+pub trait SeedReveal<I: Hash + Eq + Ord>: Signed {
     fn id(&self) -> I;
     fn value(&self) -> Vec<u8>;
 }
 
-pub trait VdfResult<I: Hash + Eq + Ord>: Clone {
+pub trait VdfResult<I: Hash + Eq + Ord>: Signed + Clone {
     fn id(&self) -> I;
     fn seed(&self) -> Vec<u8>;
     fn value(&self) -> Vec<u8>;
+
+    /// Scheme the result was calculated with, `None` if the sender didn't tell.
+    fn scheme(&self) -> Option<VdfScheme> {
+        None
+    }
 }
 
-pub struct Unicorn<I: Hash + Eq + Ord, C: SeedCommitment<I>, R: VdfResult<I>, D: Digest> {
+pub struct Unicorn<
+    I: Hash + Eq + Ord,
+    C: SeedCommitment<I>,
+    R: VdfResult<I>,
+    D: Digest,
+    F: DelayFunction,
+> {
     state: UnicornState,
     seed_commitments: HashMap<I, C>,
+    reveals: HashMap<I, Vec<u8>>,
     vdf_results: HashMap<I, R>,
     seed: Option<Vec<u8>>,
     randomness: Option<Vec<u8>>,
-    threshold: usize,
+    quorum: Option<Quorum<I>>,
+    previous_randomness: Option<Vec<u8>>,
+    policy: ThresholdPolicy,
+    delay: F,
+    keys: Option<KeyRegistry<I>>,
+    members: Option<BTreeSet<I>>,
+    weights: Option<BTreeMap<I, u64>>,
 
     _digest: PhantomData<D>,
 }
 
-impl<I: Hash + Eq + Ord, C: SeedCommitment<I>, R: VdfResult<I>, D: Digest> Unicorn<I, C, R, D> {
-    pub fn new(threshold: usize) -> Self {
+impl<I, C, R, D, F> Unicorn<I, C, R, D, F>
+where
+    I: Hash + Eq + Ord + Clone,
+    C: SeedCommitment<I>,
+    R: VdfResult<I>,
+    D: Digest,
+    F: DelayFunction,
+{
+    /// Creates a round where at least `threshold` participants should agree on every step.
+    pub fn new(threshold: usize, delay: F) -> Self {
         Unicorn {
-            state: UnicornState::CollectingSeedCommitments,
+            state: UnicornState::CollectingCommitments,
             seed_commitments: HashMap::new(),
+            reveals: HashMap::new(),
             vdf_results: HashMap::new(),
             seed: None,
             randomness: None,
-            threshold,
+            quorum: None,
+            previous_randomness: None,
+            policy: ThresholdPolicy::Absolute { count: threshold },
+            delay,
+            keys: None,
+            members: None,
+            weights: None,
 
             _digest: PhantomData,
         }
     }
 
-    fn hash(&self, bytes: &[u8]) -> Vec<u8> {
+    /// Accepts only messages signed by the keys registered for their senders.
+    pub fn with_keys(mut self, keys: KeyRegistry<I>) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// Accepts messages only from the given participants of the round.
+    pub fn with_members<M: IntoIterator<Item = I>>(mut self, members: M) -> Self {
+        self.members = Some(members.into_iter().collect());
+        self
+    }
+
+    /// Decides the threshold with the `policy` instead of a fixed number of participants.
+    ///
+    /// Participants are the members of the round, or everyone with a registered key
+    /// if the round isn't restricted to its members.
+    pub fn with_policy(mut self, policy: ThresholdPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Stake of every participant, weighing their votes under `ThresholdPolicy::Stake`.
+    ///
+    /// Participants missing from the table hold no stake.
+    pub fn with_weights<W: IntoIterator<Item = (I, u64)>>(mut self, weights: W) -> Self {
+        self.weights = Some(weights.into_iter().collect());
+        self
+    }
+
+    /// Mixes randomness of the previous round into the seed of this one.
+    pub fn with_previous_randomness(mut self, randomness: Vec<u8>) -> Self {
+        self.previous_randomness = Some(randomness);
+        self
+    }
+
+    fn hash(bytes: &[u8]) -> Vec<u8> {
         let mut hash = D::new();
         hash.input(bytes);
         hash.result().to_vec()
     }
 
-    fn calculate_seed(&mut self) -> Vec<u8> {
-        // Sort commitments by ID for deterministic result
-        let mut commitments = self
-            .seed_commitments
-            .values()
-            .into_iter()
-            .map(|c| c)
-            .collect::<Vec<_>>();
-        commitments.sort_unstable_by_key(|k| k.id());
+    /// Calculates commitment value for the given secret seed share.
+    pub fn commitment(secret: &[u8]) -> Vec<u8> {
+        Self::hash(secret)
+    }
 
-        // Create the seed by appending commitments and hashing them
-        let seed = commitments
-            .into_iter()
-            .map(|c| c.value())
-            .flatten()
-            .collect::<Vec<_>>();
+    fn authenticate<M: Signed>(&self, id: &I, msg: &M) -> Result<(), UnicornError> {
+        if self.members.as_ref().is_some_and(|m| !m.contains(id)) {
+            return Err(UnicornError::NotAMember);
+        }
 
-        self.hash(&seed)
+        match &self.keys {
+            Some(keys) => keys.verify(id, msg),
+            None => Ok(()),
+        }
     }
 
-    pub fn finalize_seed(&mut self) -> Result<(), UnicornError> {
-        if self.seed_commitments.len() >= self.threshold {
-            self.seed = Some(self.calculate_seed());
-            self.state = UnicornState::SeedReady;
-        } else {
-            return Err(UnicornError::NotEnoughSeedCommitments);
-        }
+    fn calculate_seed(&self) -> Vec<u8> {
+        // Sort reveals by ID for deterministic result
+        let mut reveals = self.reveals.iter().collect::<Vec<_>>();
+        reveals.sort_unstable_by_key(|(id, _)| *id);
 
-        return Ok(());
+        // Create the seed by appending revealed values to the previous randomness
+        // (if any) and hashing them
+        let mut seed = self.previous_randomness.clone().unwrap_or_default();
+        seed.extend(reveals.into_iter().flat_map(|(_, value)| value.clone()));
+
+        Self::hash(&seed)
     }
 
     pub fn add_seed_commitment(&mut self, commitment: C) -> Result<(), UnicornError> {
-        if self.state != UnicornState::CollectingSeedCommitments {
+        if self.state != UnicornState::CollectingCommitments {
             return Err(UnicornError::NotCollectingSeedCommitments);
         }
 
-        self.seed_commitments.insert(commitment.id(), commitment);
+        let id = commitment.id();
+        self.authenticate(&id, &commitment)?;
+
+        // Replacing the commitment would let the participant pick its value after seeing others
+        match self.seed_commitments.entry(id) {
+            Entry::Occupied(_) => Err(UnicornError::DuplicateCommitment),
+            Entry::Vacant(entry) => {
+                entry.insert(commitment);
+                Ok(())
+            }
+        }
+    }
+
+    /// Closes the commitment phase and starts accepting reveals.
+    pub fn finalize_commitments(&mut self) -> Result<(), UnicornError> {
+        if self.state != UnicornState::CollectingCommitments {
+            return Err(UnicornError::NotCollectingSeedCommitments);
+        }
+
+        if !self.reached(self.seed_commitments.keys())? {
+            return Err(UnicornError::NotEnoughSeedCommitments);
+        }
+
+        self.state = UnicornState::CollectingReveals;
 
         Ok(())
     }
 
-    pub fn add_vdf_result(&mut self, vdf_result: R) -> Result<(), UnicornError> {
-        if self.state != UnicornState::SeedReady {
-            return Err(UnicornError::NotCollectingVdfResults);
+    pub fn add_seed_reveal<S: SeedReveal<I>>(&mut self, reveal: S) -> Result<(), UnicornError> {
+        if self.state != UnicornState::CollectingReveals {
+            return Err(UnicornError::NotCollectingReveals);
         }
 
-        self.vdf_results.insert(vdf_result.id(), vdf_result);
+        let id = reveal.id();
+        self.authenticate(&id, &reveal)?;
+
+        self.insert_reveal(id, reveal.value())
+    }
+
+    /// Accepts the value if it matches the commitment of the participant.
+    fn insert_reveal(&mut self, id: I, value: Vec<u8>) -> Result<(), UnicornError> {
+        match self.seed_commitments.get(&id) {
+            None => return Err(UnicornError::UnknownCommitment),
+            Some(commitment) if commitment.value() != Self::commitment(&value) => {
+                return Err(UnicornError::RevealMismatch)
+            }
+            Some(_) => {}
+        }
+
+        self.reveals.insert(id, value);
 
         Ok(())
     }
 
-    fn most_frequent_vdf_result(&mut self) -> Option<(Vec<u8>, usize)> {
-        let mut freq_map = HashMap::<Vec<u8>, usize>::new();
+    /// Committed participants that haven't revealed their values, sorted. Empty unless
+    /// the reveals are being collected.
+    pub fn missing_reveals(&self) -> Vec<I> {
+        if self.state != UnicornState::CollectingReveals {
+            return vec![];
+        }
+
+        let mut missing = self
+            .seed_commitments
+            .keys()
+            .filter(|id| !self.reveals.contains_key(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        missing.sort_unstable();
+
+        missing
+    }
 
-        for res in self.vdf_results.values() {
-            *freq_map.entry(res.value()).or_insert(0) += 1;
+    /// Derives the seed once every committed participant has revealed its value.
+    ///
+    /// Seed is never derived from a part of the reveals, otherwise the last participant to
+    /// reveal could pick between two seeds by withholding its value. Withholding still aborts
+    /// the round, so a participant may veto a seed it saw, but it only gets a new round with
+    /// fresh values, and the delay function keeps it from telling the randomness of the seed
+    /// before the reveals time out.
+    pub fn finalize_seed(&mut self) -> Result<(), UnicornError> {
+        if self.state != UnicornState::CollectingReveals {
+            return Err(UnicornError::NotCollectingReveals);
         }
 
-        let mut freq_vec = freq_map.into_iter().collect::<Vec<_>>();
-        freq_vec.sort_unstable_by_key(|(_, freq)| *freq);
+        let revealed = |id: &I| self.reveals.contains_key(id);
+        if !self.seed_commitments.keys().all(revealed) {
+            return Err(UnicornError::MissingReveals);
+        }
 
-        freq_vec.first().cloned()
+        self.seed = Some(self.calculate_seed());
+        self.state = UnicornState::SeedReady;
+
+        Ok(())
     }
 
-    pub fn finalize_vdf_result(&mut self) -> Result<(), UnicornError> {
-        if let Some((res, freq)) = self.most_frequent_vdf_result() {
-            if freq < self.threshold {
-                return Err(UnicornError::NotEnoughVdfResults);
+    pub fn add_vdf_result(&mut self, vdf_result: R) -> Result<(), UnicornError> {
+        if self.state != UnicornState::SeedReady {
+            return Err(UnicornError::NotCollectingVdfResults);
+        }
+
+        let id = vdf_result.id();
+        self.authenticate(&id, &vdf_result)?;
+
+        let seed = vdf_result.seed();
+        if self.seed.as_ref() != Some(&seed) {
+            return Err(UnicornError::VdfSeedMismatch);
+        }
+
+        if let (Some(scheme), Some(expected)) = (vdf_result.scheme(), self.delay.scheme()) {
+            if scheme != expected {
+                return Err(UnicornError::VdfSchemeMismatch);
             }
+        }
 
-            self.randomness = Some(self.hash(&res));
-            self.state = UnicornState::RandomnessReady;
-        } else {
-            return Err(UnicornError::NotEnoughVdfResults);
+        if !self.delay.verify(&seed, &vdf_result.value()) {
+            return Err(UnicornError::InvalidVdfProof);
         }
 
-        return Ok(());
+        self.vdf_results.insert(id, vdf_result);
+
+        Ok(())
+    }
+
+    /// Picks the VDF result supported by at least `threshold` participants as the randomness,
+    /// or by participants holding at least `stake_threshold` of the stake.
+    pub fn finalize_vdf_result(&mut self) -> Result<(), UnicornError> {
+        let votes = self
+            .vdf_results
+            .iter()
+            .map(|(id, res)| (id.clone(), res.value()));
+        let quorum = match self.stake_threshold() {
+            Some(threshold) => quorum::select_weighted(votes, |id| self.stake(id), threshold)?,
+            None => quorum::select(votes, self.threshold()?)?,
+        };
+
+        self.randomness = Some(Self::hash(&quorum.value));
+        self.quorum = Some(quorum);
+        self.state = UnicornState::RandomnessReady;
+
+        Ok(())
     }
 
     pub fn state(&self) -> UnicornState {
@@ -147,120 +392,222 @@ impl<I: Hash + Eq + Ord, C: SeedCommitment<I>, R: VdfResult<I>, D: Digest> Unico
         self.seed.clone()
     }
 
-    pub fn reset(self) -> Self {
-        Self {
-            state: UnicornState::CollectingSeedCommitments,
-            seed_commitments: Default::default(),
-            vdf_results: Default::default(),
-            seed: None,
-            randomness: None,
-            threshold: self.threshold,
-            _digest: PhantomData,
-        }
+    /// Randomness produced by the round, available once it is finalized.
+    pub fn randomness(&self) -> Option<Vec<u8>> {
+        self.randomness.clone()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use sha2::Sha256;
-    use vdf::*;
+    /// Commitments received so far, sorted by participant.
+    pub fn commitments(&self) -> Vec<(I, Vec<u8>)> {
+        let mut commitments = self
+            .seed_commitments
+            .iter()
+            .map(|(id, commitment)| (id.clone(), commitment.value()))
+            .collect::<Vec<_>>();
+        commitments.sort_unstable_by(|a, b| a.0.cmp(&b.0));
 
-    struct SimpleSeedCommitment {
-        id: u64,
-        value: Vec<u8>,
+        commitments
     }
 
-    impl SeedCommitment<u64> for SimpleSeedCommitment {
-        fn id(&self) -> u64 {
-            self.id
-        }
+    /// Verified VDF result of the participant, if it sent one.
+    pub fn vdf_result(&self, id: &I) -> Option<&R> {
+        self.vdf_results.get(id)
+    }
 
-        fn value(&self) -> Vec<u8> {
-            self.value.clone()
+    /// Everything the finalized round can be checked with, `None` if it isn't finalized yet.
+    pub fn record(&self, round: u64) -> Option<BeaconRecord<I>> {
+        match (self.state, &self.randomness, &self.quorum) {
+            (UnicornState::RandomnessReady, Some(randomness), Some(quorum)) => Some(BeaconRecord {
+                round,
+                previous_randomness: self.previous_randomness.clone(),
+                members: self.members.as_ref().map(|m| m.iter().cloned().collect()),
+                weights: self.weights.clone(),
+                commitments: self.commitments(),
+                seed: self.seed.clone().unwrap_or_default(),
+                vdf_result: quorum.value.clone(),
+                randomness: randomness.clone(),
+            }),
+            _ => None,
         }
     }
 
-    #[derive(Debug, Clone)]
-    struct SimpleVdfResult {
-        id_from: u64,
-        seed: Vec<u8>,
-        result: Vec<u8>,
+    /// Deterministic generator seeded with the randomness of the finalized round.
+    pub fn rng(&self) -> Option<BeaconRng> {
+        self.randomness
+            .as_ref()
+            .map(|randomness| BeaconRng::from_randomness(randomness))
     }
 
-    impl VdfResult<u64> for SimpleVdfResult {
-        fn id(&self) -> u64 {
-            self.id_from
-        }
+    /// Participants that agreed on the randomness and those that diverged from them.
+    pub fn quorum(&self) -> Option<&Quorum<I>> {
+        self.quorum.as_ref()
+    }
 
-        fn seed(&self) -> Vec<u8> {
-            self.seed.clone()
-        }
+    pub fn previous_randomness(&self) -> Option<Vec<u8>> {
+        self.previous_randomness.clone()
+    }
 
-        fn value(&self) -> Vec<u8> {
-            self.result.clone()
+    pub fn policy(&self) -> &ThresholdPolicy {
+        &self.policy
+    }
+
+    /// Number of participants that should agree on every step.
+    ///
+    /// Fails if the participants are too few for the policy, the round can't be finished then.
+    pub fn threshold(&self) -> Result<usize, UnicornError> {
+        let participants = match (&self.members, &self.keys) {
+            (Some(members), _) => members.len(),
+            (None, Some(keys)) => keys.len(),
+            (None, None) => 0,
+        };
+
+        self.policy
+            .threshold(participants)
+            .map_err(|_| UnicornError::UnsafeThreshold)
+    }
+
+    pub fn weights(&self) -> Option<&BTreeMap<I, u64>> {
+        self.weights.as_ref()
+    }
+
+    /// Stake of the participant, the same for everyone without a weight table.
+    pub fn stake(&self, id: &I) -> u64 {
+        match &self.weights {
+            Some(weights) => weights.get(id).copied().unwrap_or(0),
+            None => 1,
         }
     }
 
-    type SimpleUnicorn = Unicorn<u64, SimpleSeedCommitment, SimpleVdfResult, Sha256>;
+    /// Stake that should agree on every step, `None` if votes aren't weighed.
+    ///
+    /// Total stake is held by the members of the round, or by everyone in the weight table
+    /// if the round isn't restricted to its members.
+    pub fn stake_threshold(&self) -> Option<u64> {
+        let weights = self.weights.as_ref()?;
+        let total = match &self.members {
+            Some(members) => members
+                .iter()
+                .map(|id| self.stake(id))
+                .fold(0, u64::saturating_add),
+            None => weights.values().copied().fold(0, u64::saturating_add),
+        };
 
-    fn seed_unicorn_with(
-        unicorn: &mut SimpleUnicorn,
-        commitments: Vec<SimpleSeedCommitment>,
-    ) -> Result<(), UnicornError> {
-        // Generate enough seed shares
-        for sc in commitments {
-            assert_eq!(unicorn.state(), UnicornState::CollectingSeedCommitments);
-            unicorn.add_seed_commitment(sc)?;
+        self.policy.stake_threshold(total)
+    }
+
+    /// Whether the participants agreeing on a step are enough to finish it.
+    fn reached<'a, P: Iterator<Item = &'a I>>(&self, participants: P) -> Result<bool, UnicornError>
+    where
+        I: 'a,
+    {
+        match self.stake_threshold() {
+            Some(threshold) => {
+                let stake = participants.map(|id| self.stake(id));
+                Ok(stake.fold(0, u64::saturating_add) >= threshold)
+            }
+            None => Ok(participants.count() >= self.threshold()?),
         }
+    }
 
-        Ok(())
+    /// Participants of the round, `None` if anyone may take part.
+    pub fn members(&self) -> Option<&BTreeSet<I>> {
+        self.members.as_ref()
+    }
+
+    /// Changes who takes part in the next rounds and how many of them should agree.
+    pub(crate) fn set_members(&mut self, policy: ThresholdPolicy, members: &BTreeSet<I>) {
+        self.policy = policy;
+        self.members = Some(members.clone());
+    }
+
+    /// Delay function the randomness is derived from.
+    pub fn delay(&self) -> &F {
+        &self.delay
     }
 
+    /// Evaluates the delay function on the finalized seed, the result is to be sent to others.
+    pub fn solve(&self) -> Result<Vec<u8>, UnicornError> {
+        let seed = self.seed.as_ref().ok_or(UnicornError::SeedNotReady)?;
+
+        self.delay.solve(seed).map_err(|e| match e {
+            DelayError::InvalidDifficulty(_) => UnicornError::InvalidDifficulty,
+            DelayError::Cancelled => UnicornError::Cancelled,
+        })
+    }
+
+    pub fn reset(mut self) -> Self {
+        let previous_randomness = self.previous_randomness.take();
+        self.restart(previous_randomness);
+        self
+    }
+
+    /// Drops everything collected so far, keeping the policy, weights, delay function and keys.
+    pub(crate) fn restart(&mut self, previous_randomness: Option<Vec<u8>>) {
+        self.state = UnicornState::CollectingCommitments;
+        self.seed_commitments.clear();
+        self.reveals.clear();
+        self.vdf_results.clear();
+        self.seed = None;
+        self.randomness = None;
+        self.quorum = None;
+        self.previous_randomness = previous_randomness;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use vdf::*;
+
     #[test]
     pub fn test_not_enough_seed_commitments() {
         const THRESHOLD: usize = 3;
-        let mut unicorn = SimpleUnicorn::new(THRESHOLD);
+        let mut unicorn = new_unicorn(THRESHOLD);
 
         // 1 of 3 seed commitments
-        let commitments = vec![SimpleSeedCommitment {
+        let commitments = vec![SimpleSeedReveal {
             id: 0,
             value: vec![0u8, 0u8, 0u8],
         }];
 
-        assert!(seed_unicorn_with(&mut unicorn, commitments).is_ok());
+        assert!(commit_unicorn_with(&mut unicorn, &commitments).is_ok());
 
-        // Should be unable to produce seed
+        // Should be unable to proceed to reveals
         assert_eq!(
-            unicorn.finalize_seed(),
+            unicorn.finalize_commitments(),
             Err(UnicornError::NotEnoughSeedCommitments)
         );
+        assert_eq!(
+            unicorn.finalize_seed(),
+            Err(UnicornError::NotCollectingReveals)
+        );
     }
 
     #[test]
     pub fn test_more_seed_commitments() {
         const THRESHOLD: usize = 3;
-        let mut unicorn = SimpleUnicorn::new(THRESHOLD);
+        let mut unicorn = new_unicorn(THRESHOLD);
 
         // 5 of 3 seed commitments
         let commitments = vec![
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 0,
                 value: vec![0u8, 0u8, 0u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 1,
                 value: vec![1u8, 1u8, 1u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 2,
                 value: vec![2u8, 2u8, 2u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 3,
                 value: vec![3u8, 3u8, 3u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 4,
                 value: vec![4u8, 4u8, 4u8],
             },
@@ -275,7 +622,7 @@ mod tests {
         assert_eq!(unicorn.state(), UnicornState::SeedReady);
         assert!(unicorn.seed().is_some());
         assert_eq!(
-            hex::encode(&unicorn.seed().unwrap()),
+            hex::encode(unicorn.seed().unwrap()),
             "8d84c7b55695b4ac9ef8a92224a64f449107a4027dd763587003fc65a664f4ce"
         );
     }
@@ -283,24 +630,28 @@ mod tests {
     #[test]
     pub fn test_seed_creation() {
         const THRESHOLD: usize = 3;
-        let mut unicorn = SimpleUnicorn::new(THRESHOLD);
+        let mut unicorn = new_unicorn(THRESHOLD);
 
         // Shouldn't be able to produce seed too early
         assert_eq!(
             unicorn.finalize_seed(),
+            Err(UnicornError::NotCollectingReveals)
+        );
+        assert_eq!(
+            unicorn.finalize_commitments(),
             Err(UnicornError::NotEnoughSeedCommitments)
         );
 
         let commitments = vec![
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 0,
                 value: vec![0u8, 0u8, 0u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 1,
                 value: vec![1u8, 1u8, 1u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 2,
                 value: vec![2u8, 2u8, 2u8],
             },
@@ -315,26 +666,124 @@ mod tests {
         assert_eq!(unicorn.state(), UnicornState::SeedReady);
         assert!(unicorn.seed().is_some());
         assert_eq!(
-            hex::encode(&unicorn.seed().unwrap()),
+            hex::encode(unicorn.seed().unwrap()),
             "4333ddceb169e2f1741ae48779c9b647154fd69affc8b61f050de97a87945ba3"
         );
     }
 
+    #[test]
+    pub fn test_reveal_mismatch() {
+        const THRESHOLD: usize = 2;
+        let mut unicorn = new_unicorn(THRESHOLD);
+
+        let commitments = vec![
+            SimpleSeedReveal {
+                id: 0,
+                value: vec![0u8, 0u8, 0u8],
+            },
+            SimpleSeedReveal {
+                id: 1,
+                value: vec![1u8, 1u8, 1u8],
+            },
+        ];
+
+        commit_unicorn_with(&mut unicorn, &commitments).unwrap();
+
+        // Commitment can't be swapped for another one, nor sent twice
+        for value in [[1u8, 1u8, 1u8], [2u8, 2u8, 2u8]].iter() {
+            assert_eq!(
+                unicorn.add_seed_commitment(SimpleSeedCommitment {
+                    id: 1,
+                    value: SimpleUnicorn::commitment(value),
+                }),
+                Err(UnicornError::DuplicateCommitment)
+            );
+        }
+
+        // Reveals aren't accepted before the commitment phase is over
+        assert_eq!(
+            unicorn.add_seed_reveal(SimpleSeedReveal {
+                id: 0,
+                value: vec![0u8, 0u8, 0u8],
+            }),
+            Err(UnicornError::NotCollectingReveals)
+        );
+
+        unicorn.finalize_commitments().unwrap();
+
+        // Participant tries to change its value after seeing others' commitments
+        assert_eq!(
+            unicorn.add_seed_reveal(SimpleSeedReveal {
+                id: 1,
+                value: vec![2u8, 2u8, 2u8],
+            }),
+            Err(UnicornError::RevealMismatch)
+        );
+
+        // Participant that didn't commit can't contribute to the seed
+        assert_eq!(
+            unicorn.add_seed_reveal(SimpleSeedReveal {
+                id: 2,
+                value: vec![2u8, 2u8, 2u8],
+            }),
+            Err(UnicornError::UnknownCommitment)
+        );
+
+        // Late commitments are rejected
+        assert_eq!(
+            unicorn.add_seed_commitment(SimpleSeedCommitment {
+                id: 2,
+                value: SimpleUnicorn::commitment(&[2u8, 2u8, 2u8]),
+            }),
+            Err(UnicornError::NotCollectingSeedCommitments)
+        );
+    }
+
+    #[test]
+    pub fn test_missing_reveals() {
+        // Reveals reaching the threshold aren't enough, every committed value is
+        const THRESHOLD: usize = 1;
+        let mut unicorn = new_unicorn(THRESHOLD);
+
+        let commitments = vec![
+            SimpleSeedReveal {
+                id: 0,
+                value: vec![0u8, 0u8, 0u8],
+            },
+            SimpleSeedReveal {
+                id: 1,
+                value: vec![1u8, 1u8, 1u8],
+            },
+        ];
+
+        commit_unicorn_with(&mut unicorn, &commitments).unwrap();
+        unicorn.finalize_commitments().unwrap();
+
+        // Only one of two committed participants revealed its value, the other one could
+        // pick the seed by revealing or not
+        let reveal = commitments.into_iter().next().unwrap();
+        unicorn.add_seed_reveal(reveal).unwrap();
+
+        assert_eq!(unicorn.missing_reveals(), vec![1]);
+        assert_eq!(unicorn.finalize_seed(), Err(UnicornError::MissingReveals));
+        assert_eq!(unicorn.state(), UnicornState::CollectingReveals);
+    }
+
     #[test]
     pub fn test_vdf_results() {
         const THRESHOLD: usize = 3;
-        let mut unicorn = SimpleUnicorn::new(THRESHOLD);
+        let mut unicorn = new_unicorn(THRESHOLD);
 
         let commitments = vec![
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 0,
                 value: vec![0u8, 0u8, 0u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 1,
                 value: vec![1u8, 1u8, 1u8],
             },
-            SimpleSeedCommitment {
+            SimpleSeedReveal {
                 id: 2,
                 value: vec![2u8, 2u8, 2u8],
             },
@@ -344,10 +793,9 @@ mod tests {
         unicorn.finalize_seed().unwrap();
 
         let seed = unicorn.seed().unwrap();
-        let vdf = vdf::PietrzakVDFParams(1024).new();
+        let vdf = vdf::PietrzakVDFParams(VDF_PARAMS).new();
         let vdf_result = (0..THRESHOLD)
-            .into_iter()
-            .map(|_| vdf.solve(&seed, 1_000).unwrap())
+            .map(|_| vdf.solve(&seed, VDF_DIFFICULTY).unwrap())
             .enumerate()
             .map(|(id, res)| SimpleVdfResult {
                 id_from: id as u64,
@@ -361,12 +809,442 @@ mod tests {
         }
 
         assert!(unicorn.finalize_vdf_result().is_ok());
-        let randomness = unicorn.randomness.unwrap();
-        let randomness = hex::encode(&randomness);
+        let randomness = unicorn.randomness().unwrap();
+        let randomness = hex::encode(randomness);
 
         assert_eq!(
             randomness,
             "5eade8103071b0421c012c771fe92b5939101682ac0b321d98a57c16a96efe23"
         );
     }
+
+    #[test]
+    pub fn test_custom_delay_function() {
+        let reveals = (0..3)
+            .map(|id| SimpleSeedReveal {
+                id,
+                value: vec![id as u8; 3],
+            })
+            .collect();
+
+        let mut unicorn = SimpleUnicornWith::new(3, IteratedHash::new(1_000));
+        assert_eq!(unicorn.solve(), Err(UnicornError::SeedNotReady));
+
+        seed_unicorn_with(&mut unicorn, reveals).unwrap();
+        unicorn.finalize_seed().unwrap();
+
+        let seed = unicorn.seed().unwrap();
+        let result = unicorn.solve().unwrap();
+        assert_eq!(Some(result.len()), unicorn.delay().proof_size());
+
+        for id in 0..3 {
+            let mut result = result.clone();
+            if id == 2 {
+                result[0] ^= 0xff;
+            }
+
+            let vdf_result = SimpleVdfResult {
+                id_from: id,
+                seed: seed.clone(),
+                result,
+            };
+            let expected = if id == 2 {
+                Err(UnicornError::InvalidVdfProof)
+            } else {
+                Ok(())
+            };
+            assert_eq!(unicorn.add_vdf_result(vdf_result), expected);
+        }
+
+        assert_eq!(
+            unicorn.finalize_vdf_result(),
+            Err(UnicornError::NotEnoughVdfResults)
+        );
+    }
+
+    #[test]
+    pub fn test_dissenting_vdf_results() {
+        // Accepts any VDF result, so participants can disagree
+        struct AnyProof;
+
+        impl VdfVerifier for AnyProof {
+            fn verify(&self, _seed: &[u8], _value: &[u8]) -> bool {
+                true
+            }
+        }
+
+        impl DelayFunction for AnyProof {
+            fn solve(&self, seed: &[u8]) -> Result<Vec<u8>, DelayError> {
+                Ok(seed.to_vec())
+            }
+
+            fn parameters(&self) -> DelayParameters {
+                DelayParameters {
+                    name: "any".into(),
+                    difficulty: 0,
+                    size_bits: 0,
+                }
+            }
+
+            fn proof_size(&self) -> Option<usize> {
+                None
+            }
+        }
+
+        let votes = |values: &[u8]| {
+            let reveals = (0..5)
+                .map(|id| SimpleSeedReveal {
+                    id,
+                    value: vec![id as u8; 3],
+                })
+                .collect();
+
+            let mut unicorn = SimpleUnicornWith::new(3, AnyProof);
+            seed_unicorn_with(&mut unicorn, reveals).unwrap();
+            unicorn.finalize_seed().unwrap();
+
+            let seed = unicorn.seed().unwrap();
+            for (id, value) in values.iter().enumerate() {
+                let result = SimpleVdfResult {
+                    id_from: id as u64,
+                    seed: seed.clone(),
+                    result: vec![*value],
+                };
+                unicorn.add_vdf_result(result).unwrap();
+            }
+
+            unicorn
+        };
+
+        // Single dissenter doesn't prevent the majority from agreeing
+        let mut unicorn = votes(&[7, 9, 9, 8, 9]);
+        assert_eq!(unicorn.finalize_vdf_result(), Ok(()));
+        assert_eq!(unicorn.randomness(), Some(SimpleUnicorn::commitment(&[9])));
+
+        let quorum = unicorn.quorum().unwrap();
+        assert_eq!(quorum.supporters, vec![1, 2, 4]);
+        assert_eq!(quorum.dissenters, vec![0, 3]);
+
+        let mut unicorn = votes(&[7, 9, 9, 8, 8]);
+        assert_eq!(
+            unicorn.finalize_vdf_result(),
+            Err(UnicornError::NotEnoughVdfResults)
+        );
+        assert_eq!(unicorn.quorum(), None);
+    }
+
+    #[test]
+    pub fn test_invalid_vdf_results() {
+        const THRESHOLD: usize = 2;
+        let mut unicorn = new_unicorn(THRESHOLD);
+
+        let commitments = vec![
+            SimpleSeedReveal {
+                id: 0,
+                value: vec![0u8, 0u8, 0u8],
+            },
+            SimpleSeedReveal {
+                id: 1,
+                value: vec![1u8, 1u8, 1u8],
+            },
+        ];
+
+        seed_unicorn_with(&mut unicorn, commitments).unwrap();
+        unicorn.finalize_seed().unwrap();
+
+        let seed = unicorn.seed().unwrap();
+        let other_seed = vec![42u8; 32];
+        let vdf = vdf::PietrzakVDFParams(VDF_PARAMS).new();
+
+        // Valid proof, but for the wrong seed
+        assert_eq!(
+            unicorn.add_vdf_result(SimpleVdfResult {
+                id_from: 0,
+                seed: other_seed.clone(),
+                result: vdf.solve(&other_seed, VDF_DIFFICULTY).unwrap(),
+            }),
+            Err(UnicornError::VdfSeedMismatch)
+        );
+
+        // Right seed, but garbage instead of the proof
+        let mut result = vdf.solve(&seed, VDF_DIFFICULTY).unwrap();
+        result[0] ^= 0xff;
+        for id in 0..THRESHOLD as u64 {
+            assert_eq!(
+                unicorn.add_vdf_result(SimpleVdfResult {
+                    id_from: id,
+                    seed: seed.clone(),
+                    result: result.clone(),
+                }),
+                Err(UnicornError::InvalidVdfProof)
+            );
+        }
+
+        // Colluding majority of garbage values can't finalize the randomness
+        assert_eq!(
+            unicorn.finalize_vdf_result(),
+            Err(UnicornError::NotEnoughVdfResults)
+        );
+    }
+
+    struct SignedSeedCommitment {
+        id: u64,
+        value: Vec<u8>,
+        signature: Vec<u8>,
+    }
+
+    impl Signed for SignedSeedCommitment {
+        fn signed_bytes(&self) -> Vec<u8> {
+            self.value.clone()
+        }
+
+        fn signature(&self) -> Option<Vec<u8>> {
+            Some(self.signature.clone())
+        }
+    }
+
+    impl SeedCommitment<u64> for SignedSeedCommitment {
+        fn id(&self) -> u64 {
+            self.id
+        }
+
+        fn value(&self) -> Vec<u8> {
+            self.value.clone()
+        }
+    }
+
+    type SignedUnicorn =
+        Unicorn<u64, SignedSeedCommitment, SimpleVdfResult, sha2::Sha256, PietrzakVerifier>;
+
+    #[test]
+    pub fn test_signed_messages() {
+        use crate::identity::{Signer, SigningKey};
+
+        let keys = (0..2u8)
+            .map(|i| SigningKey::from_bytes(&[i; 32]))
+            .collect::<Vec<_>>();
+
+        let mut registry = KeyRegistry::new();
+        for (id, key) in keys.iter().enumerate() {
+            registry.register(id as u64, key.verifying_key());
+        }
+
+        let mut unicorn = SignedUnicorn::new(2, PietrzakVerifier::new(VDF_PARAMS, VDF_DIFFICULTY))
+            .with_keys(registry);
+
+        let commitment = |id: u64, key: &SigningKey| {
+            let value = SignedUnicorn::commitment(&[id as u8; 3]);
+
+            SignedSeedCommitment {
+                id,
+                signature: key.sign(&value).to_vec(),
+                value,
+            }
+        };
+
+        assert_eq!(unicorn.add_seed_commitment(commitment(0, &keys[0])), Ok(()));
+
+        // Peer #0 can't commit on behalf of peer #1
+        assert_eq!(
+            unicorn.add_seed_commitment(commitment(1, &keys[0])),
+            Err(UnicornError::InvalidSignature)
+        );
+        assert_eq!(
+            unicorn.add_seed_commitment(commitment(2, &keys[1])),
+            Err(UnicornError::UnknownParticipant)
+        );
+        assert_eq!(
+            unicorn.finalize_commitments(),
+            Err(UnicornError::NotEnoughSeedCommitments)
+        );
+
+        assert_eq!(unicorn.add_seed_commitment(commitment(1, &keys[1])), Ok(()));
+        assert_eq!(unicorn.finalize_commitments(), Ok(()));
+
+        // Unsigned reveal is rejected even though it matches the commitment
+        assert_eq!(
+            unicorn.add_seed_reveal(SimpleSeedReveal {
+                id: 0,
+                value: vec![0u8; 3],
+            }),
+            Err(UnicornError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    pub fn test_members() {
+        let mut unicorn = new_unicorn(2).with_members(vec![0, 1, 2]);
+
+        let commitment = |id: u64| SimpleSeedCommitment {
+            id,
+            value: SimpleUnicorn::commitment(&[id as u8; 3]),
+        };
+
+        assert_eq!(unicorn.add_seed_commitment(commitment(0)), Ok(()));
+        assert_eq!(
+            unicorn.add_seed_commitment(commitment(3)),
+            Err(UnicornError::NotAMember)
+        );
+        assert_eq!(
+            unicorn.finalize_commitments(),
+            Err(UnicornError::NotEnoughSeedCommitments)
+        );
+        assert_eq!(unicorn.members().map(|m| m.len()), Some(3));
+
+        let unicorn = unicorn.with_policy(ThresholdPolicy::Faults { faults: 0 });
+        assert_eq!(unicorn.threshold(), Ok(3));
+
+        // 3 members can't tolerate a faulty one, the round isn't finished by fewer of them
+        let mut unicorn = unicorn.with_policy(ThresholdPolicy::Faults { faults: 1 });
+        assert_eq!(unicorn.threshold(), Err(UnicornError::UnsafeThreshold));
+        assert_eq!(
+            unicorn.finalize_commitments(),
+            Err(UnicornError::UnsafeThreshold)
+        );
+
+        // Without members or keys there's nobody to take 2/3 of, one commitment isn't enough
+        let mut unicorn = new_unicorn(2).with_policy(ThresholdPolicy::default());
+        assert_eq!(unicorn.threshold(), Err(UnicornError::UnsafeThreshold));
+        commit_unicorn_with(
+            &mut unicorn,
+            &[SimpleSeedReveal {
+                id: 0,
+                value: vec![0; 3],
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            unicorn.finalize_commitments(),
+            Err(UnicornError::UnsafeThreshold)
+        );
+    }
+
+    #[test]
+    pub fn test_weighted_quorums() {
+        let stake = ThresholdPolicy::Stake {
+            numerator: 2,
+            denominator: 3,
+        };
+        let weighted = || {
+            new_unicorn(1)
+                .with_policy(stake)
+                .with_members(vec![0, 1, 2, 3])
+                .with_weights(vec![(0, 6), (1, 1), (2, 1), (3, 1)])
+        };
+        let reveal = |id: u64| SimpleSeedReveal {
+            id,
+            value: vec![id as u8; 3],
+        };
+
+        // 3 of 4 participants hold only 3 of 9
+        let mut unicorn = weighted();
+        assert_eq!(unicorn.stake_threshold(), Some(6));
+        commit_unicorn_with(&mut unicorn, &[reveal(1), reveal(2), reveal(3)]).unwrap();
+        assert_eq!(
+            unicorn.finalize_commitments(),
+            Err(UnicornError::NotEnoughSeedCommitments)
+        );
+
+        // While a single participant holds enough
+        let mut unicorn = weighted();
+        seed_unicorn_with(&mut unicorn, vec![reveal(0), reveal(1)]).unwrap();
+        assert_eq!(unicorn.finalize_seed(), Ok(()));
+
+        let mut minority = weighted();
+        seed_unicorn_with(&mut minority, vec![reveal(0), reveal(1)]).unwrap();
+        minority.finalize_seed().unwrap();
+        assert_eq!(
+            solve_unicorn_with(&mut minority, &[1, 2, 3]),
+            Err(UnicornError::NotEnoughVdfResults)
+        );
+
+        assert_eq!(solve_unicorn_with(&mut unicorn, &[0]), Ok(()));
+        assert_eq!(unicorn.quorum().unwrap().supporters, vec![0]);
+
+        // Weights don't matter to the policies counting participants
+        let unicorn = weighted().with_policy(ThresholdPolicy::default());
+        assert_eq!(unicorn.stake_threshold(), None);
+        assert_eq!(unicorn.threshold(), Ok(3));
+    }
+
+    /// VDF result that tells which scheme it was calculated with.
+    #[derive(Clone)]
+    struct SchemedVdfResult {
+        inner: SimpleVdfResult,
+        scheme: VdfScheme,
+    }
+
+    impl Signed for SchemedVdfResult {}
+
+    impl VdfResult<u64> for SchemedVdfResult {
+        fn id(&self) -> u64 {
+            self.inner.id_from
+        }
+
+        fn seed(&self) -> Vec<u8> {
+            self.inner.seed.clone()
+        }
+
+        fn value(&self) -> Vec<u8> {
+            self.inner.result.clone()
+        }
+
+        fn scheme(&self) -> Option<VdfScheme> {
+            Some(self.scheme)
+        }
+    }
+
+    #[test]
+    pub fn test_vdf_scheme_mismatch() {
+        type SchemedUnicorn =
+            Unicorn<u64, SimpleSeedCommitment, SchemedVdfResult, sha2::Sha256, SchemeVerifier>;
+
+        let reveals = (0..2)
+            .map(|id| SimpleSeedReveal {
+                id,
+                value: vec![id as u8; 3],
+            })
+            .collect::<Vec<_>>();
+
+        let verifier = SchemeVerifier::new(VdfScheme::Wesolowski, VDF_PARAMS, VDF_DIFFICULTY);
+        let mut unicorn = SchemedUnicorn::new(2, verifier);
+
+        for r in reveals.iter() {
+            unicorn
+                .add_seed_commitment(SimpleSeedCommitment {
+                    id: r.id,
+                    value: SchemedUnicorn::commitment(&r.value),
+                })
+                .unwrap();
+        }
+        unicorn.finalize_commitments().unwrap();
+        for r in reveals {
+            unicorn.add_seed_reveal(r).unwrap();
+        }
+        unicorn.finalize_seed().unwrap();
+
+        let seed = unicorn.seed().unwrap();
+        let result = |id, scheme: VdfScheme| SchemedVdfResult {
+            inner: SimpleVdfResult {
+                id_from: id,
+                seed: seed.clone(),
+                result: scheme.solve(VDF_PARAMS, &seed, VDF_DIFFICULTY).unwrap(),
+            },
+            scheme,
+        };
+
+        assert_eq!(
+            unicorn.add_vdf_result(result(0, VdfScheme::Pietrzak)),
+            Err(UnicornError::VdfSchemeMismatch)
+        );
+        assert_eq!(
+            unicorn.add_vdf_result(result(0, VdfScheme::Wesolowski)),
+            Ok(())
+        );
+        assert_eq!(
+            unicorn.add_vdf_result(result(1, VdfScheme::Wesolowski)),
+            Ok(())
+        );
+        assert_eq!(unicorn.finalize_vdf_result(), Ok(()));
+    }
 }
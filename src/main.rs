@@ -1,37 +1,629 @@
+mod byzantine;
+mod config;
+mod faults;
 mod network;
 mod peer;
+mod protocol;
+mod recovery;
+mod sim;
+mod tcp;
+mod worker;
 
 use actix::prelude::*;
+use clap::{Args, Parser, Subcommand};
+use rand_core::OsRng;
+use sha2::Sha256;
+use unicorn_p2p_rng::identity::{SigningKey, VerifyingKey};
+use unicorn_p2p_rng::{
+    verify_round, DelayFunction, History, KeyRegistry, NamedDigest, RoundRules, RoundTranscript,
+    ThresholdPolicy, TranscriptFormat, VdfScheme, VdfVerifier,
+};
 
-/// Number of peers that want to agree on a single verifiable random number
-const NUM_PEERS: u32 = 6;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
 
-/// Minimum delay (sec.) before peer send its commitment
-pub const COMMITMENTS_DELAY_MIN: u64 = 1;
+use config::Config;
+use network::Transport;
+use protocol::{PeerId, PeerProtocol};
 
-/// Timeout (sec.) in which peers are waiting for other peer's commitments
-pub const COMMITMENTS_ROUND_TIMEOUT: u64 = NUM_PEERS as u64 * 1;
+/// Peers agreeing on verifiable random numbers.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// TOML file with protocol parameters, see `config.example.toml`
+    #[arg(long, short, global = true)]
+    config: Option<PathBuf>,
 
-/// Timeout (sec.) in which peers are waiting for other peer's VDF calculation results
-/// after they calculated and sent its own VDF
-pub const VDF_GATHERING_TIMEOUT: u64 = NUM_PEERS as u64 * 1;
+    /// Overrides difficulty of the VDF calculation set in the config
+    #[arg(long, global = true)]
+    difficulty: Option<u64>,
 
-pub const VDF_PARAMS: u16 = 1024;
+    /// Overrides VDF scheme set in the config: pietrzak or wesolowski
+    #[arg(long, global = true)]
+    vdf_scheme: Option<VdfScheme>,
 
-/// Difficulty of the VDF calculation.
-/// VDF delay for the most CPU-powerful peer should be at least
-/// two times more than timeout of commitments gathering
-pub const VDF_DIFFICULTY: u64 = 100_000;
+    /// Runs `simulate` if omitted
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs all peers in a single process
+    Simulate {
+        /// Overrides number of peers set in the config
+        #[arg(long)]
+        peers: Option<u32>,
+
+        /// Runs peers in virtual time, the same seed always gives the same run
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Prints progress of every peer in virtual time
+        #[arg(long, requires = "seed")]
+        verbose: bool,
+    },
+
+    /// Runs a single peer connected to others over TCP
+    Node(NodeArgs),
+
+    /// Checks that the VDF result is a valid proof for the seed,
+    /// or repeats every step of a finished round from its transcript
+    Verify {
+        /// Hex-encoded seed
+        #[arg(long, requires = "result", required_unless_present = "transcript")]
+        seed: Option<String>,
+
+        /// Hex-encoded VDF result
+        #[arg(long, requires = "seed")]
+        result: Option<String>,
+
+        /// File with the transcript of a round in any format, checked against the configured VDF,
+        /// members and threshold policy
+        #[arg(long, conflicts_with = "seed")]
+        transcript: Option<PathBuf>,
+
+        /// Participant of the round as <ID>@<PUBLIC KEY>, added to the node and its peers
+        /// from the config
+        #[arg(long = "signer", value_parser = Participant::parse, requires = "transcript")]
+        signers: Vec<Participant>,
+    },
+
+    /// Generates identity key of a node
+    Keygen,
+}
+
+/// Options of the `node` command, override the `[node]` section of the config.
+#[derive(Args)]
+struct NodeArgs {
+    #[arg(long)]
+    id: Option<PeerId>,
+
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+
+    /// Hex-encoded secret key of the node
+    #[arg(long)]
+    key: Option<String>,
+
+    /// Remote peer as <ID>@<ADDR>@<PUBLIC KEY>, added to peers from the config
+    #[arg(long = "peer", value_parser = RemotePeer::parse)]
+    peers: Vec<RemotePeer>,
+
+    /// File finalized rounds are appended to, the node carries on after the last one
+    #[arg(long)]
+    history: Option<PathBuf>,
+
+    /// Directory a transcript of every finalized round is exported to
+    #[arg(long)]
+    transcripts: Option<PathBuf>,
+
+    /// Format of the exported transcripts: json or binary
+    #[arg(long)]
+    transcript_format: Option<TranscriptFormat>,
+
+    /// File the round in progress is saved to and resumed from after a restart
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+}
+
+/// Participant whose messages a transcript is checked against.
+#[derive(Clone)]
+struct Participant {
+    id: PeerId,
+    key: VerifyingKey,
+}
+
+/// Remote peer from the static peer list of a node.
+#[derive(Clone)]
+struct RemotePeer {
+    id: PeerId,
+    addr: SocketAddr,
+    key: VerifyingKey,
+}
 
 fn main() {
-    actix::System::run(|| {
+    let cli = Cli::parse();
+
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| fail(&e)),
+        None => Config::default(),
+    };
+
+    if let Some(difficulty) = cli.difficulty {
+        config.vdf_difficulty = difficulty;
+    }
+
+    if let Some(scheme) = cli.vdf_scheme {
+        config.vdf_scheme = scheme;
+    }
+
+    config.validate().unwrap_or_else(|e| fail(&e));
+
+    let command = cli.command.unwrap_or(Command::Simulate {
+        peers: None,
+        seed: None,
+        verbose: false,
+    });
+
+    match command {
+        Command::Simulate {
+            peers,
+            seed,
+            verbose,
+        } => {
+            if let Some(peers) = peers {
+                config.num_peers = peers;
+                config.validate().unwrap_or_else(|e| fail(&e));
+            }
+
+            match seed {
+                Some(seed) => simulate_virtual(config, seed, verbose),
+                None => simulate(config),
+            }
+        }
+        Command::Node(args) => node(config, args),
+        Command::Verify {
+            seed,
+            result,
+            transcript,
+            signers,
+        } => match (transcript, seed, result) {
+            (Some(path), _, _) => verify_transcript(&config, &path, signers),
+            (None, Some(seed), Some(result)) => verify(&config, &seed, &result),
+            _ => fail("either a transcript or a seed with a result is required"),
+        },
+        Command::Keygen => keygen(),
+    }
+}
+
+fn fail(error: &str) -> ! {
+    eprintln!("error: {}", error);
+    process::exit(2)
+}
+
+fn simulate(config: Config) {
+    actix::System::run(move || {
         // Create the network relay actor
         let network = network::Network::default().start();
 
-        for id in 0u32..NUM_PEERS {
-            let peer = peer::Peer::new(id, NUM_PEERS, network.clone());
+        // Every peer has its own identity key, public keys are known to everyone in advance
+        let keys = (0..config.num_peers)
+            .map(|_| SigningKey::generate(&mut OsRng))
+            .collect::<Vec<_>>();
+
+        let mut registry = KeyRegistry::new();
+        for (id, key) in keys.iter().enumerate() {
+            registry.register(id as PeerId, key.verifying_key());
+        }
+
+        let workers = worker::start(config.vdf_workers);
+
+        for (id, key) in keys.into_iter().enumerate() {
+            let protocol = PeerProtocol::new(
+                id as PeerId,
+                config.num_peers,
+                key,
+                registry.clone(),
+                config.clone(),
+                config.vdf_verifier(),
+            );
+            let mut peer = peer::Peer::new(protocol, Transport::new(&network), workers.clone());
+
+            let leave = config
+                .simulation
+                .leaves
+                .iter()
+                .find(|l| l.peer == id as PeerId);
+            if let Some(leave) = leave {
+                peer = peer.with_leave_after(Duration::from_millis(leave.at_ms));
+            }
 
             Arbiter::start(move |_| peer);
         }
     });
 }
+
+fn simulate_virtual(config: Config, seed: u64, verbose: bool) {
+    let started = Instant::now();
+    let report = sim::Simulation::new(&config, seed, verbose).run();
+
+    for (id, outcome) in report.outcomes.iter() {
+        if report.byzantine.contains(id) {
+            println!("Peer #{}: byzantine", id);
+            continue;
+        }
+
+        match outcome {
+            Some(Ok(randomness)) => println!("Peer #{}: {}", id, hex::encode(randomness)),
+            Some(Err(e)) => println!("Peer #{}: failed ({:?})", id, e),
+            None => println!("Peer #{}: didn't finish", id),
+        }
+    }
+
+    println!(
+        "{} peers, {} messages delivered, {} lost, {} VDF evaluations, {:?} of virtual time in {:?}",
+        report.outcomes.len(),
+        report.delivered,
+        report.dropped,
+        report.evaluations,
+        report.elapsed,
+        started.elapsed()
+    );
+
+    if let Some(randomness) = report.agreed() {
+        println!("[SUCCESS] Peers agreed on: {}", hex::encode(randomness));
+        return;
+    }
+
+    // Faulty network may prevent some peers from finishing, but never from agreeing
+    match report.randomness().as_slice() {
+        [randomness] => println!(
+            "[SUCCESS] Peers {:?} agreed on: {}",
+            report.succeeded(),
+            hex::encode(randomness)
+        ),
+        [] => {
+            println!("[FAILURE] Peers didn't agree, replay with --seed {}", seed);
+            process::exit(1);
+        }
+        _ => {
+            println!(
+                "[FAILURE] Peers agreed on different numbers, replay with --seed {}",
+                seed
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn node(config: Config, args: NodeArgs) {
+    let section = config.node.clone();
+
+    let id = args
+        .id
+        .or_else(|| section.as_ref()?.id)
+        .unwrap_or_else(|| fail("node ID is required"));
+    let listen = args
+        .listen
+        .or_else(|| section.as_ref()?.listen)
+        .unwrap_or_else(|| fail("listen address is required"));
+    let key = args
+        .key
+        .or_else(|| section.as_ref()?.key.clone())
+        .unwrap_or_else(|| fail("secret key is required"));
+    let key = SigningKey::from_bytes(&parse_key(&key).unwrap_or_else(|e| fail(&e)));
+    let history = args
+        .history
+        .or_else(|| section.as_ref()?.history.clone())
+        .map(|path| match History::open(&path) {
+            Ok(history) => history,
+            Err(e) => fail(&format!("failed to open {}: {:?}", path.display(), e)),
+        });
+    let last_round = match history.as_ref().map(History::last).transpose() {
+        Ok(last) => last.flatten(),
+        Err(e) => fail(&format!("failed to read the last round: {:?}", e)),
+    };
+    let transcripts = args
+        .transcripts
+        .or_else(|| section.as_ref()?.transcripts.clone());
+    let transcript_format = args
+        .transcript_format
+        .or_else(|| section.as_ref()?.transcript_format)
+        .unwrap_or(TranscriptFormat::Json);
+    if let Some(dir) = &transcripts {
+        if let Err(e) = fs::create_dir_all(dir) {
+            fail(&format!("failed to create {}: {}", dir.display(), e));
+        }
+    }
+    let checkpoint = args
+        .checkpoint
+        .or_else(|| section.as_ref()?.checkpoint.clone());
+
+    let mut peers = vec![];
+    for peer in section.map(|s| s.peers).unwrap_or_default() {
+        peers.push(RemotePeer {
+            id: peer.id,
+            addr: peer.addr,
+            key: parse_public_key(&peer.key).unwrap_or_else(|e| fail(&e)),
+        });
+    }
+    peers.extend(args.peers);
+
+    let remotes = peers.iter().map(|p| (p.id, p.addr)).collect();
+    let network = match tcp::TcpNetwork::bind(listen, remotes) {
+        Ok(network) => network,
+        Err(e) => fail(&format!("failed to listen on {}: {}", listen, e)),
+    };
+
+    let mut registry = KeyRegistry::new();
+    registry.register(id, key.verifying_key());
+    for remote in peers.iter() {
+        registry.register(remote.id, remote.key);
+    }
+
+    let num_peers = peers.len() as u32 + 1;
+    let members = config.members.as_ref().map_or(num_peers as usize, Vec::len);
+    if let Err(e) = config.threshold.validate_for(members) {
+        fail(&format!(
+            "invalid threshold for {} members: {:?}",
+            members, e
+        ));
+    }
+
+    actix::System::run(move || {
+        let network = network.start();
+        let workers = worker::start(config.vdf_workers);
+        let mut protocol = PeerProtocol::new(
+            id,
+            num_peers,
+            key,
+            registry,
+            config.clone(),
+            config.vdf_verifier(),
+        );
+        // Rounds are numbered after the beacon, not after the runs of the node
+        if let Some(last) = &last_round {
+            protocol = protocol
+                .with_last_round(last)
+                .unwrap_or_else(|e| fail(&format!("invalid history: {}", e)));
+        }
+        let mut peer = peer::Peer::new(protocol, Transport::new(&network), workers);
+        if let Some(history) = history {
+            peer = peer.with_history(history);
+        }
+        if let Some(dir) = transcripts {
+            peer = peer.with_transcripts(dir, transcript_format);
+        }
+        if let Some(path) = checkpoint {
+            peer = peer.with_checkpoint(path).unwrap_or_else(|e| fail(&e));
+        }
+
+        Arbiter::start(move |_| peer);
+    });
+}
+
+fn verify(config: &Config, seed: &str, result: &str) {
+    let seed = hex::decode(seed).unwrap_or_else(|_| fail("seed is not a hex string"));
+    let result = hex::decode(result).unwrap_or_else(|_| fail("result is not a hex string"));
+
+    if config.vdf_verifier().verify(&seed, &result) {
+        println!("valid");
+    } else {
+        println!("invalid");
+        process::exit(1);
+    }
+}
+
+fn verify_transcript(config: &Config, path: &Path, signers: Vec<Participant>) {
+    let bytes = fs::read(path)
+        .unwrap_or_else(|e| fail(&format!("failed to read {}: {}", path.display(), e)));
+    let transcript = RoundTranscript::<PeerId>::import(&bytes)
+        .unwrap_or_else(|e| fail(&format!("invalid transcript {}: {:?}", path.display(), e)));
+
+    // Threshold and stake come from the config, the transcript only declares them
+    let rules = RoundRules::new(participant_keys(config, signers)).with_policy(config.threshold);
+    let rules = match &config.members {
+        Some(members) => rules.with_members(members.iter().copied()),
+        None => rules,
+    };
+    let weights = config.weights();
+    let rules = match &weights {
+        Some(weights) => rules.with_weights(weights.clone()),
+        None => rules,
+    };
+
+    let delay = config.vdf_verifier();
+    let report = verify_round::<_, Sha256, _>(&transcript, &delay, &rules);
+    let check = |passed: bool| if passed { "ok" } else { "FAILED" };
+
+    println!("round {}", report.round);
+    println!(
+        "  digest: {} ({})",
+        check(report.digest_matches),
+        transcript.digest
+    );
+    if !report.digest_matches {
+        println!("    expected {}", Sha256::NAME);
+    }
+
+    let parameters = &transcript.parameters;
+    println!(
+        "  parameters: {} ({} with difficulty {} over {} bits)",
+        check(report.parameters_match),
+        parameters.name,
+        parameters.difficulty,
+        parameters.size_bits
+    );
+    if !report.parameters_match {
+        let expected = delay.parameters();
+        println!(
+            "    expected {} with difficulty {} over {} bits",
+            expected.name, expected.difficulty, expected.size_bits
+        );
+    }
+
+    let unit = match (config.threshold, &weights) {
+        (ThresholdPolicy::Stake { .. }, Some(_)) => "stake",
+        _ => "peers",
+    };
+    match report.threshold {
+        Some(threshold) => println!(
+            "  threshold: {} ({} {}, declared {})",
+            check(report.threshold_sufficient),
+            threshold,
+            unit,
+            transcript.threshold
+        ),
+        None => println!(
+            "  threshold: {} (participants are too few for the policy, declared {})",
+            check(false),
+            transcript.threshold
+        ),
+    }
+
+    let commitments_valid = report.invalid_signatures.is_empty() && report.commitments_reached;
+    println!(
+        "  commitments: {} ({} peers)",
+        check(commitments_valid),
+        transcript.commitments.len()
+    );
+    for id in report.invalid_signatures.iter() {
+        println!("    #{} didn't sign its commitment", id);
+    }
+
+    let reveals_valid = report.uncommitted_reveals.is_empty()
+        && report.mismatched_reveals.is_empty()
+        && report.reveals_complete;
+    println!(
+        "  reveals: {} ({} of {} committed peers)",
+        check(reveals_valid),
+        transcript.reveals.len(),
+        transcript.commitments.len()
+    );
+    for id in report.duplicates.iter() {
+        println!("    #{} took part more than once", id);
+    }
+    for id in report.uncommitted_reveals.iter() {
+        println!("    #{} revealed without a commitment", id);
+    }
+    for id in report.mismatched_reveals.iter() {
+        println!(
+            "    #{} revealed a value that doesn't match its commitment",
+            id
+        );
+    }
+
+    println!(
+        "  seed: {} ({})",
+        check(report.seed_matches),
+        hex::encode(&report.seed)
+    );
+    println!("  vdf proof: {}", check(report.vdf_valid));
+
+    let quorum_valid = report.invalid_vdf_signatures.is_empty() && report.quorum_reached;
+    println!(
+        "  quorum: {} ({} vdf results)",
+        check(quorum_valid),
+        transcript.vdf_results.len()
+    );
+    for id in report.invalid_vdf_signatures.iter() {
+        println!("    #{} didn't sign its vdf result for the seed", id);
+    }
+    println!(
+        "  randomness: {} ({})",
+        check(report.randomness_matches),
+        hex::encode(&report.randomness)
+    );
+
+    if report.passed() {
+        println!("valid");
+    } else {
+        println!("invalid");
+        process::exit(1);
+    }
+}
+
+/// Public keys of the node and its peers from the config, and of the `signers`.
+fn participant_keys(config: &Config, signers: Vec<Participant>) -> KeyRegistry<PeerId> {
+    let mut keys = KeyRegistry::new();
+    if let Some(node) = &config.node {
+        if let (Some(id), Some(key)) = (node.id, &node.key) {
+            let key = SigningKey::from_bytes(&parse_key(key).unwrap_or_else(|e| fail(&e)));
+            keys.register(id, key.verifying_key());
+        }
+        for peer in node.peers.iter() {
+            keys.register(
+                peer.id,
+                parse_public_key(&peer.key).unwrap_or_else(|e| fail(&e)),
+            );
+        }
+    }
+    for signer in signers {
+        keys.register(signer.id, signer.key);
+    }
+
+    if keys.is_empty() {
+        fail("public keys of the participants are required, pass them with --signer");
+    }
+
+    keys
+}
+
+fn keygen() {
+    let key = SigningKey::generate(&mut OsRng);
+
+    println!("secret key: {}", hex::encode(key.to_bytes()));
+    println!(
+        "public key: {}",
+        hex::encode(key.verifying_key().to_bytes())
+    );
+}
+
+impl RemotePeer {
+    fn parse(value: &str) -> Result<Self, String> {
+        let parts = value.split('@').collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return Err("expected <ID>@<ADDR>@<PUBLIC KEY>".into());
+        }
+
+        Ok(RemotePeer {
+            id: parts[0].parse().map_err(|_| "invalid peer ID")?,
+            addr: parts[1].parse().map_err(|_| "invalid peer address")?,
+            key: parse_public_key(parts[2])?,
+        })
+    }
+}
+
+impl Participant {
+    fn parse(value: &str) -> Result<Self, String> {
+        let parts = value.split('@').collect::<Vec<_>>();
+        if parts.len() != 2 {
+            return Err("expected <ID>@<PUBLIC KEY>".into());
+        }
+
+        Ok(Participant {
+            id: parts[0].parse().map_err(|_| "invalid participant ID")?,
+            key: parse_public_key(parts[1])?,
+        })
+    }
+}
+
+fn parse_key(value: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(value).map_err(|_| "key is not a hex string")?;
+    if bytes.len() != 32 {
+        return Err("key should be 32 bytes long".into());
+    }
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&bytes);
+
+    Ok(key)
+}
+
+fn parse_public_key(value: &str) -> Result<VerifyingKey, String> {
+    VerifyingKey::from_bytes(&parse_key(value)?).map_err(|_| "invalid public key".into())
+}
@@ -0,0 +1,202 @@
+//! Set of participants taking part in every round.
+//!
+//! Participants join or leave only at round boundaries: a change announced during a round
+//! takes effect at the start of the next one, so every round is run by a fixed set and
+//! its thresholds don't move while the round is in progress.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{ThresholdError, ThresholdPolicy};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Change {
+    Join,
+    Leave,
+}
+
+impl Change {
+    /// Identifier of the change in encoded messages.
+    pub fn id(self) -> u8 {
+        match self {
+            Change::Join => 1,
+            Change::Leave => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Change::Join),
+            2 => Some(Change::Leave),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MembershipError {
+    /// Round of the change has already started.
+    RoundStarted,
+}
+
+/// Participants of the current round, changes scheduled for the next ones
+/// and the participants of every round so far.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Membership<I: Ord> {
+    round: u64,
+    members: BTreeSet<I>,
+    policy: ThresholdPolicy,
+    pending: BTreeMap<u64, Vec<(I, Change)>>,
+    history: BTreeMap<u64, Vec<I>>,
+}
+
+impl<I: Ord + Clone> Membership<I> {
+    /// Starts round zero with the given participants, at least 2/3 of them should agree.
+    pub fn new<M: IntoIterator<Item = I>>(members: M) -> Self {
+        Self::starting_at(0, members)
+    }
+
+    /// Starts the `round` with the given participants, the rounds before it are unknown.
+    pub fn starting_at<M: IntoIterator<Item = I>>(round: u64, members: M) -> Self {
+        let members = members.into_iter().collect::<BTreeSet<_>>();
+
+        let mut history = BTreeMap::new();
+        history.insert(round, members.iter().cloned().collect());
+
+        Membership {
+            round,
+            members,
+            policy: ThresholdPolicy::default(),
+            pending: BTreeMap::new(),
+            history,
+        }
+    }
+
+    /// Decides how many participants of every round should agree with the `policy`.
+    pub fn with_policy(mut self, policy: ThresholdPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> &ThresholdPolicy {
+        &self.policy
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    /// Participants of the current round.
+    pub fn members(&self) -> &BTreeSet<I> {
+        &self.members
+    }
+
+    pub fn contains(&self, id: &I) -> bool {
+        self.members.contains(id)
+    }
+
+    /// Participants of the given round, `None` if the round hasn't started yet.
+    pub fn members_at(&self, round: u64) -> Option<&[I]> {
+        self.history.get(&round).map(Vec::as_slice)
+    }
+
+    /// Number of participants of the current round that should agree on every step.
+    ///
+    /// Fails if the members are too few for the policy to be safe.
+    pub fn threshold(&self) -> Result<usize, ThresholdError> {
+        self.policy.threshold(self.members.len())
+    }
+
+    /// Applies the change at the beginning of the `round`.
+    ///
+    /// Changes of the same round are applied in order they were scheduled in.
+    pub fn schedule(&mut self, round: u64, id: I, change: Change) -> Result<(), MembershipError> {
+        if round <= self.round {
+            return Err(MembershipError::RoundStarted);
+        }
+
+        self.pending.entry(round).or_default().push((id, change));
+
+        Ok(())
+    }
+
+    /// Changes scheduled for the next rounds, in order they are applied in.
+    pub fn pending(&self) -> impl Iterator<Item = (u64, &I, Change)> + '_ {
+        self.pending.iter().flat_map(|(round, changes)| {
+            changes
+                .iter()
+                .map(move |(id, change)| (*round, id, *change))
+        })
+    }
+
+    /// Applies the change at the beginning of the next round.
+    pub fn schedule_next(&mut self, id: I, change: Change) {
+        let next = self.round + 1;
+        self.pending.entry(next).or_default().push((id, change));
+    }
+
+    /// Starts the next round with the changes scheduled for it.
+    pub fn advance(&mut self) -> &BTreeSet<I> {
+        self.round += 1;
+
+        if let Some(changes) = self.pending.remove(&self.round) {
+            for (id, change) in changes {
+                match change {
+                    Change::Join => self.members.insert(id),
+                    Change::Leave => self.members.remove(&id),
+                };
+            }
+        }
+
+        let members = self.members.iter().cloned().collect();
+        self.history.insert(self.round, members);
+
+        &self.members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_changes_at_round_boundaries() {
+        let mut membership = Membership::new(vec![0u64, 1, 2, 3]);
+        assert_eq!(membership.threshold(), Ok(3));
+
+        assert_eq!(
+            membership.schedule(0, 4, Change::Join),
+            Err(MembershipError::RoundStarted)
+        );
+        membership.schedule(2, 4, Change::Join).unwrap();
+        membership.schedule(2, 5, Change::Join).unwrap();
+        membership.schedule_next(0, Change::Leave);
+
+        // Changes wait for their round
+        assert_eq!(membership.advance().len(), 3);
+        assert!(!membership.contains(&0));
+        assert_eq!(membership.threshold(), Ok(2));
+
+        assert_eq!(membership.advance().len(), 5);
+        assert!(membership.contains(&4) && membership.contains(&5));
+        assert_eq!(membership.threshold(), Ok(4));
+
+        // Participant leaving and joining again in the same round stays
+        membership.schedule_next(1, Change::Leave);
+        membership.schedule_next(1, Change::Join);
+        assert_eq!(
+            membership.pending().collect::<Vec<_>>(),
+            vec![(3, &1, Change::Leave), (3, &1, Change::Join)]
+        );
+        membership.advance();
+        assert!(membership.contains(&1));
+
+        assert_eq!(membership.round(), 3);
+        assert_eq!(membership.members_at(0), Some(&[0, 1, 2, 3][..]));
+        assert_eq!(membership.members_at(1), Some(&[1, 2, 3][..]));
+        assert_eq!(membership.members_at(2), Some(&[1, 2, 3, 4, 5][..]));
+        assert_eq!(membership.members_at(4), None);
+
+        let membership = membership.with_policy(ThresholdPolicy::Faults { faults: 1 });
+        assert_eq!(membership.threshold(), Ok(4));
+    }
+}
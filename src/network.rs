@@ -1,56 +1,437 @@
+use actix::dev::ToEnvelope;
 use actix::prelude::*;
 use rand::{self, Rng};
 
-use crate::peer::*;
+use unicorn_p2p_rng::identity::{signed_bytes, Signature, Signer, SigningKey};
+use unicorn_p2p_rng::identity::{COMMITMENT, MEMBERSHIP, REVEAL, VDF_RESULT};
+use unicorn_p2p_rng::wire::{self, Reader, WireError, WireMessage, Writer};
+use unicorn_p2p_rng::{Change, SeedCommitment, SeedReveal, Signed, VdfScheme};
 
-#[derive(Debug)]
+use crate::protocol::PeerId;
+
+#[derive(Default)]
 pub struct Network {
-    pub peers: Vec<Addr<Peer>>,
+    pub peers: Vec<(PeerId, Recipient<NetworkMessage>)>,
 }
 
 /// A peer connected to the network
 #[derive(Message)]
 pub struct Connect {
     pub id: PeerId,
-    pub addr: Addr<Peer>,
+    pub addr: Recipient<NetworkMessage>,
+}
+
+/// A peer left the network
+#[derive(Message)]
+pub struct Disconnect {
+    pub id: PeerId,
 }
 
 /// A peer sent its commitment to the randomness
-#[derive(Message, Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct Commitment {
     pub id_from: PeerId,
+    /// Round of the protocol the message belongs to.
+    pub round: u64,
     pub value: [u8; 32],
+    pub signature: Signature,
+}
+
+/// A peer revealed the secret behind its commitment
+#[derive(Debug, Copy, Clone)]
+pub struct Reveal {
+    pub id_from: PeerId,
+    /// Round of the protocol the message belongs to.
+    pub round: u64,
+    pub value: [u8; 32],
+    pub signature: Signature,
 }
 
 /// A peer calculated VDF and sent it result and seed
-#[derive(Message, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct VdfResult {
     pub id_from: PeerId,
+    /// Round of the protocol the message belongs to.
+    pub round: u64,
 
+    /// Scheme the VDF was calculated with.
+    pub scheme: VdfScheme,
     pub seed: Vec<u8>,
     pub result: Vec<u8>,
+    pub signature: Signature,
 }
 
-/// Make actor from `Network`
-impl Actor for Network {
-    type Context = Context<Self>;
+/// A peer announced that it joins or leaves the protocol after the round
+#[derive(Debug, Copy, Clone)]
+pub struct MembershipChange {
+    pub id_from: PeerId,
+    /// Round of the protocol the message belongs to.
+    pub round: u64,
+    pub change: Change,
+    pub signature: Signature,
 }
 
-impl Network {
-    pub fn broadcast_commitment(&self, commitment: Commitment) {
-        let mut peers = self.peers.clone();
+impl Commitment {
+    pub fn new(id_from: PeerId, round: u64, value: [u8; 32], key: &SigningKey) -> Self {
+        Commitment {
+            id_from,
+            round,
+            value,
+            signature: key.sign(&signed_bytes(COMMITMENT, &id_from, round, &[&value])),
+        }
+    }
+}
 
-        // Shuffle peers to simulate network propagation delay and non-determinism.
-        // Algorithm should be robust against difference in time of arrival of messages
-        rand::thread_rng().shuffle(peers.as_mut());
+impl Reveal {
+    pub fn new(id_from: PeerId, round: u64, value: [u8; 32], key: &SigningKey) -> Self {
+        Reveal {
+            id_from,
+            round,
+            value,
+            signature: key.sign(&signed_bytes(REVEAL, &id_from, round, &[&value])),
+        }
+    }
+}
 
-        // Broadcast message among peers
-        for peer in peers.iter() {
-            peer.do_send(commitment)
+impl VdfResult {
+    pub fn new(
+        id_from: PeerId,
+        round: u64,
+        scheme: VdfScheme,
+        seed: Vec<u8>,
+        result: Vec<u8>,
+        key: &SigningKey,
+    ) -> Self {
+        let parts: [&[u8]; 3] = [&[scheme.id()], &seed, &result];
+        let signature = key.sign(&signed_bytes(VDF_RESULT, &id_from, round, &parts));
+
+        VdfResult {
+            id_from,
+            round,
+            scheme,
+            seed,
+            result,
+            signature,
+        }
+    }
+}
+
+impl MembershipChange {
+    pub fn new(id_from: PeerId, round: u64, change: Change, key: &SigningKey) -> Self {
+        MembershipChange {
+            id_from,
+            round,
+            change,
+            signature: key.sign(&signed_bytes(
+                MEMBERSHIP,
+                &id_from,
+                round,
+                &[&[change.id()]],
+            )),
+        }
+    }
+}
+
+impl Signed for Commitment {
+    fn signed_bytes(&self) -> Vec<u8> {
+        signed_bytes(COMMITMENT, &self.id_from, self.round, &[&self.value])
+    }
+
+    fn signature(&self) -> Option<Vec<u8>> {
+        Some(self.signature.to_vec())
+    }
+}
+
+impl Signed for Reveal {
+    fn signed_bytes(&self) -> Vec<u8> {
+        signed_bytes(REVEAL, &self.id_from, self.round, &[&self.value])
+    }
+
+    fn signature(&self) -> Option<Vec<u8>> {
+        Some(self.signature.to_vec())
+    }
+}
+
+impl Signed for VdfResult {
+    fn signed_bytes(&self) -> Vec<u8> {
+        let parts: [&[u8]; 3] = [&[self.scheme.id()], &self.seed, &self.result];
+        signed_bytes(VDF_RESULT, &self.id_from, self.round, &parts)
+    }
+
+    fn signature(&self) -> Option<Vec<u8>> {
+        Some(self.signature.to_vec())
+    }
+}
+
+impl Signed for MembershipChange {
+    fn signed_bytes(&self) -> Vec<u8> {
+        signed_bytes(
+            MEMBERSHIP,
+            &self.id_from,
+            self.round,
+            &[&[self.change.id()]],
+        )
+    }
+
+    fn signature(&self) -> Option<Vec<u8>> {
+        Some(self.signature.to_vec())
+    }
+}
+
+impl SeedCommitment<PeerId> for Commitment {
+    fn id(&self) -> PeerId {
+        self.id_from
+    }
+
+    fn value(&self) -> Vec<u8> {
+        self.value.to_vec()
+    }
+}
+
+impl SeedReveal<PeerId> for Reveal {
+    fn id(&self) -> PeerId {
+        self.id_from
+    }
+
+    fn value(&self) -> Vec<u8> {
+        self.value.to_vec()
+    }
+}
+
+impl unicorn_p2p_rng::VdfResult<PeerId> for VdfResult {
+    fn id(&self) -> PeerId {
+        self.id_from
+    }
+
+    fn seed(&self) -> Vec<u8> {
+        self.seed.clone()
+    }
+
+    fn value(&self) -> Vec<u8> {
+        self.result.clone()
+    }
+
+    fn scheme(&self) -> Option<VdfScheme> {
+        Some(self.scheme)
+    }
+}
+
+impl WireMessage for Commitment {
+    const KIND: u8 = 1;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u32(self.id_from);
+        writer.put_u64(self.round);
+        writer.put_fixed(&self.value);
+        writer.put_fixed(&self.signature.to_bytes());
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        Ok(Commitment {
+            id_from: reader.get_u32()?,
+            round: reader.get_u64()?,
+            value: reader.get_fixed()?,
+            signature: Signature::from_bytes(&reader.get_fixed()?),
+        })
+    }
+}
+
+impl WireMessage for Reveal {
+    const KIND: u8 = 2;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u32(self.id_from);
+        writer.put_u64(self.round);
+        writer.put_fixed(&self.value);
+        writer.put_fixed(&self.signature.to_bytes());
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        Ok(Reveal {
+            id_from: reader.get_u32()?,
+            round: reader.get_u64()?,
+            value: reader.get_fixed()?,
+            signature: Signature::from_bytes(&reader.get_fixed()?),
+        })
+    }
+}
+
+impl WireMessage for VdfResult {
+    const KIND: u8 = 3;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u32(self.id_from);
+        writer.put_u64(self.round);
+        writer.put_u8(self.scheme.id());
+        writer.put_bytes(&self.seed);
+        writer.put_bytes(&self.result);
+        writer.put_fixed(&self.signature.to_bytes());
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        let id_from = reader.get_u32()?;
+        let round = reader.get_u64()?;
+        let scheme = reader.get_u8()?;
+
+        Ok(VdfResult {
+            id_from,
+            round,
+            scheme: VdfScheme::from_id(scheme).ok_or(WireError::UnknownTag {
+                kind: Self::KIND,
+                tag: scheme,
+            })?,
+            seed: reader.get_bytes()?,
+            result: reader.get_bytes()?,
+            signature: Signature::from_bytes(&reader.get_fixed()?),
+        })
+    }
+}
+
+impl WireMessage for MembershipChange {
+    const KIND: u8 = 4;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u32(self.id_from);
+        writer.put_u64(self.round);
+        writer.put_u8(self.change.id());
+        writer.put_fixed(&self.signature.to_bytes());
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        let id_from = reader.get_u32()?;
+        let round = reader.get_u64()?;
+        let change = reader.get_u8()?;
+
+        Ok(MembershipChange {
+            id_from,
+            round,
+            change: Change::from_id(change).ok_or(WireError::UnknownTag {
+                kind: Self::KIND,
+                tag: change,
+            })?,
+            signature: Signature::from_bytes(&reader.get_fixed()?),
+        })
+    }
+}
+
+/// Any of the protocol messages peers exchange.
+#[derive(Message, Debug, Clone)]
+pub enum NetworkMessage {
+    Commitment(Commitment),
+    Reveal(Reveal),
+    VdfResult(VdfResult),
+    Membership(MembershipChange),
+}
+
+impl NetworkMessage {
+    pub fn id_from(&self) -> PeerId {
+        match self {
+            NetworkMessage::Commitment(msg) => msg.id_from,
+            NetworkMessage::Reveal(msg) => msg.id_from,
+            NetworkMessage::VdfResult(msg) => msg.id_from,
+            NetworkMessage::Membership(msg) => msg.id_from,
+        }
+    }
+
+    /// Round of the protocol the message belongs to.
+    pub fn round(&self) -> u64 {
+        match self {
+            NetworkMessage::Commitment(msg) => msg.round,
+            NetworkMessage::Reveal(msg) => msg.round,
+            NetworkMessage::VdfResult(msg) => msg.round,
+            NetworkMessage::Membership(msg) => msg.round,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            NetworkMessage::Commitment(msg) => wire::encode(msg),
+            NetworkMessage::Reveal(msg) => wire::encode(msg),
+            NetworkMessage::VdfResult(msg) => wire::encode(msg),
+            NetworkMessage::Membership(msg) => wire::encode(msg),
+        }
+    }
+
+    /// Decodes payload of a frame by the kind of message in its header.
+    pub fn decode_payload(kind: u8, payload: &[u8]) -> Result<Self, WireError> {
+        match kind {
+            Commitment::KIND => wire::decode_payload(kind, payload).map(NetworkMessage::Commitment),
+            Reveal::KIND => wire::decode_payload(kind, payload).map(NetworkMessage::Reveal),
+            VdfResult::KIND => wire::decode_payload(kind, payload).map(NetworkMessage::VdfResult),
+            MembershipChange::KIND => {
+                wire::decode_payload(kind, payload).map(NetworkMessage::Membership)
+            }
+            _ => Err(WireError::UnexpectedKind(kind)),
+        }
+    }
+
+    /// Hands the message over to the peer.
+    pub fn deliver_to(self, peer: &Recipient<NetworkMessage>) {
+        let _ = peer.do_send(self);
+    }
+}
+
+impl From<Commitment> for NetworkMessage {
+    fn from(msg: Commitment) -> Self {
+        NetworkMessage::Commitment(msg)
+    }
+}
+
+impl From<Reveal> for NetworkMessage {
+    fn from(msg: Reveal) -> Self {
+        NetworkMessage::Reveal(msg)
+    }
+}
+
+impl From<VdfResult> for NetworkMessage {
+    fn from(msg: VdfResult) -> Self {
+        NetworkMessage::VdfResult(msg)
+    }
+}
+
+impl From<MembershipChange> for NetworkMessage {
+    fn from(msg: MembershipChange) -> Self {
+        NetworkMessage::Membership(msg)
+    }
+}
+
+/// Network the peer is connected to: either in-process `Network` relay or `TcpNetwork`.
+#[derive(Clone)]
+pub struct Transport {
+    pub connect: Recipient<Connect>,
+    pub disconnect: Recipient<Disconnect>,
+    pub messages: Recipient<NetworkMessage>,
+}
+
+impl Transport {
+    pub fn new<A>(addr: &Addr<A>) -> Self
+    where
+        A: Handler<Connect> + Handler<Disconnect> + Handler<NetworkMessage>,
+        A::Context:
+            ToEnvelope<A, Connect> + ToEnvelope<A, Disconnect> + ToEnvelope<A, NetworkMessage>,
+    {
+        Transport {
+            connect: addr.clone().recipient(),
+            disconnect: addr.clone().recipient(),
+            messages: addr.clone().recipient(),
         }
     }
 
-    pub fn broadcast_vdf_result(&self, result: VdfResult) {
+    /// Sends the message to every peer, including the sender itself.
+    pub fn broadcast<M: Into<NetworkMessage>>(&self, msg: M) {
+        if let Err(e) = self.messages.do_send(msg.into()) {
+            println!("[network] failed to send message: {}", e);
+        }
+    }
+}
+
+/// Make actor from `Network`
+impl Actor for Network {
+    type Context = Context<Self>;
+}
+
+impl Network {
+    pub fn broadcast(&self, msg: NetworkMessage) {
         let mut peers = self.peers.clone();
 
         // Shuffle peers to simulate network propagation delay and non-determinism.
@@ -58,40 +439,172 @@ impl Network {
         rand::thread_rng().shuffle(peers.as_mut());
 
         // Broadcast message among peers
-        for peer in peers.iter() {
-            peer.do_send(result.clone())
+        for (_, peer) in peers.iter() {
+            msg.clone().deliver_to(peer);
         }
     }
 }
 
-impl Default for Network {
-    fn default() -> Self {
-        Network { peers: vec![] }
-    }
-}
-
 impl Handler<Connect> for Network {
     type Result = ();
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) {
         println!("[network] Peer {:?} joined the network", msg.id);
 
-        self.peers.push(msg.addr);
+        self.peers.push((msg.id, msg.addr));
     }
 }
 
-impl Handler<Commitment> for Network {
+impl Handler<Disconnect> for Network {
     type Result = ();
 
-    fn handle(&mut self, msg: Commitment, _: &mut Context<Self>) {
-        self.broadcast_commitment(msg);
+    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
+        println!("[network] Peer {:?} left the network", msg.id);
+
+        self.peers.retain(|(id, _)| *id != msg.id);
     }
 }
 
-impl Handler<VdfResult> for Network {
+impl Handler<NetworkMessage> for Network {
     type Result = ();
 
-    fn handle(&mut self, msg: VdfResult, _: &mut Context<Self>) {
-        self.broadcast_vdf_result(msg);
+    fn handle(&mut self, msg: NetworkMessage, _: &mut Context<Self>) {
+        self.broadcast(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unicorn_p2p_rng::{KeyRegistry, UnicornError};
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32])
+    }
+
+    #[test]
+    pub fn test_messages_roundtrip() {
+        let commitment = Commitment::new(1, 5, [1u8; 32], &key());
+        let decoded = wire::decode::<Commitment>(&wire::encode(&commitment)).unwrap();
+        assert_eq!(decoded.id_from, commitment.id_from);
+        assert_eq!(decoded.round, commitment.round);
+        assert_eq!(decoded.value, commitment.value);
+        assert_eq!(decoded.signed_bytes(), commitment.signed_bytes());
+        assert_eq!(decoded.signature, commitment.signature);
+
+        let reveal = Reveal::new(2, 5, [2u8; 32], &key());
+        let decoded = wire::decode::<Reveal>(&wire::encode(&reveal)).unwrap();
+        assert_eq!(decoded.id_from, reveal.id_from);
+        assert_eq!(decoded.round, reveal.round);
+        assert_eq!(decoded.value, reveal.value);
+        assert_eq!(decoded.signature, reveal.signature);
+
+        let vdf_result = VdfResult::new(
+            3,
+            u64::MAX,
+            VdfScheme::Wesolowski,
+            vec![3u8; 32],
+            vec![4u8; 100],
+            &key(),
+        );
+        let frame = NetworkMessage::from(vdf_result.clone()).encode();
+        let frame = wire::split_frame(&frame).unwrap().unwrap();
+        match NetworkMessage::decode_payload(frame.kind, frame.payload).unwrap() {
+            NetworkMessage::VdfResult(decoded) => {
+                assert_eq!(decoded.id_from, vdf_result.id_from);
+                assert_eq!(decoded.round, vdf_result.round);
+                assert_eq!(decoded.scheme, vdf_result.scheme);
+                assert_eq!(decoded.seed, vdf_result.seed);
+                assert_eq!(decoded.result, vdf_result.result);
+                assert_eq!(decoded.signature, vdf_result.signature);
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+
+        let leave = MembershipChange::new(4, 2, Change::Leave, &key());
+        let decoded = wire::decode::<MembershipChange>(&wire::encode(&leave)).unwrap();
+        assert_eq!(decoded.id_from, leave.id_from);
+        assert_eq!(decoded.round, leave.round);
+        assert_eq!(decoded.change, leave.change);
+        assert_eq!(decoded.signed_bytes(), leave.signed_bytes());
+
+        let mut frame = wire::encode(&leave);
+        frame[wire::HEADER_LEN + 12] = 0x42;
+        assert_eq!(
+            wire::decode::<MembershipChange>(&frame).err(),
+            Some(WireError::UnknownTag {
+                kind: MembershipChange::KIND,
+                tag: 0x42
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_malformed_messages() {
+        let frame = wire::encode(&Commitment::new(1, 0, [1u8; 32], &key()));
+
+        // Commitment frame can't be decoded as a reveal
+        assert_eq!(
+            wire::decode::<Reveal>(&frame).err(),
+            Some(WireError::UnexpectedKind(Commitment::KIND))
+        );
+
+        let mut unknown_kind = frame.clone();
+        unknown_kind[5] = 0x42;
+        let unknown_kind = wire::split_frame(&unknown_kind).unwrap().unwrap();
+        assert_eq!(
+            NetworkMessage::decode_payload(unknown_kind.kind, unknown_kind.payload).err(),
+            Some(WireError::UnexpectedKind(0x42))
+        );
+
+        // Signature is cut off
+        let mut truncated = frame[..frame.len() - 1].to_vec();
+        truncated[wire::HEADER_LEN - 1] -= 1;
+        assert_eq!(
+            wire::decode::<Commitment>(&truncated).err(),
+            Some(WireError::Truncated)
+        );
+
+        // Seed length points past the end of the frame
+        let vdf_result = VdfResult::new(
+            3,
+            0,
+            VdfScheme::Pietrzak,
+            vec![3u8; 32],
+            vec![4u8; 100],
+            &key(),
+        );
+        let mut frame = wire::encode(&vdf_result);
+        frame[wire::HEADER_LEN + 13..wire::HEADER_LEN + 17].copy_from_slice(&[0xff; 4]);
+        assert_eq!(
+            wire::decode::<VdfResult>(&frame).err(),
+            Some(WireError::Truncated)
+        );
+
+        let mut frame = wire::encode(&vdf_result);
+        frame[wire::HEADER_LEN + 12] = 0x42;
+        assert_eq!(
+            wire::decode::<VdfResult>(&frame).err(),
+            Some(WireError::UnknownTag {
+                kind: VdfResult::KIND,
+                tag: 0x42
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_signature_covers_round() {
+        let mut registry = KeyRegistry::new();
+        registry.register(1, key().verifying_key());
+
+        let mut commitment = Commitment::new(1, 3, [1u8; 32], &key());
+        assert_eq!(registry.verify(&1, &commitment), Ok(()));
+
+        // Message of an earlier round can't be passed off as a message of the current one
+        commitment.round = 4;
+        assert_eq!(
+            registry.verify(&1, &commitment),
+            Err(UnicornError::InvalidSignature)
+        );
     }
 }
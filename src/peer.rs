@@ -1,232 +1,347 @@
 use actix::prelude::*;
 
-use super::{
-    COMMITMENTS_DELAY_MIN, COMMITMENTS_ROUND_TIMEOUT, VDF_DIFFICULTY, VDF_GATHERING_TIMEOUT,
-    VDF_PARAMS,
-};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use crate::network::*;
+use crate::protocol::*;
+use crate::recovery::PeerCheckpoint;
+use crate::worker::{Job, Solve, VdfWorker};
 
-use rand::{self, Rng};
+use unicorn_p2p_rng::{
+    BeaconRecord, DelayError, DelayFunction, DelayState, History, SchemeVerifier, TranscriptFormat,
+    UnicornState,
+};
 
-use std::collections::HashMap;
-use std::time::Duration;
+/// How often a peer reports progress of its VDF calculation.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
-use vdf::*;
+/// Runs the peer protocol in real time on top of a network transport.
+pub struct Peer<F: DelayFunction + 'static = SchemeVerifier> {
+    /// Protocol state of the peer.
+    pub protocol: PeerProtocol<F>,
 
-/// Defines possible states of the peer.
-#[derive(Debug, Copy, Clone)]
-pub enum PeerState {
-    Idle,
-    Connected,
-    Commit,
-    DoingVdf,
-    VerifyingVdf,
-}
+    /// Network the peer is connected to.
+    pub net: Transport,
 
-pub type PeerId = u32;
+    /// Threads VDF is calculated on.
+    pub workers: Addr<VdfWorker<PeerVerifier<F>>>,
 
-/// Describes single independent peer in the network.
-#[derive(Debug)]
-pub struct Peer {
-    /// ID of this peer.
-    pub id: PeerId,
+    /// VDF calculation in progress.
+    job: Option<Job>,
 
-    /// Total number of peers known.
-    pub num_peers: u32,
+    /// When the peer leaves the protocol, if ever.
+    leave_after: Option<Duration>,
 
-    /// Peer's address in the network.
-    pub net_addr: Addr<Network>,
+    /// Store of the finalized rounds, if any.
+    history: Option<History<PeerId>>,
 
-    /// Current state of the peer.
-    pub state: PeerState,
+    /// Directory transcripts of the finalized rounds are exported to, if any.
+    transcripts: Option<(PathBuf, TranscriptFormat)>,
 
-    /// Collection of the commitments to the seed from the peers.
-    pub commitments: HashMap<PeerId, Commitment>,
+    /// File the state of the round is saved to, if any.
+    checkpoint: Option<PathBuf>,
 
-    /// Seed for the VDF in current round.
-    pub seed: Option<Vec<u8>>,
+    /// Round, state of the peer and of the round the checkpoint was last saved in.
+    saved: Option<(u64, PeerState, UnicornState)>,
 
-    /// Collection of VDF results received from the peers.
-    pub vdf_results: HashMap<PeerId, VdfResult>,
+    /// The peer carries on with a round restored from the checkpoint.
+    resumed: bool,
+
+    /// VDF calculation of the restored round saved before the restart, with its seed.
+    restored_vdf: Option<(Vec<u8>, DelayState)>,
 }
 
-impl Peer {
-    pub fn new(id: u32, num_peers: u32, net_addr: Addr<Network>) -> Self {
+impl<F: DelayFunction + Clone + Send + 'static> Peer<F> {
+    pub fn new(
+        protocol: PeerProtocol<F>,
+        net: Transport,
+        workers: Addr<VdfWorker<PeerVerifier<F>>>,
+    ) -> Self {
         Peer {
-            id,
-            num_peers,
-            net_addr,
-
-            state: PeerState::Idle,
-            commitments: HashMap::new(),
-            seed: None,
-            vdf_results: HashMap::new(),
+            protocol,
+            net,
+            workers,
+            job: None,
+            leave_after: None,
+            history: None,
+            transcripts: None,
+            checkpoint: None,
+            saved: None,
+            resumed: false,
+            restored_vdf: None,
         }
     }
 
-    fn create_commitment_after_delay(&mut self, ctx: &mut actix::Context<Self>) {
-        println!(
-            "[commitment round] Peer #{} is creating a commitment",
-            self.id
-        );
+    pub fn with_leave_after(mut self, delay: Duration) -> Self {
+        self.leave_after = Some(delay);
+        self
+    }
 
-        let delay = COMMITMENTS_DELAY_MIN + rand::thread_rng().gen::<u64>() % 5;
-        ctx.run_later(Duration::new(delay, 0), |act, _| {
-            let mut array = [0u8; 32];
-            rand::thread_rng().fill_bytes(&mut array);
+    /// Appends every finalized round to the `history`.
+    pub fn with_history(mut self, history: History<PeerId>) -> Self {
+        self.history = Some(history);
+        self
+    }
 
-            let commitment = Commitment {
-                id_from: act.id,
-                value: array,
-            };
+    /// Exports the transcript of every finalized round to a file of its own in the `dir`.
+    pub fn with_transcripts(mut self, dir: PathBuf, format: TranscriptFormat) -> Self {
+        self.transcripts = Some((dir, format));
+        self
+    }
 
-            act.net_addr.do_send(commitment);
-            act.state = PeerState::Commit;
-        });
+    /// Saves the state of the round to the `path` every time it moves on to the next step,
+    /// and carries on with the round saved there if the file exists.
+    ///
+    /// The checkpoint is removed once its round is finalized and stored. A round the
+    /// history already has went down before its checkpoint was removed, it's not resumed.
+    pub fn with_checkpoint(mut self, path: PathBuf) -> Result<Self, String> {
+        let last_round = self.history.as_ref().and_then(History::last_round);
+        let checkpoint = PeerCheckpoint::load(&path)?
+            .filter(|checkpoint| last_round.is_none_or(|last| checkpoint.round > last));
+
+        if checkpoint.is_none() {
+            PeerCheckpoint::remove(&path)
+                .map_err(|e| format!("failed to remove {}: {}", path.display(), e))?;
+        }
 
-        ctx.run_later(Duration::new(COMMITMENTS_ROUND_TIMEOUT, 0), |act, ctx| {
-            act.commitments_round_finished(ctx)
-        });
+        if let Some(checkpoint) = checkpoint {
+            self.restored_vdf = checkpoint.unicorn.seed.clone().zip(checkpoint.vdf.clone());
+            self.protocol
+                .restore(checkpoint)
+                .map_err(|e| format!("failed to restore {}: {:?}", path.display(), e))?;
+            self.resumed = true;
+        }
+
+        self.checkpoint = Some(path);
+        Ok(self)
     }
 
-    fn commitments_round_finished(&mut self, ctx: &mut Context<Self>) {
-        // Commitment round finished but actor wasn't commit,
-        // something went wrong and actor is aboring
-        match self.state {
-            PeerState::Commit => {}
+    fn perform(&mut self, actions: Vec<Action>, ctx: &mut Context<Self>) {
+        // Secret and messages of the peer are on disk before anybody sees them, they're
+        // only sent when the round moves on
+        let step = (
+            self.protocol.round,
+            self.protocol.state,
+            self.protocol.unicorn.state(),
+        );
+        if self.saved != Some(step) {
+            self.save_checkpoint();
+            self.saved = Some(step);
+        }
+
+        for action in actions {
+            match action {
+                Action::Broadcast(msg) => self.net.broadcast(msg),
+
+                Action::Schedule(delay, timer) => {
+                    ctx.run_later(delay, move |act, ctx| {
+                        let actions = act.protocol.on_timer(timer);
+                        act.perform(actions, ctx);
+                    });
+                }
+
+                Action::SolveVdf(seed) => self.solve_vdf(seed, ctx),
+
+                Action::CancelVdf => self.cancel_vdf(),
 
-            _ => {
-                println!("[commitment round] Peer {} wasn't commit", self.id);
+                Action::Record(record) => {
+                    self.store(&record);
+                    self.export_transcript(record.round);
+                    self.remove_checkpoint();
+                }
 
-                ctx.stop();
-                return;
+                Action::Stop => ctx.stop(),
             }
+        }
+    }
+
+    /// Hands the calculation over to the workers, the peer keeps handling messages meanwhile.
+    fn solve_vdf(&mut self, seed: Vec<u8>, ctx: &mut Context<Self>) {
+        self.cancel_vdf();
+
+        let job = Job::new();
+        self.job = Some(job.clone());
+        let handle = job.clone();
+
+        let from = self
+            .restored_vdf
+            .as_ref()
+            .filter(|(restored, _)| *restored == seed)
+            .map(|(_, state)| state.clone());
+
+        let solve = Solve {
+            delay: self.protocol.delay().clone(),
+            seed: seed.clone(),
+            from,
+            job,
         };
 
-        // If we collected more than 2/3 of commitments we can proceed to
-        // combining them into a seed
-        if self.commitments.len() as f32 >= self.num_peers as f32 * (2f32 / 3f32) {
-            // Sort commitments by peer ID to protect from different result per peer due to
-            // different time of arrival of particular commitment to the particular peer.
-            let mut commitments = self
-                .commitments
-                .values()
-                .into_iter()
-                .map(|c| *c)
-                .collect::<Vec<_>>();
-            commitments.sort_unstable_by_key(|k| k.id_from);
+        self.workers
+            .send(solve)
+            .into_actor(self)
+            .then(move |result, act, ctx| {
+                // Cancelled calculation may finish after the next one started, leave that one be
+                if act.job.as_ref().is_some_and(|current| current.is(&handle)) {
+                    act.job = None;
+                }
 
-            println!(
-                "[commitment round] Peer #{} is creating seed from commitments: {:?}",
-                self.id, commitments
-            );
+                match result {
+                    Ok(Ok(result)) => {
+                        let actions = act.protocol.on_vdf_solved(seed, result);
+                        act.perform(actions, ctx);
+                    }
 
-            // Create a seed by appending commitments
-            let seed = commitments
-                .into_iter()
-                .map(|c| c.value.to_vec())
-                .flatten()
-                .collect::<Vec<_>>();
-            let seed = hash(&seed);
+                    Ok(Err(DelayError::Cancelled)) => {}
 
-            self.seed = Some(seed.clone());
+                    Ok(Err(e)) => {
+                        println!(
+                            "[error] Peer #{} failed to calculate VDF: {:?}",
+                            act.protocol.id, e
+                        );
+                        ctx.stop();
+                    }
 
-            println!(
-                "[commitment round] #{}: seed created: {}",
-                self.id,
-                hex::encode(seed)
-            );
+                    Err(e) => {
+                        println!("[error] Peer #{} lost VDF workers: {}", act.protocol.id, e);
+                        ctx.stop();
+                    }
+                }
 
-            self.calculate_vdf(ctx);
-        } else {
-            println!(
-                "[commitment round] #{}: not enough commitments collected, restarting",
-                self.id
-            );
-            self.create_commitment_after_delay(ctx);
-        }
+                actix::fut::ok(())
+            })
+            .spawn(ctx);
     }
 
-    fn calculate_vdf(&mut self, ctx: &mut Context<Self>) {
-        if let None = self.seed {
-            println!("[error] Peer #{} didn't generated a seed", self.id);
-            return;
+    fn store(&mut self, record: &BeaconRecord<PeerId>) {
+        if let Some(history) = self.history.as_mut() {
+            if let Err(e) = history.append(record) {
+                println!(
+                    "[error] Peer #{} failed to store round {}: {:?}",
+                    self.protocol.id, record.round, e
+                );
+            }
         }
+    }
 
-        let seed = self.seed.clone().unwrap();
+    fn export_transcript(&self, round: u64) {
+        let (dir, format) = match &self.transcripts {
+            Some(transcripts) => transcripts,
+            None => return,
+        };
 
-        println!("[vdf round] Peer #{} is calculating VDF...", self.id);
+        // Round is still the finalized one until the timer starts the next
+        let transcript = match self.protocol.unicorn.transcript(round) {
+            Some(transcript) => transcript,
+            None => return,
+        };
 
-        self.state = PeerState::DoingVdf;
-        let witness = vdf::PietrzakVDFParams(VDF_PARAMS)
-            .new()
-            .solve(&seed, VDF_DIFFICULTY)
-            .unwrap();
+        let path = dir.join(format!("round-{}.{}", round, format.extension()));
+        if let Err(e) = fs::write(&path, transcript.export(*format)) {
+            println!(
+                "[error] Peer #{} failed to export transcript {}: {}",
+                self.protocol.id,
+                path.display(),
+                e
+            );
+        }
+    }
 
-        let vdf_result = VdfResult {
-            id_from: self.id,
-            seed,
-            result: witness,
+    fn save_checkpoint(&self) {
+        let path = match &self.checkpoint {
+            Some(path) => path,
+            None => return,
         };
 
-        //println!("[vdf round] Peer #{} is calculated VDF and sent its result", self.id);
-        self.net_addr.do_send(vdf_result);
-
-        ctx.run_later(Duration::new(VDF_GATHERING_TIMEOUT, 0), |act, _| {
-            act.state = PeerState::VerifyingVdf;
+        // Finalized round is over, its checkpoint is removed once it's stored
+        if self.protocol.unicorn.state() == UnicornState::RandomnessReady {
+            return;
+        }
 
-            println!("[vdf round] Peer #{} is verifying {} VDF results", act.id, act.vdf_results.len());
+        let mut checkpoint = self.protocol.checkpoint();
+        checkpoint.vdf = self.job.as_ref().and_then(Job::saved).or_else(|| {
+            let (seed, state) = self.restored_vdf.as_ref()?;
+            (checkpoint.unicorn.seed.as_ref() == Some(seed)).then(|| state.clone())
+        });
 
-            // Verify all VDF results that we collected
-            let mut num_valid = 0;
-            if let Some(seed) = act.seed.clone() {
-                for vdf_result in act.vdf_results.values() {
-                    // Reject results with different seed
-                    if vdf_result.seed != seed {
-                        continue
-                    }
+        if let Err(e) = checkpoint.save(path) {
+            println!(
+                "[error] Peer #{} failed to save checkpoint {}: {}",
+                self.protocol.id,
+                path.display(),
+                e
+            );
+        }
+    }
 
-                    let verification = vdf::PietrzakVDFParams(VDF_PARAMS).new().verify(&seed, VDF_DIFFICULTY, &vdf_result.result);
-                    if verification.is_ok() {
-                        num_valid += 1;
-                    }
-                }
+    fn remove_checkpoint(&self) {
+        if let Some(path) = &self.checkpoint {
+            if let Err(e) = PeerCheckpoint::remove(path) {
+                println!(
+                    "[error] Peer #{} failed to remove checkpoint {}: {}",
+                    self.protocol.id,
+                    path.display(),
+                    e
+                );
             }
+        }
+    }
 
-            // If more than 2/3 of valid results collected
-            if num_valid as f32 >= act.num_peers as f32 * (2f32 / 3f32) {
-                // New random is the any of the valid VDF results (they're supposed to be the same)
-                let new_random_number = &act.vdf_results
-                    .values().nth(0).clone().unwrap()
-                    .result;
-                let new_random_number = hash(&new_random_number);
-
-                println!("[SUCCESS] Peer #{} thinks that more than 2/3 of peers agreed on: {} as next random number", act.id, hex::encode(new_random_number));
-            } else {
-                println!("[FAILURE] Peer #{} thinks that there's not enough evidence to think that any valid number are possible to obtain.", act.id);
-            }
-        });
+    fn cancel_vdf(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.cancel();
+        }
+    }
+
+    fn report_progress(&self) {
+        if let (Some(job), true) = (&self.job, self.protocol.verbose) {
+            println!(
+                "[vdf round] Peer #{} did {} of {} VDF steps in {:?}",
+                self.protocol.id,
+                job.steps(),
+                self.protocol.delay().parameters().difficulty,
+                job.started.elapsed()
+            );
+        }
     }
 }
 
 /// Make actor from `Peer`
-impl Actor for Peer {
+impl<F: DelayFunction + Clone + Send + 'static> Actor for Peer<F> {
     type Context = actix::Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(PROGRESS_INTERVAL, |act, _| {
+            act.report_progress();
+
+            // Keep the calculated part of VDF in case the peer goes down before it finishes
+            if act.job.is_some() {
+                act.save_checkpoint();
+            }
+        });
+
+        if let Some(delay) = self.leave_after {
+            ctx.notify_later(Leave, delay);
+        }
+
         // register self in network. `AsyncContext::wait` register
         // future within context, but context waits until this future resolves
         // before processing any other events.
-        self.net_addr
+        self.net
+            .connect
             .send(Connect {
-                addr: ctx.address(),
-                id: self.id,
+                addr: ctx.address().recipient(),
+                id: self.protocol.id,
             })
             .into_actor(self)
             .then(|_, act, ctx| {
-                act.state = PeerState::Connected;
-
-                act.create_commitment_after_delay(ctx);
+                let actions = if act.resumed {
+                    act.protocol.resume()
+                } else {
+                    act.protocol.start()
+                };
+                act.perform(actions, ctx);
 
                 actix::fut::ok(())
             })
@@ -234,49 +349,33 @@ impl Actor for Peer {
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.cancel_vdf();
+        let _ = self.net.disconnect.do_send(Disconnect {
+            id: self.protocol.id,
+        });
+
         Running::Stop
     }
 }
 
-impl Handler<Commitment> for Peer {
-    type Result = ();
+/// Asks the peer to leave the protocol
+#[derive(Message)]
+pub struct Leave;
 
-    fn handle(&mut self, msg: Commitment, _: &mut Context<Self>) {
-        // Save commitment if it wasn't already received
-        if !self.commitments.contains_key(&msg.id_from) {
-            let id_from = msg.id_from;
-            self.commitments.insert(msg.id_from, msg);
+impl<F: DelayFunction + Clone + Send + 'static> Handler<Leave> for Peer<F> {
+    type Result = ();
 
-            println!(
-                "[commitment round] Peer #{} saved commitment {} from #{}",
-                self.id,
-                hex::encode(msg.value),
-                id_from
-            );
-        }
+    fn handle(&mut self, _: Leave, ctx: &mut Context<Self>) {
+        let actions = self.protocol.leave();
+        self.perform(actions, ctx);
     }
 }
 
-impl Handler<VdfResult> for Peer {
+impl<F: DelayFunction + Clone + Send + 'static> Handler<NetworkMessage> for Peer<F> {
     type Result = ();
 
-    fn handle(&mut self, msg: VdfResult, _: &mut Context<Self>) {
-        if !self.vdf_results.contains_key(&msg.id_from) {
-            let id_from = msg.id_from;
-            self.vdf_results.insert(msg.id_from, msg);
-
-            println!(
-                "[vdf round] Peer #{} saved VDF result from #{}",
-                self.id, id_from
-            );
-        }
+    fn handle(&mut self, msg: NetworkMessage, ctx: &mut Context<Self>) {
+        let actions = self.protocol.on_message(msg);
+        self.perform(actions, ctx);
     }
 }
-
-fn hash(bytes: &[u8]) -> Vec<u8> {
-    use sha2::Digest;
-
-    let mut sha = sha2::Sha256::new();
-    sha.input(bytes);
-    sha.result().to_vec()
-}
@@ -0,0 +1,1062 @@
+//! Protocol logic of a single peer, independent of the clock and the network it runs on.
+//!
+//! `PeerProtocol` reacts to messages, timers and solved VDFs with a list of `Action`s
+//! for the runtime to perform: the `Peer` actor performs them in real time, the simulator
+//! in virtual time.
+
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use unicorn_p2p_rng::identity::SigningKey;
+use unicorn_p2p_rng::{
+    BeaconRecord, CachedVerifier, Change, DelayFunction, KeyRegistry, Membership, SchemeVerifier,
+    Signed, Unicorn, UnicornError, UnicornState,
+};
+
+use crate::config::Config;
+use crate::network::*;
+use crate::recovery::PeerCheckpoint;
+
+pub type PeerId = u32;
+
+/// Delay function shared by the peers running in the same process.
+pub type PeerVerifier<F = SchemeVerifier> = CachedVerifier<F>;
+
+/// Randomness beacon state machine driven by the peer.
+pub type PeerUnicorn<F = SchemeVerifier> =
+    Unicorn<PeerId, Commitment, VdfResult, Sha256, PeerVerifier<F>>;
+
+/// Randomness agreed on by the peer, or the reason it failed to agree.
+pub type Outcome = Result<Vec<u8>, UnicornError>;
+
+/// Defines possible states of the peer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PeerState {
+    Idle,
+    Connected,
+    Commit,
+    Reveal,
+    DoingVdf,
+    /// Waiting for a designated evaluator to calculate VDF.
+    AwaitingVdf,
+    VerifyingVdf,
+}
+
+impl PeerState {
+    /// Identifier of the state in checkpoints.
+    pub fn id(self) -> u8 {
+        match self {
+            PeerState::Idle => 1,
+            PeerState::Connected => 2,
+            PeerState::Commit => 3,
+            PeerState::Reveal => 4,
+            PeerState::DoingVdf => 5,
+            PeerState::AwaitingVdf => 6,
+            PeerState::VerifyingVdf => 7,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(PeerState::Idle),
+            2 => Some(PeerState::Connected),
+            3 => Some(PeerState::Commit),
+            4 => Some(PeerState::Reveal),
+            5 => Some(PeerState::DoingVdf),
+            6 => Some(PeerState::AwaitingVdf),
+            7 => Some(PeerState::VerifyingVdf),
+            _ => None,
+        }
+    }
+}
+
+/// Timers the peer schedules for itself.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Timer {
+    /// Time to create and send the commitment.
+    Commit,
+    CommitmentsRoundFinished,
+    RevealsRoundFinished,
+    VdfGatheringFinished,
+    /// Designated evaluators before this peer didn't deliver a valid VDF result in time.
+    EvaluatorTimedOut,
+}
+
+/// Effect the runtime should perform on behalf of the peer.
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Send the message to every peer, including the sender itself.
+    Broadcast(NetworkMessage),
+    /// Call `PeerProtocol::on_timer` after the delay.
+    Schedule(Duration, Timer),
+    /// Calculate VDF for the seed and pass the result to `PeerProtocol::on_vdf_solved`.
+    SolveVdf(Vec<u8>),
+    /// VDF result is not needed anymore, stop calculating it.
+    CancelVdf,
+    /// The round is finalized, its record may be stored.
+    Record(BeaconRecord<PeerId>),
+    /// The peer gave up, nothing else should be delivered to it.
+    Stop,
+}
+
+/// Describes single independent peer in the network.
+///
+/// Randomness is derived from the delay function `F`, the VDF of the `vdf` crate by default.
+pub struct PeerProtocol<F: DelayFunction = SchemeVerifier> {
+    /// ID of this peer.
+    pub id: PeerId,
+
+    /// Peers taking part in the current round and the ones before.
+    pub membership: Membership<PeerId>,
+
+    /// Key this peer signs its messages with.
+    pub key: SigningKey,
+
+    /// Public keys of all known peers.
+    pub keys: KeyRegistry<PeerId>,
+
+    /// Timeouts and VDF parameters.
+    pub config: Config,
+
+    /// Current state of the peer.
+    pub state: PeerState,
+
+    /// Round the peer is in, every restart begins a new one.
+    pub round: u64,
+
+    /// Secret seed share of this peer in the current round.
+    pub secret: Option<[u8; 32]>,
+
+    /// Commitments, reveals and VDF results collected in the current round.
+    pub unicorn: PeerUnicorn<F>,
+
+    /// Reveals that arrived before this peer finished its commitment round.
+    pub early_reveals: Vec<Reveal>,
+
+    /// VDF results that arrived before this peer created a seed.
+    pub early_vdf_results: Vec<VdfResult>,
+
+    /// Messages of the next round that arrived before this peer restarted.
+    pub next_round_messages: Vec<NetworkMessage>,
+
+    /// Peers that withheld their reveals in the round this peer restarted from, their
+    /// commitments aren't accepted in the current round.
+    pub withheld: BTreeSet<PeerId>,
+
+    /// Set once the peer finished the round.
+    pub outcome: Option<Outcome>,
+
+    /// Prints progress of the peer.
+    pub verbose: bool,
+
+    verifier: PeerVerifier<F>,
+    rng: ChaCha20Rng,
+}
+
+macro_rules! log {
+    ($peer:expr, $($arg:tt)*) => {
+        if $peer.verbose {
+            println!($($arg)*);
+        }
+    };
+}
+
+impl<F: DelayFunction + Clone> PeerProtocol<F> {
+    /// Creates a peer evaluating the `delay` function, normally `config.vdf_verifier()`.
+    ///
+    /// Peers `0..num_peers` take part from the start unless `config.members` tells otherwise.
+    pub fn new(
+        id: PeerId,
+        num_peers: u32,
+        key: SigningKey,
+        keys: KeyRegistry<PeerId>,
+        config: Config,
+        delay: F,
+    ) -> Self {
+        let verifier = CachedVerifier::new(delay);
+        let membership = Membership::new(
+            config
+                .members
+                .clone()
+                .unwrap_or_else(|| (0..num_peers).collect()),
+        )
+        .with_policy(config.threshold);
+
+        PeerProtocol {
+            id,
+            unicorn: Self::new_unicorn(&membership, &verifier, &keys, &config, None),
+            membership,
+            key,
+            keys,
+            config,
+            verifier,
+
+            state: PeerState::Idle,
+            round: 0,
+            secret: None,
+            early_reveals: vec![],
+            early_vdf_results: vec![],
+            next_round_messages: vec![],
+            withheld: BTreeSet::new(),
+            outcome: None,
+            verbose: true,
+            rng: ChaCha20Rng::from_entropy(),
+        }
+    }
+
+    /// Draws secrets and delays from the given generator instead of the OS entropy.
+    pub fn with_rng(mut self, rng: ChaCha20Rng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Shares cache of verified VDF results with other peers.
+    pub fn with_verifier(mut self, verifier: PeerVerifier<F>) -> Self {
+        self.verifier = verifier;
+        self.unicorn = self.next_unicorn();
+        self
+    }
+
+    /// Carries on after the `last` finalized round: starts with the round following it,
+    /// with the members it recorded, and mixes its randomness into the seed.
+    ///
+    /// Fails if the `last` round is the last one there can be.
+    pub fn with_last_round(mut self, last: &BeaconRecord<PeerId>) -> Result<Self, String> {
+        self.round = last
+            .round
+            .checked_add(1)
+            .ok_or_else(|| format!("no round can follow round {}", last.round))?;
+
+        // Changes that took effect before the node went down are in the record, not the config
+        let members = match &last.members {
+            Some(members) => members.iter().copied().collect(),
+            None => self.membership.members().clone(),
+        };
+        self.membership =
+            Membership::starting_at(self.round, members).with_policy(self.config.threshold);
+        self.unicorn = Self::new_unicorn(
+            &self.membership,
+            &self.verifier,
+            &self.keys,
+            &self.config,
+            Some(last.randomness.clone()),
+        );
+        Ok(self)
+    }
+
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Delay function the peer evaluates and checks results of others with.
+    pub fn delay(&self) -> &PeerVerifier<F> {
+        &self.verifier
+    }
+
+    fn new_unicorn(
+        membership: &Membership<PeerId>,
+        verifier: &PeerVerifier<F>,
+        keys: &KeyRegistry<PeerId>,
+        config: &Config,
+        previous_randomness: Option<Vec<u8>>,
+    ) -> PeerUnicorn<F> {
+        // Threshold is decided by the policy of the membership from the start
+        let unicorn = PeerUnicorn::new(1, verifier.clone())
+            .with_policy(*membership.policy())
+            .with_keys(keys.clone())
+            .with_members(membership.members().iter().cloned());
+
+        let unicorn = match config.weights() {
+            Some(weights) => unicorn.with_weights(weights),
+            None => unicorn,
+        };
+
+        match previous_randomness {
+            Some(randomness) => unicorn.with_previous_randomness(randomness),
+            None => unicorn,
+        }
+    }
+
+    /// Round of the current members following the same finalized round as the current one.
+    fn next_unicorn(&self) -> PeerUnicorn<F> {
+        Self::new_unicorn(
+            &self.membership,
+            &self.verifier,
+            &self.keys,
+            &self.config,
+            self.unicorn.previous_randomness(),
+        )
+    }
+
+    /// The peer joined the network.
+    pub fn start(&mut self) -> Vec<Action> {
+        self.state = PeerState::Connected;
+
+        let mut actions = vec![];
+        if !self.membership.contains(&self.id) {
+            log!(
+                self,
+                "[membership] Peer #{} joins after round {}",
+                self.id,
+                self.round
+            );
+
+            self.membership.schedule_next(self.id, Change::Join);
+            let join = MembershipChange::new(self.id, self.round, Change::Join, &self.key);
+            actions.push(Action::Broadcast(join.into()));
+        }
+
+        actions.extend(self.create_commitment_after_delay());
+        actions
+    }
+
+    /// State of the current round to carry on with after a restart, see `restore`.
+    ///
+    /// Progress of the VDF calculation is up to the runtime performing it.
+    pub fn checkpoint(&self) -> PeerCheckpoint {
+        PeerCheckpoint {
+            round: self.round,
+            state: self.state,
+            secret: self.secret,
+            members: self.membership.members().iter().cloned().collect(),
+            pending: self
+                .membership
+                .pending()
+                .map(|(round, id, change)| (round, *id, change))
+                .collect(),
+            unicorn: self.unicorn.checkpoint(),
+            early_reveals: self.early_reveals.clone(),
+            early_vdf_results: self.early_vdf_results.clone(),
+            next_round_messages: self.next_round_messages.clone(),
+            vdf: None,
+        }
+    }
+
+    /// Continues the round of the `checkpoint` instead of starting from scratch,
+    /// `resume` takes the place of `start` then.
+    ///
+    /// Messages of the checkpoint are checked once again, the peer is left intact if
+    /// any of them is rejected.
+    pub fn restore(&mut self, checkpoint: PeerCheckpoint) -> Result<(), UnicornError> {
+        let mut membership = Membership::starting_at(checkpoint.round, checkpoint.members)
+            .with_policy(self.config.threshold);
+        for (round, id, change) in checkpoint.pending {
+            membership
+                .schedule(round, id, change)
+                .map_err(|_| UnicornError::InvalidCheckpoint)?;
+        }
+
+        let mut unicorn =
+            Self::new_unicorn(&membership, &self.verifier, &self.keys, &self.config, None);
+        unicorn.restore(checkpoint.unicorn)?;
+
+        self.outcome = unicorn.randomness().map(Ok);
+        self.unicorn = unicorn;
+        self.membership = membership;
+        self.round = checkpoint.round;
+        self.state = checkpoint.state;
+        self.secret = checkpoint.secret;
+        self.early_reveals = checkpoint.early_reveals;
+        self.early_vdf_results = checkpoint.early_vdf_results;
+        self.next_round_messages = checkpoint.next_round_messages;
+
+        Ok(())
+    }
+
+    /// The restored peer joined the network again.
+    ///
+    /// Timers didn't survive the restart, so the current step of the round gets its full
+    /// timeout once more. Own message of the step is sent again in case the peer went down
+    /// before it left.
+    pub fn resume(&mut self) -> Vec<Action> {
+        log!(
+            self,
+            "[recovery] Peer #{} resumes round {} in state {:?}",
+            self.id,
+            self.round,
+            self.state
+        );
+
+        let secret = match (self.state, self.secret) {
+            (PeerState::Idle, _) => return self.start(),
+            (_, Some(secret)) => secret,
+
+            // Delay before the commitment has passed while the peer was down
+            (_, None) => {
+                let mut actions = self.commit();
+                actions.push(Action::Schedule(
+                    Duration::new(self.config.commitments_round_timeout, 0),
+                    Timer::CommitmentsRoundFinished,
+                ));
+                return actions;
+            }
+        };
+
+        match self.unicorn.state() {
+            UnicornState::CollectingCommitments => {
+                let mut value = [0u8; 32];
+                value.copy_from_slice(&PeerUnicorn::<F>::commitment(&secret));
+
+                vec![
+                    Action::Broadcast(
+                        Commitment::new(self.id, self.round, value, &self.key).into(),
+                    ),
+                    Action::Schedule(
+                        Duration::new(self.config.commitments_round_timeout, 0),
+                        Timer::CommitmentsRoundFinished,
+                    ),
+                ]
+            }
+
+            UnicornState::CollectingReveals => vec![
+                Action::Broadcast(Reveal::new(self.id, self.round, secret, &self.key).into()),
+                Action::Schedule(
+                    Duration::new(self.config.reveals_round_timeout, 0),
+                    Timer::RevealsRoundFinished,
+                ),
+            ],
+
+            // Own VDF result might not have made it anywhere, not even to this peer
+            UnicornState::SeedReady
+                if self.state == PeerState::VerifyingVdf
+                    && self.unicorn.vdf_result(&self.id).is_some() =>
+            {
+                vec![Action::Schedule(
+                    Duration::new(self.config.vdf_gathering_timeout, 0),
+                    Timer::VdfGatheringFinished,
+                )]
+            }
+            UnicornState::SeedReady => self.calculate_vdf(),
+
+            UnicornState::RandomnessReady => self
+                .unicorn
+                .record(self.round)
+                .map(Action::Record)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// The peer leaves for good, others stop counting on it from the next round on.
+    pub fn leave(&mut self) -> Vec<Action> {
+        log!(
+            self,
+            "[membership] Peer #{} leaves after round {}",
+            self.id,
+            self.round
+        );
+
+        let mut actions = vec![];
+        if self.state == PeerState::DoingVdf {
+            actions.push(Action::CancelVdf);
+        }
+
+        let leave = MembershipChange::new(self.id, self.round, Change::Leave, &self.key);
+        actions.push(Action::Broadcast(leave.into()));
+        actions.push(Action::Stop);
+
+        actions
+    }
+
+    pub fn on_timer(&mut self, timer: Timer) -> Vec<Action> {
+        match timer {
+            Timer::Commit => self.commit(),
+            Timer::CommitmentsRoundFinished => self.commitments_round_finished(),
+            Timer::RevealsRoundFinished => self.reveals_round_finished(),
+            Timer::VdfGatheringFinished => self.vdf_gathering_finished(),
+            Timer::EvaluatorTimedOut => self.evaluator_timed_out(),
+        }
+    }
+
+    pub fn on_message(&mut self, msg: NetworkMessage) -> Vec<Action> {
+        // Peers that restarted a bit earlier are one round ahead, anything else is stale or bogus
+        if self.round.checked_add(1) == Some(msg.round()) {
+            if self.authenticated_message(&msg) {
+                self.buffer_next_round(msg);
+            }
+            return vec![];
+        }
+
+        if msg.round() != self.round {
+            log!(
+                self,
+                "[network] Peer #{} dropped message of round {} from #{} in round {}",
+                self.id,
+                msg.round(),
+                msg.id_from(),
+                self.round
+            );
+            return vec![];
+        }
+
+        match msg {
+            NetworkMessage::Commitment(msg) => {
+                if self.authenticated("commitment", msg.id_from, &msg) {
+                    self.add_commitment(msg);
+                }
+            }
+
+            NetworkMessage::Reveal(msg) => {
+                if self.authenticated("reveal", msg.id_from, &msg) {
+                    self.add_reveal(msg);
+                }
+            }
+
+            NetworkMessage::VdfResult(msg) => {
+                if self.authenticated("VDF result", msg.id_from, &msg) {
+                    return self.add_vdf_result(msg);
+                }
+            }
+
+            // Membership changes take effect at the round after the one they were sent in
+            NetworkMessage::Membership(msg) => {
+                if self.authenticated("membership change", msg.id_from, &msg) {
+                    self.change_membership(msg);
+                }
+            }
+        }
+
+        vec![]
+    }
+
+    pub fn on_vdf_solved(&mut self, seed: Vec<u8>, result: Vec<u8>) -> Vec<Action> {
+        // Round was restarted or a valid result was received while VDF was being calculated
+        if self.state != PeerState::DoingVdf || self.unicorn.seed().as_ref() != Some(&seed) {
+            return vec![];
+        }
+
+        self.send_vdf_result(seed, result)
+    }
+
+    fn send_vdf_result(&mut self, seed: Vec<u8>, result: Vec<u8>) -> Vec<Action> {
+        let vdf_result = VdfResult::new(
+            self.id,
+            self.round,
+            self.config.vdf_scheme,
+            seed,
+            result,
+            &self.key,
+        );
+
+        self.state = PeerState::VerifyingVdf;
+
+        vec![
+            Action::Broadcast(vdf_result.into()),
+            Action::Schedule(
+                Duration::new(self.config.vdf_gathering_timeout, 0),
+                Timer::VdfGatheringFinished,
+            ),
+        ]
+    }
+
+    fn create_commitment_after_delay(&mut self) -> Vec<Action> {
+        log!(
+            self,
+            "[commitment round] Peer #{} is creating a commitment",
+            self.id
+        );
+
+        let delay = self.config.commitments_delay_min + self.rng.next_u64() % 5;
+
+        vec![
+            Action::Schedule(Duration::new(delay, 0), Timer::Commit),
+            Action::Schedule(
+                Duration::new(self.config.commitments_round_timeout, 0),
+                Timer::CommitmentsRoundFinished,
+            ),
+        ]
+    }
+
+    fn commit(&mut self) -> Vec<Action> {
+        let mut secret = [0u8; 32];
+        self.rng.fill_bytes(&mut secret);
+
+        let mut value = [0u8; 32];
+        value.copy_from_slice(&PeerUnicorn::<F>::commitment(&secret));
+
+        let commitment = Commitment::new(self.id, self.round, value, &self.key);
+
+        self.secret = Some(secret);
+        self.state = PeerState::Commit;
+
+        vec![Action::Broadcast(commitment.into())]
+    }
+
+    fn restart_round(&mut self) -> Vec<Action> {
+        let mut actions = vec![];
+        if self.state == PeerState::DoingVdf {
+            actions.push(Action::CancelVdf);
+        }
+
+        // Otherwise a peer committing to a value it never reveals would abort every round
+        self.withheld = self.unicorn.missing_reveals().into_iter().collect();
+        if !self.withheld.is_empty() {
+            log!(
+                self,
+                "[reveal round] Peer #{} ignores peers {:?} that withheld their reveals",
+                self.id,
+                self.withheld
+            );
+        }
+
+        self.round += 1;
+        self.membership.advance();
+        if let Err(e) = self.membership.threshold() {
+            log!(
+                self,
+                "[membership] Peer #{} can't finish round {} with {} members, err: {:?}",
+                self.id,
+                self.round,
+                self.membership.members().len(),
+                e
+            );
+        }
+        self.secret = None;
+        self.unicorn = self.next_unicorn();
+        self.early_reveals.clear();
+        self.early_vdf_results.clear();
+
+        actions.extend(self.create_commitment_after_delay());
+
+        // Messages other peers sent after they restarted a bit earlier
+        for msg in std::mem::take(&mut self.next_round_messages) {
+            actions.extend(self.on_message(msg));
+        }
+
+        actions
+    }
+
+    fn commitments_round_finished(&mut self) -> Vec<Action> {
+        // Commitment round finished but actor wasn't commit,
+        // something went wrong and actor is aboring
+        let secret = match (self.state, self.secret) {
+            (PeerState::Commit, Some(secret)) => secret,
+
+            _ => {
+                log!(self, "[commitment round] Peer {} wasn't commit", self.id);
+
+                return vec![Action::Stop];
+            }
+        };
+
+        // If we collected enough commitments for the threshold we can reveal our secret
+        match self.unicorn.finalize_commitments() {
+            Ok(()) => {
+                log!(
+                    self,
+                    "[reveal round] Peer #{} is revealing its secret {}",
+                    self.id,
+                    hex::encode(secret)
+                );
+
+                self.state = PeerState::Reveal;
+
+                // Timeouts of other peers might have fired a bit earlier
+                for reveal in std::mem::take(&mut self.early_reveals) {
+                    self.add_reveal(reveal);
+                }
+
+                vec![
+                    Action::Broadcast(Reveal::new(self.id, self.round, secret, &self.key).into()),
+                    Action::Schedule(
+                        Duration::new(self.config.reveals_round_timeout, 0),
+                        Timer::RevealsRoundFinished,
+                    ),
+                ]
+            }
+
+            Err(e) => {
+                log!(
+                    self,
+                    "[commitment round] #{}: not enough commitments collected ({:?}), restarting",
+                    self.id,
+                    e
+                );
+                self.restart_round()
+            }
+        }
+    }
+
+    fn reveals_round_finished(&mut self) -> Vec<Action> {
+        // Reveals are combined into a seed in order of peer IDs, so the seed doesn't depend on
+        // time of arrival of particular reveal to the particular peer.
+        match self.unicorn.finalize_seed() {
+            Ok(()) => {
+                log!(
+                    self,
+                    "[reveal round] #{}: seed created: {}",
+                    self.id,
+                    hex::encode(self.unicorn.seed().unwrap_or_default())
+                );
+
+                let mut actions = self.calculate_vdf();
+                for vdf_result in std::mem::take(&mut self.early_vdf_results) {
+                    actions.extend(self.add_vdf_result(vdf_result));
+                }
+
+                actions
+            }
+
+            Err(e) => {
+                log!(
+                    self,
+                    "[reveal round] #{}: not every commitment was revealed ({:?}), restarting",
+                    self.id,
+                    e
+                );
+                self.restart_round()
+            }
+        }
+    }
+
+    fn calculate_vdf(&mut self) -> Vec<Action> {
+        let seed = match self.unicorn.seed() {
+            Some(seed) => seed,
+            None => {
+                log!(self, "[error] Peer #{} didn't generated a seed", self.id);
+                return vec![];
+            }
+        };
+
+        let slot = match self.config.evaluators {
+            Some(evaluators) => evaluation_order(&seed, self.membership.members())
+                .iter()
+                .position(|id| *id == self.id)
+                .map_or(0, |position| position as u32 / evaluators),
+            None => 0,
+        };
+
+        if slot == 0 {
+            return self.solve_vdf(seed);
+        }
+
+        log!(
+            self,
+            "[vdf round] Peer #{} is waiting for designated evaluators",
+            self.id
+        );
+
+        self.state = PeerState::AwaitingVdf;
+
+        // Evaluators of every slot get the same time to deliver before the next ones start
+        let timeout = slot as u64 * self.config.evaluator_timeout;
+        vec![Action::Schedule(
+            Duration::new(timeout, 0),
+            Timer::EvaluatorTimedOut,
+        )]
+    }
+
+    fn solve_vdf(&mut self, seed: Vec<u8>) -> Vec<Action> {
+        log!(self, "[vdf round] Peer #{} is calculating VDF...", self.id);
+
+        self.state = PeerState::DoingVdf;
+
+        vec![Action::SolveVdf(seed)]
+    }
+
+    fn evaluator_timed_out(&mut self) -> Vec<Action> {
+        match (self.state, self.unicorn.seed()) {
+            (PeerState::AwaitingVdf, Some(seed)) => {
+                log!(
+                    self,
+                    "[vdf round] Peer #{} got no VDF result from designated evaluators",
+                    self.id
+                );
+
+                self.solve_vdf(seed)
+            }
+
+            _ => vec![],
+        }
+    }
+
+    fn vdf_gathering_finished(&mut self) -> Vec<Action> {
+        self.state = PeerState::VerifyingVdf;
+
+        // VDF results are verified as they arrive, so only the agreement is left to check
+        match self.unicorn.finalize_vdf_result() {
+            Ok(()) => {
+                log!(self, "[SUCCESS] Peer #{} thinks that a quorum of peers agreed on: {} as next random number", self.id, hex::encode(self.unicorn.randomness().unwrap_or_default()));
+
+                if let Some(quorum) = self.unicorn.quorum().filter(|q| !q.dissenters.is_empty()) {
+                    log!(
+                        self,
+                        "[vdf round] Peer #{} saw peers {:?} diverge from the majority",
+                        self.id,
+                        quorum.dissenters
+                    );
+                }
+
+                self.outcome = self.unicorn.randomness().map(Ok);
+
+                self.unicorn
+                    .record(self.round)
+                    .map(Action::Record)
+                    .into_iter()
+                    .collect()
+            }
+
+            Err(e) => {
+                log!(self, "[FAILURE] Peer #{} thinks that there's not enough evidence to think that any valid number are possible to obtain ({:?}).", self.id, e);
+
+                self.outcome = Some(Err(e));
+
+                vec![]
+            }
+        }
+    }
+
+    /// Drops messages that aren't signed by the peer they claim to come from.
+    fn authenticated<M: Signed>(&self, kind: &str, id_from: PeerId, msg: &M) -> bool {
+        match self.keys.verify(&id_from, msg) {
+            Ok(()) => true,
+
+            Err(e) => {
+                log!(
+                    self,
+                    "[network] Peer #{} rejected {} claiming to be from #{}: {:?}",
+                    self.id,
+                    kind,
+                    id_from,
+                    e
+                );
+
+                false
+            }
+        }
+    }
+
+    fn authenticated_message(&self, msg: &NetworkMessage) -> bool {
+        match msg {
+            NetworkMessage::Commitment(msg) => self.authenticated("commitment", msg.id_from, msg),
+            NetworkMessage::Reveal(msg) => self.authenticated("reveal", msg.id_from, msg),
+            NetworkMessage::VdfResult(msg) => self.authenticated("VDF result", msg.id_from, msg),
+            NetworkMessage::Membership(msg) => {
+                self.authenticated("membership change", msg.id_from, msg)
+            }
+        }
+    }
+
+    /// Keeps the message until the next round starts, only the first message of every kind
+    /// from every peer is kept.
+    fn buffer_next_round(&mut self, msg: NetworkMessage) {
+        let kind = std::mem::discriminant(&msg);
+        let buffered = self
+            .next_round_messages
+            .iter()
+            .any(|other| other.id_from() == msg.id_from() && std::mem::discriminant(other) == kind);
+
+        if buffered {
+            log!(
+                self,
+                "[network] Peer #{} dropped another message of round {} from #{}",
+                self.id,
+                msg.round(),
+                msg.id_from()
+            );
+            return;
+        }
+
+        self.next_round_messages.push(msg);
+    }
+
+    fn change_membership(&mut self, msg: MembershipChange) {
+        // Every peer applies the change in the same round, whatever round it is in itself
+        let round = match msg.round.checked_add(1) {
+            Some(round) => round,
+            None => {
+                log!(
+                    self,
+                    "[membership] Peer #{} dropped #{} {:?} after the last round",
+                    self.id,
+                    msg.id_from,
+                    msg.change
+                );
+                return;
+            }
+        };
+
+        match self.membership.schedule(round, msg.id_from, msg.change) {
+            Ok(()) => log!(
+                self,
+                "[membership] Peer #{} saw #{} {:?} from round {}",
+                self.id,
+                msg.id_from,
+                msg.change,
+                round
+            ),
+            Err(e) => log!(
+                self,
+                "[membership] Peer #{} rejected #{} {:?} from round {}, err: {:?}",
+                self.id,
+                msg.id_from,
+                msg.change,
+                round,
+                e
+            ),
+        }
+    }
+
+    fn add_commitment(&mut self, msg: Commitment) {
+        let id_from = msg.id_from;
+        let value = msg.value;
+
+        if self.withheld.contains(&id_from) {
+            log!(
+                self,
+                "[commitment round] Peer #{} ignored commitment from #{} that withheld its reveal",
+                self.id,
+                id_from
+            );
+            return;
+        }
+
+        match self.unicorn.add_seed_commitment(msg) {
+            Ok(()) => log!(
+                self,
+                "[commitment round] Peer #{} saved commitment {} from #{}",
+                self.id,
+                hex::encode(value),
+                id_from
+            ),
+
+            Err(e) => log!(
+                self,
+                "[commitment round] Peer #{} rejected commitment from #{}: {:?}",
+                self.id,
+                id_from,
+                e
+            ),
+        }
+    }
+
+    fn add_reveal(&mut self, msg: Reveal) {
+        let id_from = msg.id_from;
+
+        match self.unicorn.add_seed_reveal(msg) {
+            Ok(()) => log!(
+                self,
+                "[reveal round] Peer #{} saved reveal from #{}",
+                self.id,
+                id_from
+            ),
+
+            Err(UnicornError::NotCollectingReveals)
+                if self.unicorn.state() == UnicornState::CollectingCommitments =>
+            {
+                let buffered = self.early_reveals.iter().map(|r| r.id_from);
+                if self.keeps_early(buffered, id_from) {
+                    self.early_reveals.push(msg)
+                }
+            }
+
+            Err(e) => log!(
+                self,
+                "[reveal round] Peer #{} rejected reveal from #{}: {:?}",
+                self.id,
+                id_from,
+                e
+            ),
+        }
+    }
+
+    fn add_vdf_result(&mut self, msg: VdfResult) -> Vec<Action> {
+        let id_from = msg.id_from;
+
+        match self.unicorn.add_vdf_result(msg.clone()) {
+            Ok(()) => {
+                log!(
+                    self,
+                    "[vdf round] Peer #{} saved VDF result from #{}",
+                    self.id,
+                    id_from
+                );
+
+                // Verified result is as good as the own one, no need to calculate it anymore
+                let cancel = match self.state {
+                    PeerState::AwaitingVdf => Some(vec![]),
+                    PeerState::DoingVdf if self.config.evaluators.is_some() => {
+                        Some(vec![Action::CancelVdf])
+                    }
+                    _ => None,
+                };
+
+                if let Some(mut actions) = cancel {
+                    log!(
+                        self,
+                        "[vdf round] Peer #{} vouches for VDF result of #{}",
+                        self.id,
+                        id_from
+                    );
+
+                    actions.extend(self.send_vdf_result(msg.seed, msg.result));
+                    return actions;
+                }
+            }
+
+            Err(UnicornError::NotCollectingVdfResults)
+                if matches!(
+                    self.unicorn.state(),
+                    UnicornState::CollectingCommitments | UnicornState::CollectingReveals
+                ) =>
+            {
+                let buffered = self.early_vdf_results.iter().map(|r| r.id_from);
+                if self.keeps_early(buffered, id_from) {
+                    self.early_vdf_results.push(msg)
+                }
+            }
+
+            Err(e) => log!(
+                self,
+                "[vdf round] Peer #{} rejected VDF result from #{}: {:?}",
+                self.id,
+                id_from,
+                e
+            ),
+        }
+
+        vec![]
+    }
+
+    /// Whether a message that came too early is kept until the peer is ready for it.
+    ///
+    /// Only the first message of every member is, so nobody can fill the memory of the peer.
+    fn keeps_early<B: Iterator<Item = PeerId>>(&self, mut buffered: B, id_from: PeerId) -> bool {
+        if self.membership.contains(&id_from) && !buffered.any(|id| id == id_from) {
+            return true;
+        }
+
+        log!(
+            self,
+            "[network] Peer #{} dropped early message from #{}",
+            self.id,
+            id_from
+        );
+        false
+    }
+}
+
+/// Order in which peers take turns calculating VDF for the `seed`.
+///
+/// Peers are sorted by hash of the seed and their ID, so every seed picks different
+/// evaluators, and every peer knowing the seed picks the same ones.
+pub fn evaluation_order(seed: &[u8], members: &BTreeSet<PeerId>) -> Vec<PeerId> {
+    let mut order = members
+        .iter()
+        .map(|&id| {
+            let mut hash = Sha256::new();
+            hash.input(seed);
+            hash.input(id.to_be_bytes());
+
+            (hash.result(), id)
+        })
+        .collect::<Vec<_>>();
+
+    order.sort_unstable();
+    order.into_iter().map(|(_, id)| id).collect()
+}
@@ -0,0 +1,144 @@
+//! Selection of the value agreed on by a quorum of participants.
+
+use std::collections::BTreeMap;
+
+use crate::UnicornError;
+
+/// Value supported by a quorum, together with everyone who voted otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quorum<I> {
+    pub value: Vec<u8>,
+    /// Participants that voted for `value`, sorted by ID.
+    pub supporters: Vec<I>,
+    /// Participants that voted for any other value, sorted by ID.
+    pub dissenters: Vec<I>,
+}
+
+/// Picks the value that at least `threshold` participants voted for.
+///
+/// Result doesn't depend on the order of `votes`. Fails with `NotEnoughVdfResults` if no
+/// value reached the threshold and with `Equivocation` if more than one value did.
+pub fn select<I, V>(votes: V, threshold: usize) -> Result<Quorum<I>, UnicornError>
+where
+    I: Ord,
+    V: IntoIterator<Item = (I, Vec<u8>)>,
+{
+    select_weighted(votes, |_| 1, threshold as u64)
+}
+
+/// Picks the value that participants holding at least `threshold` of the stake voted for,
+/// every participant holds `weight` of it.
+///
+/// Fails like `select` does.
+pub fn select_weighted<I, V, W>(
+    votes: V,
+    weight: W,
+    threshold: u64,
+) -> Result<Quorum<I>, UnicornError>
+where
+    I: Ord,
+    V: IntoIterator<Item = (I, Vec<u8>)>,
+    W: Fn(&I) -> u64,
+{
+    let mut tally = BTreeMap::<Vec<u8>, Vec<I>>::new();
+    for (id, value) in votes {
+        tally.entry(value).or_default().push(id);
+    }
+
+    let mut quorums = tally
+        .iter()
+        .filter(|(_, voters)| {
+            let stake = voters.iter().map(&weight).fold(0u64, u64::saturating_add);
+            !voters.is_empty() && stake >= threshold
+        })
+        .map(|(value, _)| value.clone());
+
+    let value = match (quorums.next(), quorums.next()) {
+        (Some(value), None) => value,
+        (None, _) => return Err(UnicornError::NotEnoughVdfResults),
+        (Some(_), Some(_)) => return Err(UnicornError::Equivocation),
+    };
+
+    let mut supporters = tally.remove(&value).unwrap_or_default();
+    supporters.sort_unstable();
+
+    let mut dissenters = tally.into_values().flatten().collect::<Vec<_>>();
+    dissenters.sort_unstable();
+
+    Ok(Quorum {
+        value,
+        supporters,
+        dissenters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn votes(values: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(id, value)| (id as u64, vec![*value]))
+            .collect()
+    }
+
+    #[test]
+    pub fn test_most_supported_value() {
+        // Minority value comes first in every order the votes could be iterated
+        let quorum = select(votes(&[1, 2, 2, 2, 3]), 3).unwrap();
+
+        assert_eq!(quorum.value, vec![2]);
+        assert_eq!(quorum.supporters, vec![1, 2, 3]);
+        assert_eq!(quorum.dissenters, vec![0, 4]);
+
+        let mut reversed = votes(&[1, 2, 2, 2, 3]);
+        reversed.reverse();
+        assert_eq!(select(reversed, 3), Ok(quorum));
+    }
+
+    #[test]
+    pub fn test_no_quorum() {
+        assert_eq!(
+            select(votes(&[1, 1, 2, 2, 3]), 3),
+            Err(UnicornError::NotEnoughVdfResults)
+        );
+        assert_eq!(
+            select(votes(&[]), 0),
+            Err(UnicornError::NotEnoughVdfResults)
+        );
+    }
+
+    #[test]
+    pub fn test_weighted_votes() {
+        // Single participant 4 outweighs the other four
+        let weight = |id: &u64| if *id == 4 { 10 } else { 1 };
+
+        let quorum = select_weighted(votes(&[1, 1, 1, 1, 2]), weight, 9).unwrap();
+        assert_eq!(quorum.value, vec![2]);
+        assert_eq!(quorum.supporters, vec![4]);
+        assert_eq!(quorum.dissenters, vec![0, 1, 2, 3]);
+
+        assert_eq!(
+            select_weighted(votes(&[1, 1, 1, 2, 2]), weight, 12),
+            Err(UnicornError::NotEnoughVdfResults)
+        );
+        assert_eq!(
+            select_weighted(votes(&[1, 1, 1, 1, 2]), weight, 4),
+            Err(UnicornError::Equivocation)
+        );
+    }
+
+    #[test]
+    pub fn test_conflicting_quorums() {
+        assert_eq!(
+            select(votes(&[1, 1, 2, 2]), 2),
+            Err(UnicornError::Equivocation)
+        );
+        assert_eq!(
+            select(votes(&[1, 1, 1, 2, 2]), 2),
+            Err(UnicornError::Equivocation)
+        );
+    }
+}
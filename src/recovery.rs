@@ -0,0 +1,249 @@
+//! Round state of a peer saved to disk, so a restarted peer rejoins the round it was in.
+//!
+//! The file holds a single wire frame. It's replaced as a whole: a new checkpoint is written
+//! next to the old one and renamed over it, so a crash leaves either of them intact.
+//! The secret of the peer is stored in the clear, so only the owner may read the file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use unicorn_p2p_rng::wire::{self, get_list, get_option, put_list, put_option};
+use unicorn_p2p_rng::wire::{Reader, WireError, WireMessage, Writer};
+use unicorn_p2p_rng::{Change, DelayState, UnicornCheckpoint};
+
+use crate::network::*;
+use crate::protocol::{PeerId, PeerState};
+
+/// Everything a peer needs to carry on with its current round.
+#[derive(Debug, Clone)]
+pub struct PeerCheckpoint {
+    pub round: u64,
+    pub state: PeerState,
+    /// Secret behind the commitment of the peer, once it committed.
+    pub secret: Option<[u8; 32]>,
+    /// Members of the current round.
+    pub members: Vec<PeerId>,
+    /// Membership changes scheduled for the next rounds.
+    pub pending: Vec<(u64, PeerId, Change)>,
+    pub unicorn: UnicornCheckpoint<PeerId, Commitment, VdfResult>,
+    pub early_reveals: Vec<Reveal>,
+    pub early_vdf_results: Vec<VdfResult>,
+    pub next_round_messages: Vec<NetworkMessage>,
+    /// Partial VDF calculation for the seed of the round.
+    pub vdf: Option<DelayState>,
+}
+
+impl PeerCheckpoint {
+    /// Reads the checkpoint, `None` if there's no file.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+
+        wire::decode(&bytes)
+            .map(Some)
+            .map_err(|e| format!("invalid checkpoint {}: {:?}", path.display(), e))
+    }
+
+    /// Replaces the checkpoint stored in the file, returns once it's on disk.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+
+        // Leftover of a crash might be readable by others, it isn't reused
+        match fs::remove_file(&temp) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&temp)?;
+        file.write_all(&wire::encode(self))?;
+        file.sync_all()?;
+
+        fs::rename(&temp, path)?;
+        sync_dir(path)
+    }
+
+    /// Removes the checkpoint of a round that is over, if there's one.
+    pub fn remove(path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+            Ok(()) => sync_dir(path),
+        }
+    }
+}
+
+/// Makes the renaming or removal of the file in its directory survive a crash.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+impl WireMessage for PeerCheckpoint {
+    const KIND: u8 = 0x20;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u64(self.round);
+        writer.put_u8(self.state.id());
+        put_option(writer, &self.secret, |secret, w| w.put_fixed(secret));
+        put_list(writer, &self.members, |id, w| w.put_u32(*id));
+        put_list(writer, &self.pending, |(round, id, change), w| {
+            w.put_u64(*round);
+            w.put_u32(*id);
+            w.put_u8(change.id());
+        });
+        writer.put_bytes(&wire::encode(&self.unicorn));
+        put_list(writer, &self.early_reveals, |reveal, w| {
+            w.put_bytes(&wire::encode(reveal))
+        });
+        put_list(writer, &self.early_vdf_results, |result, w| {
+            w.put_bytes(&wire::encode(result))
+        });
+        put_list(writer, &self.next_round_messages, |msg, w| {
+            w.put_bytes(&msg.encode())
+        });
+        put_option(writer, &self.vdf, |vdf, w| {
+            w.put_u64(vdf.steps);
+            w.put_bytes(&vdf.state);
+        });
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        let round = reader.get_u64()?;
+        let state = reader.get_u8()?;
+
+        Ok(PeerCheckpoint {
+            round,
+            state: PeerState::from_id(state).ok_or(WireError::UnknownTag {
+                kind: Self::KIND,
+                tag: state,
+            })?,
+            secret: get_option(reader, |r| r.get_fixed())?,
+            members: get_list(reader, |r| r.get_u32())?,
+            pending: get_list(reader, |r| {
+                let round = r.get_u64()?;
+                let id = r.get_u32()?;
+                let change = r.get_u8()?;
+
+                Ok((
+                    round,
+                    id,
+                    Change::from_id(change).ok_or(WireError::UnknownTag {
+                        kind: Self::KIND,
+                        tag: change,
+                    })?,
+                ))
+            })?,
+            unicorn: wire::decode(&reader.get_bytes()?)?,
+            early_reveals: get_list(reader, |r| wire::decode(&r.get_bytes()?))?,
+            early_vdf_results: get_list(reader, |r| wire::decode(&r.get_bytes()?))?,
+            next_round_messages: get_list(reader, |r| {
+                let frame = r.get_bytes()?;
+                match wire::split_frame(&frame)? {
+                    Some(msg) if msg.len == frame.len() => {
+                        NetworkMessage::decode_payload(msg.kind, msg.payload)
+                    }
+                    Some(_) => Err(WireError::TrailingBytes),
+                    None => Err(WireError::Truncated),
+                }
+            })?,
+            vdf: get_option(reader, |r| {
+                Ok(DelayState {
+                    steps: r.get_u64()?,
+                    state: r.get_bytes()?,
+                })
+            })?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::protocol::{Action, PeerProtocol, Timer};
+    use std::{env, process};
+    use unicorn_p2p_rng::identity::SigningKey;
+    use unicorn_p2p_rng::{IteratedHash, KeyRegistry};
+
+    fn peer() -> PeerProtocol<IteratedHash> {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let mut keys = KeyRegistry::new();
+        keys.register(0, key.verifying_key());
+
+        PeerProtocol::new(0, 1, key, keys, Config::default(), IteratedHash::new(1_000))
+            .with_verbose(false)
+    }
+
+    #[test]
+    pub fn test_save_and_restore() {
+        let path = env::temp_dir().join(format!("unicorn-{}-checkpoint", process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(PeerCheckpoint::load(&path).unwrap().is_none());
+
+        // The only peer commits and gets its own commitment back
+        let mut original = peer();
+        original.start();
+        for action in original.on_timer(Timer::Commit) {
+            if let Action::Broadcast(msg) = action {
+                original.on_message(msg);
+            }
+        }
+
+        let mut checkpoint = original.checkpoint();
+        checkpoint.vdf = Some(DelayState {
+            steps: 10,
+            state: vec![1; 32],
+        });
+        checkpoint.save(&path).unwrap();
+
+        let loaded = PeerCheckpoint::load(&path).unwrap().unwrap();
+        assert_eq!(wire::encode(&loaded), wire::encode(&checkpoint));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut restored = peer();
+        restored.restore(loaded).unwrap();
+        assert_eq!(restored.state, PeerState::Commit);
+        assert_eq!(restored.secret, original.secret);
+        assert_eq!(
+            restored.unicorn.commitments(),
+            original.unicorn.commitments()
+        );
+
+        // Damaged file isn't mistaken for an empty one
+        fs::write(&path, b"garbage").unwrap();
+        assert!(PeerCheckpoint::load(&path).is_err());
+
+        PeerCheckpoint::remove(&path).unwrap();
+        assert!(PeerCheckpoint::load(&path).unwrap().is_none());
+        PeerCheckpoint::remove(&path).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use rand_chacha::ChaCha20Rng;
+use rand_core::{CryptoRng, Error, RngCore, SeedableRng};
+use sha2::{Digest, Sha256};
+
+/// Deterministic random number generator expanding the randomness of a finalized round.
+///
+/// Everyone who knows the randomness of the round draws the same sequence of numbers from it,
+/// so any number drawn can be verified by replaying the generator.
+#[derive(Debug, Clone)]
+pub struct BeaconRng(ChaCha20Rng);
+
+impl BeaconRng {
+    /// Creates generator seeded with SHA-256 of the round's randomness.
+    pub fn from_randomness(randomness: &[u8]) -> Self {
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&Sha256::digest(randomness));
+
+        Self::from_seed(seed)
+    }
+}
+
+impl RngCore for BeaconRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+impl SeedableRng for BeaconRng {
+    type Seed = [u8; 32];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        BeaconRng(ChaCha20Rng::from_seed(seed))
+    }
+}
+
+impl CryptoRng for BeaconRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_same_randomness_same_numbers() {
+        let mut rng = BeaconRng::from_randomness(&[1u8; 32]);
+        let mut other = BeaconRng::from_randomness(&[1u8; 32]);
+        let mut different = BeaconRng::from_randomness(&[2u8; 32]);
+
+        let numbers = (0..16).map(|_| rng.next_u64()).collect::<Vec<_>>();
+        assert_eq!(
+            numbers,
+            (0..16).map(|_| other.next_u64()).collect::<Vec<_>>()
+        );
+        assert_ne!(
+            numbers,
+            (0..16).map(|_| different.next_u64()).collect::<Vec<_>>()
+        );
+
+        // Cloned generator continues from the same position
+        let mut cloned = rng.clone();
+        assert_eq!(rng.next_u32(), cloned.next_u32());
+    }
+
+    #[test]
+    pub fn test_randomness_is_hashed_into_seed() {
+        let randomness = b"beacon output of arbitrary length";
+
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&Sha256::digest(randomness));
+
+        let mut rng = BeaconRng::from_randomness(randomness);
+        let mut seeded = BeaconRng::from_seed(seed);
+
+        let mut bytes = [0u8; 64];
+        let mut seeded_bytes = [0u8; 64];
+        rng.fill_bytes(&mut bytes);
+        seeded.fill_bytes(&mut seeded_bytes);
+
+        assert_eq!(bytes.to_vec(), seeded_bytes.to_vec());
+    }
+}
@@ -0,0 +1,832 @@
+//! Deterministic discrete-event simulation of the peer protocol.
+//!
+//! Peers run the same `PeerProtocol` as the actors do, but timers, message delivery and VDF
+//! calculation advance a virtual clock instead of waiting for the real one. Every random choice
+//! is drawn from a single seeded generator, so a run is fully reproducible from its seed.
+//!
+//! Messages travel according to the `NetworkModel`, which may delay, lose, duplicate them
+//! or cut peers off each other for a while. Byzantine peers tamper with the messages they send,
+//! the run is judged by the honest peers only.
+//!
+//! A crashed peer loses its timers, VDF calculation and messages sent to it while it's down.
+//! It restarts as a new `PeerProtocol` restored from the checkpoint the runtime would have
+//! saved after the last event it handled.
+
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Duration;
+
+use unicorn_p2p_rng::identity::SigningKey;
+use unicorn_p2p_rng::wire;
+use unicorn_p2p_rng::{CachedVerifier, DelayFunction, KeyRegistry, SchemeVerifier};
+
+use crate::byzantine::Adversary;
+use crate::config::{Behavior, Config};
+use crate::faults::NetworkModel;
+use crate::network::NetworkMessage;
+use crate::protocol::*;
+use crate::recovery::PeerCheckpoint;
+
+/// Something that happens to a peer at a moment of virtual time.
+enum EventKind {
+    Deliver(NetworkMessage),
+    Timer(Timer),
+    VdfSolved { seed: Vec<u8>, result: Vec<u8> },
+    Leave,
+    Crash { down: Duration },
+    Restart,
+}
+
+struct Event {
+    at: Duration,
+    /// Breaks ties between events scheduled at the same time in order of scheduling.
+    seq: u64,
+    peer: usize,
+    kind: EventKind,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    // Reversed, so `BinaryHeap` pops the earliest event first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// Outcome of a simulation run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Randomness agreed on by every peer, `None` for peers that didn't finish in time.
+    pub outcomes: Vec<(PeerId, Option<Outcome>)>,
+
+    /// Virtual time at which the last event happened.
+    pub elapsed: Duration,
+
+    /// Number of messages delivered to the peers.
+    pub delivered: u64,
+
+    /// Number of messages lost by the network.
+    pub dropped: u64,
+
+    /// Number of times peers calculated VDF.
+    pub evaluations: u64,
+
+    /// Peers that didn't follow the protocol.
+    pub byzantine: Vec<PeerId>,
+}
+
+impl Report {
+    fn honest_outcomes(&self) -> impl Iterator<Item = (PeerId, Option<&[u8]>)> + '_ {
+        self.outcomes
+            .iter()
+            .filter(move |(id, _)| !self.byzantine.contains(id))
+            .map(|(id, outcome)| match outcome {
+                Some(Ok(randomness)) => (*id, Some(randomness.as_slice())),
+                _ => (*id, None),
+            })
+    }
+
+    /// Randomness if all honest peers agreed on the same one.
+    pub fn agreed(&self) -> Option<&[u8]> {
+        let mut outcomes = self.honest_outcomes().map(|(_, randomness)| randomness);
+
+        let first = outcomes.next()??;
+        outcomes.all(|r| r == Some(first)).then_some(first)
+    }
+
+    /// Distinct randomness values honest peers agreed on, at most one unless safety was violated.
+    pub fn randomness(&self) -> Vec<&[u8]> {
+        let mut values = self
+            .honest_outcomes()
+            .filter_map(|(_, randomness)| randomness)
+            .collect::<Vec<_>>();
+
+        values.sort_unstable();
+        values.dedup();
+        values
+    }
+
+    /// Honest peers that agreed on some randomness.
+    pub fn succeeded(&self) -> Vec<PeerId> {
+        self.honest_outcomes()
+            .filter(|(_, randomness)| randomness.is_some())
+            .map(|(id, _)| id)
+            .collect()
+    }
+}
+
+pub struct Simulation<F: DelayFunction = SchemeVerifier> {
+    config: Config,
+    network: NetworkModel,
+    peers: Vec<PeerProtocol<F>>,
+    adversaries: Vec<Adversary>,
+    stopped: Vec<bool>,
+    keys: Vec<SigningKey>,
+    registry: KeyRegistry<PeerId>,
+    verbose: bool,
+    /// Checkpoints of the peers that are down.
+    down: Vec<Option<PeerCheckpoint>>,
+    /// Events a peer scheduled for itself up to this one died with it.
+    crashed: Vec<u64>,
+    queue: BinaryHeap<Event>,
+    now: Duration,
+    seq: u64,
+    delivered: u64,
+    dropped: u64,
+    evaluations: u64,
+    rng: ChaCha20Rng,
+    function: F,
+    delay: PeerVerifier<F>,
+    /// VDF results by seed, honest peers calculate the same VDF
+    solved: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Simulation {
+    /// Creates `config.num_peers` peers with keys and secrets derived from the `seed`.
+    pub fn new(config: &Config, seed: u64, verbose: bool) -> Self {
+        Simulation::with_delay(config, seed, verbose, config.vdf_verifier())
+    }
+}
+
+impl<F: DelayFunction + Clone> Simulation<F> {
+    /// Creates a simulation of peers evaluating the `delay` function instead of the configured VDF.
+    pub fn with_delay(config: &Config, seed: u64, verbose: bool, delay: F) -> Self {
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+
+        let keys = (0..config.num_peers)
+            .map(|_| SigningKey::generate(&mut rng))
+            .collect::<Vec<_>>();
+
+        let mut registry = KeyRegistry::new();
+        for (id, key) in keys.iter().enumerate() {
+            registry.register(id as PeerId, key.verifying_key());
+        }
+
+        // Every distinct VDF result is verified once for all peers
+        let verifier = CachedVerifier::new(delay.clone());
+
+        let adversaries = keys
+            .iter()
+            .enumerate()
+            .map(|(id, key)| {
+                let id = id as PeerId;
+                let behavior = config
+                    .simulation
+                    .adversaries
+                    .iter()
+                    .find(|adversary| adversary.peer == id)
+                    .map_or(Behavior::Honest, |adversary| adversary.behavior);
+
+                Adversary::new(behavior, id, config.num_peers, key.clone())
+            })
+            .collect();
+
+        let num_peers = keys.len();
+        let mut sim = Simulation {
+            config: config.clone(),
+            network: NetworkModel::new(&config.simulation),
+            peers: vec![],
+            adversaries,
+            stopped: vec![false; num_peers],
+            keys,
+            registry,
+            verbose,
+            down: vec![None; num_peers],
+            crashed: vec![0; num_peers],
+            queue: BinaryHeap::new(),
+            now: Duration::default(),
+            seq: 0,
+            delivered: 0,
+            dropped: 0,
+            evaluations: 0,
+            rng,
+            function: delay,
+            delay: verifier,
+            solved: HashMap::new(),
+        };
+
+        sim.peers = (0..num_peers).map(|peer| sim.new_peer(peer)).collect();
+        sim
+    }
+
+    /// Peer as it is when its process starts.
+    fn new_peer(&mut self, peer: usize) -> PeerProtocol<F> {
+        let peer_rng = ChaCha20Rng::seed_from_u64(self.rng.next_u64());
+
+        PeerProtocol::new(
+            peer as PeerId,
+            self.config.num_peers,
+            self.keys[peer].clone(),
+            self.registry.clone(),
+            self.config.clone(),
+            self.function.clone(),
+        )
+        .with_rng(peer_rng)
+        .with_verifier(self.delay.clone())
+        .with_verbose(self.verbose)
+    }
+
+    /// Runs until every peer is done or the time limit is reached.
+    pub fn run(mut self) -> Report {
+        for peer in 0..self.peers.len() {
+            let actions = self.peers[peer].start();
+            self.perform(peer, actions);
+        }
+
+        for leave in self.config.simulation.leaves.clone() {
+            let at = Duration::from_millis(leave.at_ms);
+            self.schedule(at, leave.peer as usize, EventKind::Leave);
+        }
+
+        for crash in self.config.simulation.crashes.clone() {
+            let at = Duration::from_millis(crash.at_ms);
+            let down = Duration::from_millis(crash.down_ms);
+            self.schedule(at, crash.peer as usize, EventKind::Crash { down });
+        }
+
+        let time_limit = Duration::from_secs(self.config.simulation.time_limit);
+
+        while let Some(event) = self.queue.pop() {
+            if event.at > time_limit {
+                break;
+            }
+
+            self.now = event.at;
+            if self.stopped[event.peer] || !self.is_alive(&event) {
+                continue;
+            }
+
+            let peer = &mut self.peers[event.peer];
+            let actions = match event.kind {
+                EventKind::Deliver(msg) => {
+                    self.delivered += 1;
+                    self.adversaries[event.peer].observe(&msg);
+                    peer.on_message(msg)
+                }
+                EventKind::Timer(timer) => peer.on_timer(timer),
+                EventKind::VdfSolved { seed, result } => peer.on_vdf_solved(seed, result),
+                EventKind::Leave => peer.leave(),
+                EventKind::Crash { down } => {
+                    self.crash(event.peer, down);
+                    continue;
+                }
+                EventKind::Restart => self.restart(event.peer),
+            };
+
+            self.perform(event.peer, actions);
+        }
+
+        Report {
+            outcomes: self
+                .peers
+                .iter()
+                .map(|peer| (peer.id, peer.outcome.clone()))
+                .collect(),
+            elapsed: self.now,
+            delivered: self.delivered,
+            dropped: self.dropped,
+            evaluations: self.evaluations,
+            byzantine: self
+                .adversaries
+                .iter()
+                .enumerate()
+                .filter(|(_, adversary)| adversary.behavior != Behavior::Honest)
+                .map(|(id, _)| id as PeerId)
+                .collect(),
+        }
+    }
+
+    /// Whether the event still reaches the peer: nothing does while it's down,
+    /// and timers or VDF calculations don't outlive a crash.
+    fn is_alive(&self, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Restart => true,
+            _ if self.down[event.peer].is_some() => false,
+            EventKind::Timer(_) | EventKind::VdfSolved { .. } => {
+                event.seq > self.crashed[event.peer]
+            }
+            _ => true,
+        }
+    }
+
+    fn crash(&mut self, peer: usize, down: Duration) {
+        // Checkpoint goes through the same encoding it's stored on disk with
+        let checkpoint = wire::encode(&self.peers[peer].checkpoint());
+        let checkpoint = wire::decode(&checkpoint).expect("checkpoint doesn't decode");
+
+        self.down[peer] = Some(checkpoint);
+        self.crashed[peer] = self.seq;
+        self.schedule(down, peer, EventKind::Restart);
+    }
+
+    fn restart(&mut self, peer: usize) -> Vec<Action> {
+        let checkpoint = self.down[peer].take().expect("peer isn't down");
+
+        let mut restored = self.new_peer(peer);
+        restored
+            .restore(checkpoint)
+            .expect("checkpoint of the peer is rejected");
+
+        self.peers[peer] = restored;
+        self.peers[peer].resume()
+    }
+
+    fn perform(&mut self, peer: usize, actions: Vec<Action>) {
+        let sim = self.config.simulation.clone();
+
+        for action in actions {
+            match action {
+                Action::Broadcast(msg) => {
+                    for (to, msg) in self.adversaries[peer].broadcast(msg) {
+                        let deliveries =
+                            self.network
+                                .deliveries(peer as PeerId, to, self.now, &mut self.rng);
+
+                        if deliveries.is_empty() {
+                            self.dropped += 1;
+                        }
+
+                        for latency in deliveries {
+                            let msg = EventKind::Deliver(msg.clone());
+                            self.schedule(latency, to as usize, msg);
+                        }
+                    }
+                }
+
+                Action::Schedule(delay, timer) => {
+                    self.schedule(delay, peer, EventKind::Timer(timer));
+                }
+
+                Action::SolveVdf(seed) => {
+                    self.evaluations += 1;
+
+                    let delay = &self.delay;
+                    let result = self
+                        .solved
+                        .entry(seed.clone())
+                        .or_insert_with(|| delay.solve(&seed).unwrap())
+                        .clone();
+
+                    let time = self.random_ms(sim.min_vdf_time_ms, sim.max_vdf_time_ms);
+                    self.schedule(time, peer, EventKind::VdfSolved { seed, result });
+                }
+
+                // The peer ignores results it doesn't wait for anymore
+                Action::CancelVdf | Action::Record(_) => {}
+
+                Action::Stop => self.stopped[peer] = true,
+            }
+        }
+    }
+
+    fn schedule(&mut self, delay: Duration, peer: usize, kind: EventKind) {
+        self.seq += 1;
+        self.queue.push(Event {
+            at: self.now + delay,
+            seq: self.seq,
+            peer,
+            kind,
+        });
+    }
+
+    fn random_ms(&mut self, min: u64, max: u64) -> Duration {
+        let spread = max.saturating_sub(min);
+
+        Duration::from_millis(min + self.rng.next_u64() % (spread + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        AdversaryConfig, CrashConfig, Latency, LeaveConfig, PartitionConfig, SimulationConfig,
+        WeightConfig,
+    };
+    use crate::network::{Commitment, MembershipChange, Reveal};
+    use unicorn_p2p_rng::{BeaconRecord, Change, IteratedHash, ThresholdPolicy, VdfScheme};
+
+    fn config(num_peers: u32) -> Config {
+        Config {
+            num_peers,
+            vdf_difficulty: 1_000,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    pub fn test_peers_agree() {
+        let report = Simulation::new(&config(20), 1, false).run();
+
+        assert!(report.agreed().is_some(), "{:?}", report);
+        assert!(report.elapsed < Duration::from_secs(60));
+    }
+
+    #[test]
+    pub fn test_wesolowski() {
+        let mut config = config(7);
+        config.vdf_scheme = VdfScheme::Wesolowski;
+
+        let report = Simulation::new(&config, 1, false).run();
+
+        assert!(report.agreed().is_some(), "{:?}", report);
+    }
+
+    #[test]
+    pub fn test_custom_delay_function() {
+        let mut config = config(7);
+        config.simulation.adversaries = adversaries(Behavior::BogusVdf, &[2]);
+
+        let report = Simulation::with_delay(&config, 1, false, IteratedHash::new(1_000)).run();
+        let agreed = report.agreed().expect("peers didn't agree").to_vec();
+
+        // The same secrets give different randomness through a different function
+        let report = Simulation::new(&config, 1, false).run();
+        assert_ne!(report.agreed(), Some(agreed.as_slice()));
+    }
+
+    #[test]
+    pub fn test_designated_evaluator() {
+        let mut config = config(20);
+        config.evaluators = Some(1);
+
+        let report = Simulation::new(&config, 1, false).run();
+
+        assert!(report.agreed().is_some(), "{:?}", report);
+        assert_eq!(report.evaluations, 1);
+    }
+
+    #[test]
+    pub fn test_evaluator_fallback() {
+        let mut config = config(7);
+        config.evaluators = Some(1);
+        config.simulation.adversaries = adversaries(Behavior::BogusVdf, &[1, 4]);
+
+        // Results of byzantine evaluators are rejected, the next ones take over after a timeout
+        let mut evaluations = vec![];
+        for seed in 0..5 {
+            let report = Simulation::new(&config, seed, false).run();
+
+            assert!(report.agreed().is_some(), "seed {}: {:?}", seed, report);
+            evaluations.push(report.evaluations);
+        }
+
+        assert!(evaluations.iter().any(|n| *n > 1), "{:?}", evaluations);
+        assert!(evaluations.iter().all(|n| *n < 7), "{:?}", evaluations);
+    }
+
+    #[test]
+    pub fn test_peers_leave() {
+        let mut config = config(7);
+        config.simulation.leaves = (4..7)
+            .map(|peer| LeaveConfig { peer, at_ms: 500 })
+            .collect();
+
+        // 4 of 7 peers are too few for the first round, but enough of 4 members in the next one
+        let report = Simulation::new(&config, 1, false).run();
+
+        assert_eq!(report.succeeded(), vec![0, 1, 2, 3]);
+        assert_eq!(report.randomness().len(), 1);
+    }
+
+    #[test]
+    pub fn test_peers_join() {
+        let mut config = config(7);
+        config.members = Some(vec![0, 1, 2, 3]);
+
+        // Members 0 and 1 are cut off for good, 2 of 4 members can't agree on their own
+        config.simulation.partitions = vec![PartitionConfig {
+            groups: vec![vec![0], vec![1]],
+            start_ms: 0,
+            heal_ms: None,
+        }];
+
+        let report = Simulation::new(&config, 1, false).run();
+
+        assert_eq!(report.succeeded(), vec![2, 3, 4, 5, 6]);
+        assert_eq!(report.randomness().len(), 1);
+    }
+
+    #[test]
+    pub fn test_membership_changes_apply_at_the_same_round() {
+        let mut sim = Simulation::new(&config(4), 1, false);
+
+        // Peer #1 is a round ahead of peer #0
+        sim.peers[1].round += 1;
+        sim.peers[1].membership.advance();
+
+        let leave = MembershipChange::new(3, 1, Change::Leave, &sim.keys[3]);
+        sim.peers[0].on_message(leave.into());
+        sim.peers[1].on_message(leave.into());
+
+        // Round 1 has already started for peer #1, it doesn't change after the fact
+        let late = MembershipChange::new(2, 0, Change::Leave, &sim.keys[2]);
+        sim.peers[0].on_message(late.into());
+        sim.peers[1].on_message(late.into());
+
+        let pending = |peer: &PeerProtocol<_>| {
+            let pending = peer.membership.pending();
+            pending
+                .map(|(round, id, _)| (round, *id))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pending(&sim.peers[0]), vec![(1, 2)]);
+        assert_eq!(pending(&sim.peers[1]), vec![(2, 3)]);
+
+        // Peer #0 sees the change of the next round once it gets there
+        sim.peers[0].round += 1;
+        sim.peers[0].membership.advance();
+        for msg in std::mem::take(&mut sim.peers[0].next_round_messages) {
+            sim.peers[0].on_message(msg);
+        }
+        assert_eq!(pending(&sim.peers[0]), vec![(2, 3)]);
+    }
+
+    #[test]
+    pub fn test_resume_after_last_round() {
+        let mut sim = Simulation::new(&config(4), 1, false);
+        let mut last = BeaconRecord {
+            round: 7,
+            previous_randomness: None,
+            members: Some(vec![0, 1, 3]),
+            weights: None,
+            commitments: vec![],
+            seed: vec![0; 32],
+            vdf_result: vec![],
+            randomness: vec![7; 32],
+        };
+
+        // Peer #2 left before the node went down, it doesn't come back with the config
+        let peer = sim.new_peer(0).with_last_round(&last).unwrap();
+        assert_eq!(peer.round, 8);
+        assert_eq!(
+            peer.membership
+                .members()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![0, 1, 3]
+        );
+        assert_eq!(peer.unicorn.previous_randomness(), Some(vec![7; 32]));
+
+        // Nothing follows the last round there can be
+        last.round = u64::MAX;
+        assert!(sim.new_peer(0).with_last_round(&last).is_err());
+    }
+
+    #[test]
+    pub fn test_membership_changes_of_other_rounds() {
+        let mut sim = Simulation::new(&config(4), 1, false);
+
+        // Changes announced for rounds far ahead aren't kept around
+        for round in 2..7 {
+            let leave = MembershipChange::new(3, round, Change::Leave, &sim.keys[3]);
+            sim.peers[0].on_message(leave.into());
+        }
+        assert_eq!(sim.peers[0].membership.pending().count(), 0);
+        assert!(sim.peers[0].next_round_messages.is_empty());
+
+        // Change announced in the last round has no round to take effect at
+        sim.peers[0].round = u64::MAX;
+        let leave = MembershipChange::new(3, u64::MAX, Change::Leave, &sim.keys[3]);
+        sim.peers[0].on_message(leave.into());
+        assert_eq!(sim.peers[0].membership.pending().count(), 0);
+    }
+
+    #[test]
+    pub fn test_crash_recovery() {
+        // Peer goes down while committing, revealing and calculating VDF in turn
+        for at_ms in [3_000, 8_000, 13_000].iter() {
+            let mut config = config(4);
+            config.simulation.crashes = vec![CrashConfig {
+                peer: 0,
+                at_ms: *at_ms,
+                down_ms: 1_000,
+            }];
+
+            for seed in 0..3 {
+                let report = Simulation::new(&config, seed, false).run();
+
+                assert!(
+                    report.agreed().is_some(),
+                    "crash at {} ms, seed {}: {:?}",
+                    at_ms,
+                    seed,
+                    report
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_stake_weighted_quorums() {
+        let mut config = config(6);
+        config.threshold = ThresholdPolicy::Stake {
+            numerator: 2,
+            denominator: 3,
+        };
+        config.weights = (0..6)
+            .map(|peer| WeightConfig {
+                peer,
+                weight: if peer < 2 { 10 } else { 1 },
+            })
+            .collect();
+
+        // Two peers holding 20 of 24 stake agree on their own, the other four can't
+        config.simulation.partitions = vec![PartitionConfig {
+            groups: vec![vec![0, 1]],
+            start_ms: 0,
+            heal_ms: None,
+        }];
+
+        let report = Simulation::new(&config, 1, false).run();
+
+        assert_eq!(report.succeeded(), vec![0, 1]);
+        assert_eq!(report.randomness().len(), 1);
+    }
+
+    #[test]
+    pub fn test_replay_from_seed() {
+        let run = |seed| Simulation::new(&config(7), seed, false).run();
+
+        let report = run(42);
+        assert_eq!(run(42), report);
+
+        // Secrets depend on the seed, so does the randomness
+        assert_ne!(run(43).agreed(), report.agreed());
+    }
+
+    #[test]
+    pub fn test_majority_partition() {
+        let mut config = config(6);
+        config.simulation.time_limit = 120;
+        config.simulation.partitions = vec![PartitionConfig {
+            groups: vec![vec![0, 1, 2, 3], vec![4, 5]],
+            start_ms: 0,
+            heal_ms: None,
+        }];
+
+        let report = Simulation::new(&config, 1, false).run();
+
+        // 4 of 6 peers are just enough for every threshold, 2 of 6 keep restarting
+        assert_eq!(report.succeeded(), vec![0, 1, 2, 3]);
+        assert_eq!(report.randomness().len(), 1);
+        assert!(report.dropped > 0);
+    }
+
+    #[test]
+    pub fn test_duplicated_messages() {
+        let mut config = config(6);
+        config.simulation.duplicate_rate = 1.0;
+
+        let report = Simulation::new(&config, 1, false).run();
+
+        assert!(report.agreed().is_some(), "{:?}", report);
+    }
+
+    #[test]
+    pub fn test_early_messages_are_bounded() {
+        let mut sim = Simulation::new(&config(4), 1, false);
+
+        // Peer #0 hasn't committed yet, reveals of the others wait for it
+        for value in 0..3 {
+            for id in 1..4 {
+                let reveal = Reveal::new(id, 0, [value; 32], &sim.keys[id as usize]);
+                sim.peers[0].on_message(reveal.into());
+            }
+        }
+
+        let buffered = sim.peers[0]
+            .early_reveals
+            .iter()
+            .map(|r| (r.id_from, r.value[0]));
+        assert_eq!(buffered.collect::<Vec<_>>(), vec![(1, 0), (2, 0), (3, 0)]);
+    }
+
+    #[test]
+    pub fn test_next_round_messages_are_bounded() {
+        let mut sim = Simulation::new(&config(4), 1, false);
+
+        for value in 0..3 {
+            for id in 1..4 {
+                let commitment = Commitment::new(id, 1, [value; 32], &sim.keys[id as usize]);
+                sim.peers[0].on_message(commitment.into());
+            }
+        }
+
+        // Nobody can sign on behalf of another peer to fill the buffer either
+        let spoofed = Commitment::new(2, 1, [9; 32], &sim.keys[1]);
+        sim.peers[0].on_message(spoofed.into());
+        let reveal = Reveal::new(1, 1, [0; 32], &sim.keys[1]);
+        sim.peers[0].on_message(reveal.into());
+
+        let buffered = sim.peers[0].next_round_messages.iter();
+        let buffered =
+            buffered.map(|msg| (msg.id_from(), matches!(msg, NetworkMessage::Reveal(_))));
+        assert_eq!(
+            buffered.collect::<Vec<_>>(),
+            vec![(1, false), (2, false), (3, false), (1, true)]
+        );
+    }
+
+    #[test]
+    pub fn test_faulty_network_is_safe() {
+        let mut config = config(6);
+        config.simulation = SimulationConfig {
+            latency: Latency::Exponential {
+                min_ms: 10,
+                mean_ms: 300,
+            },
+            drop_rate: 0.05,
+            duplicate_rate: 0.1,
+            partitions: vec![PartitionConfig {
+                groups: vec![vec![0, 1, 2], vec![3, 4, 5]],
+                start_ms: 0,
+                heal_ms: Some(20_000),
+            }],
+            time_limit: 120,
+            ..SimulationConfig::default()
+        };
+
+        // Lost reveals make peers compute different seeds, then some or all of them fail,
+        // but no two peers may ever finish with different randomness
+        for seed in 0..5 {
+            let report = Simulation::new(&config, seed, false).run();
+
+            assert!(
+                report.randomness().len() <= 1,
+                "seed {}: {:?}",
+                seed,
+                report
+            );
+        }
+    }
+
+    fn adversaries(behavior: Behavior, peers: &[PeerId]) -> Vec<AdversaryConfig> {
+        peers
+            .iter()
+            .map(|peer| AdversaryConfig {
+                peer: *peer,
+                behavior,
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_byzantine_minority() {
+        let behaviors = [
+            Behavior::WithholdCommitment,
+            Behavior::Equivocate,
+            Behavior::BogusVdf,
+            Behavior::Replay,
+            Behavior::Spoof,
+        ];
+
+        // 5 honest peers of 7 are enough for every threshold
+        for behavior in behaviors.iter() {
+            let mut config = config(7);
+            config.simulation.adversaries = adversaries(*behavior, &[1, 4]);
+
+            let report = Simulation::new(&config, 1, false).run();
+
+            assert_eq!(report.byzantine, vec![1, 4]);
+            assert!(report.agreed().is_some(), "{:?}: {:?}", behavior, report);
+        }
+    }
+
+    #[test]
+    pub fn test_replay_after_restart() {
+        let mut config = config(7);
+        config.simulation.adversaries = adversaries(Behavior::Replay, &[1, 4]);
+
+        // Nobody collects enough commitments before the partition heals, so everyone restarts
+        config.simulation.partitions = vec![PartitionConfig {
+            groups: vec![vec![0, 1, 2, 3], vec![4, 5, 6]],
+            start_ms: 0,
+            heal_ms: Some(6_000),
+        }];
+
+        // Messages of the first round are replayed in the second one, but don't count there
+        for seed in 0..3 {
+            let report = Simulation::new(&config, seed, false).run();
+
+            assert!(report.agreed().is_some(), "seed {}: {:?}", seed, report);
+        }
+    }
+}
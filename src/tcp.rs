@@ -0,0 +1,195 @@
+use actix::prelude::*;
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use unicorn_p2p_rng::wire;
+
+use crate::network::*;
+use crate::protocol::PeerId;
+
+/// Delay between attempts to connect to an unreachable peer.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// Relays messages between the local peer and a static list of remote peers over TCP.
+///
+/// Every remote peer gets its own writer thread that (re)connects on demand and keeps
+/// messages queued while the peer is unreachable. Incoming connections are served by
+/// reader threads that decode frames and pass messages to the local peer.
+pub struct TcpNetwork {
+    listener: Option<TcpListener>,
+    remotes: Vec<(PeerId, SocketAddr)>,
+    outgoing: Vec<mpsc::Sender<Vec<u8>>>,
+    local: Option<Recipient<NetworkMessage>>,
+}
+
+/// A message received from a remote peer
+#[derive(Message)]
+struct Inbound(NetworkMessage);
+
+impl TcpNetwork {
+    /// Binds listening socket right away, so remote peers can connect as soon as possible.
+    pub fn bind(listen: SocketAddr, remotes: Vec<(PeerId, SocketAddr)>) -> io::Result<Self> {
+        Ok(TcpNetwork {
+            listener: Some(TcpListener::bind(listen)?),
+            remotes,
+            outgoing: vec![],
+            local: None,
+        })
+    }
+}
+
+impl Actor for TcpNetwork {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(listener) = self.listener.take() {
+            let addr = ctx.address();
+            thread::spawn(move || accept_loop(listener, addr));
+        }
+
+        for (id, remote) in self.remotes.iter().cloned() {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || write_loop(id, remote, rx));
+
+            self.outgoing.push(tx);
+        }
+    }
+}
+
+impl Handler<Connect> for TcpNetwork {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connect, _: &mut Context<Self>) {
+        println!("[network] Peer {:?} joined the network", msg.id);
+
+        self.local = Some(msg.addr);
+    }
+}
+
+impl Handler<Disconnect> for TcpNetwork {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
+        println!("[network] Peer {:?} left the network", msg.id);
+
+        // Messages queued for remote peers are still delivered
+        self.local = None;
+    }
+}
+
+impl Handler<NetworkMessage> for TcpNetwork {
+    type Result = ();
+
+    fn handle(&mut self, msg: NetworkMessage, _: &mut Context<Self>) {
+        let frame = msg.encode();
+        for tx in self.outgoing.iter() {
+            // Writer thread only stops together with the process
+            let _ = tx.send(frame.clone());
+        }
+
+        // Local peer receives its own messages too, as with the in-process network
+        if let Some(local) = &self.local {
+            msg.deliver_to(local);
+        }
+    }
+}
+
+impl Handler<Inbound> for TcpNetwork {
+    type Result = ();
+
+    fn handle(&mut self, msg: Inbound, _: &mut Context<Self>) {
+        if let Some(local) = &self.local {
+            msg.0.deliver_to(local);
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, addr: Addr<TcpNetwork>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let addr = addr.clone();
+                thread::spawn(move || read_loop(stream, addr));
+            }
+
+            Err(e) => println!("[tcp] failed to accept connection: {}", e),
+        }
+    }
+}
+
+fn read_loop(mut stream: TcpStream, addr: Addr<TcpNetwork>) {
+    let remote = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+
+    let mut buffer = vec![];
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+
+        // Decode every complete frame received so far
+        loop {
+            let (msg, len) = match wire::split_frame(&buffer) {
+                Ok(Some(frame)) => (
+                    NetworkMessage::decode_payload(frame.kind, frame.payload),
+                    frame.len,
+                ),
+                Ok(None) => break,
+
+                // Stream is out of sync and can't be recovered
+                Err(e) => {
+                    println!("[tcp] dropping connection from {}: {:?}", remote, e);
+                    return;
+                }
+            };
+
+            buffer.drain(..len);
+
+            match msg {
+                Ok(msg) => addr.do_send(Inbound(msg)),
+                Err(e) => println!("[tcp] skipping malformed message from {}: {:?}", remote, e),
+            }
+        }
+    }
+}
+
+fn write_loop(id: PeerId, remote: SocketAddr, frames: mpsc::Receiver<Vec<u8>>) {
+    let mut stream: Option<TcpStream> = None;
+
+    for frame in frames {
+        loop {
+            let connected = match stream.as_mut() {
+                Some(stream) => stream,
+                None => match TcpStream::connect(remote) {
+                    Ok(connected) => {
+                        println!("[tcp] connected to peer #{} at {}", id, remote);
+                        stream.get_or_insert(connected)
+                    }
+
+                    Err(_) => {
+                        thread::sleep(RECONNECT_DELAY);
+                        continue;
+                    }
+                },
+            };
+
+            match connected.write_all(&frame) {
+                Ok(()) => break,
+
+                Err(e) => {
+                    println!("[tcp] lost connection to peer #{}: {}, reconnecting", id, e);
+                    stream = None;
+                }
+            }
+        }
+    }
+}
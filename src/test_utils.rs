@@ -0,0 +1,159 @@
+//! Participant types and helpers shared by the unit tests.
+
+use crate::wire::{Reader, WireError, WireMessage, Writer};
+use crate::*;
+use sha2::Sha256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SimpleSeedCommitment {
+    pub id: u64,
+    pub value: Vec<u8>,
+}
+
+impl Signed for SimpleSeedCommitment {}
+
+impl SeedCommitment<u64> for SimpleSeedCommitment {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn value(&self) -> Vec<u8> {
+        self.value.clone()
+    }
+}
+
+pub(crate) struct SimpleSeedReveal {
+    pub id: u64,
+    pub value: Vec<u8>,
+}
+
+impl Signed for SimpleSeedReveal {}
+
+impl SeedReveal<u64> for SimpleSeedReveal {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn value(&self) -> Vec<u8> {
+        self.value.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SimpleVdfResult {
+    pub id_from: u64,
+    pub seed: Vec<u8>,
+    pub result: Vec<u8>,
+}
+
+impl Signed for SimpleVdfResult {}
+
+impl VdfResult<u64> for SimpleVdfResult {
+    fn id(&self) -> u64 {
+        self.id_from
+    }
+
+    fn seed(&self) -> Vec<u8> {
+        self.seed.clone()
+    }
+
+    fn value(&self) -> Vec<u8> {
+        self.result.clone()
+    }
+}
+
+impl WireMessage for SimpleSeedCommitment {
+    const KIND: u8 = 0xf1;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u64(self.id);
+        writer.put_bytes(&self.value);
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        Ok(SimpleSeedCommitment {
+            id: reader.get_u64()?,
+            value: reader.get_bytes()?,
+        })
+    }
+}
+
+impl WireMessage for SimpleVdfResult {
+    const KIND: u8 = 0xf3;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u64(self.id_from);
+        writer.put_bytes(&self.seed);
+        writer.put_bytes(&self.result);
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        Ok(SimpleVdfResult {
+            id_from: reader.get_u64()?,
+            seed: reader.get_bytes()?,
+            result: reader.get_bytes()?,
+        })
+    }
+}
+
+pub(crate) type SimpleUnicornWith<V> =
+    Unicorn<u64, SimpleSeedCommitment, SimpleVdfResult, Sha256, V>;
+pub(crate) type SimpleUnicorn = SimpleUnicornWith<PietrzakVerifier>;
+
+pub(crate) const VDF_PARAMS: u16 = 1024;
+pub(crate) const VDF_DIFFICULTY: u64 = 1_000;
+
+pub(crate) fn new_unicorn(threshold: usize) -> SimpleUnicorn {
+    SimpleUnicorn::new(threshold, PietrzakVerifier::new(VDF_PARAMS, VDF_DIFFICULTY))
+}
+
+pub(crate) fn commit_unicorn_with<V: DelayFunction>(
+    unicorn: &mut SimpleUnicornWith<V>,
+    reveals: &[SimpleSeedReveal],
+) -> Result<(), UnicornError> {
+    // Commit to every seed share
+    for r in reveals {
+        assert_eq!(unicorn.state(), UnicornState::CollectingCommitments);
+        unicorn.add_seed_commitment(SimpleSeedCommitment {
+            id: r.id,
+            value: SimpleUnicornWith::<V>::commitment(&r.value),
+        })?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn seed_unicorn_with<V: DelayFunction>(
+    unicorn: &mut SimpleUnicornWith<V>,
+    reveals: Vec<SimpleSeedReveal>,
+) -> Result<(), UnicornError> {
+    commit_unicorn_with(unicorn, &reveals)?;
+    unicorn.finalize_commitments()?;
+
+    // Reveal seed shares behind the commitments
+    for r in reveals {
+        assert_eq!(unicorn.state(), UnicornState::CollectingReveals);
+        unicorn.add_seed_reveal(r)?;
+    }
+
+    Ok(())
+}
+
+/// Finalizes VDF round of the `unicorn` with honest results from the `ids`.
+pub(crate) fn solve_unicorn_with(
+    unicorn: &mut SimpleUnicorn,
+    ids: &[u64],
+) -> Result<(), UnicornError> {
+    let seed = unicorn.seed().unwrap();
+    let result = unicorn.solve()?;
+
+    for id in ids {
+        unicorn.add_vdf_result(SimpleVdfResult {
+            id_from: *id,
+            seed: seed.clone(),
+            result: result.clone(),
+        })?;
+    }
+
+    unicorn.finalize_vdf_result()
+}
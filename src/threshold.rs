@@ -0,0 +1,214 @@
+//! How many participants should agree on every step of a round.
+//!
+//! Higher thresholds tolerate more byzantine participants, lower ones let the round finish
+//! when more participants are offline. Thresholds are calculated with integer arithmetic,
+//! so every participant arrives at exactly the same number.
+
+use serde::Deserialize;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ThresholdError {
+    /// Nobody would need to agree.
+    Zero,
+    /// Fraction has zero denominator.
+    ZeroDenominator,
+    /// Fraction of more than everyone is never reached.
+    AboveOne,
+    /// Participants are too few to tolerate the faults, `n >= 3f + 1` doesn't hold.
+    TooManyFaults,
+    /// Fraction of nobody would let a single participant decide.
+    NoParticipants,
+}
+
+/// Rule deciding the number of participants that should agree.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ThresholdPolicy {
+    /// Fixed number of participants, however many take part.
+    Absolute { count: usize },
+    /// At least `numerator / denominator` of the participants.
+    Fraction { numerator: u64, denominator: u64 },
+    /// Everyone but at most `faults` faulty participants.
+    Faults { faults: usize },
+    /// Participants holding at least `numerator / denominator` of the stake.
+    Stake { numerator: u64, denominator: u64 },
+}
+
+impl Default for ThresholdPolicy {
+    /// At least 2/3 of the participants.
+    fn default() -> Self {
+        ThresholdPolicy::Fraction {
+            numerator: 2,
+            denominator: 3,
+        }
+    }
+}
+
+impl ThresholdPolicy {
+    pub fn validate(&self) -> Result<(), ThresholdError> {
+        match *self {
+            ThresholdPolicy::Absolute { count: 0 } => Err(ThresholdError::Zero),
+            ThresholdPolicy::Absolute { .. } | ThresholdPolicy::Faults { .. } => Ok(()),
+
+            ThresholdPolicy::Fraction {
+                numerator,
+                denominator,
+            }
+            | ThresholdPolicy::Stake {
+                numerator,
+                denominator,
+            } => match (numerator, denominator) {
+                (_, 0) => Err(ThresholdError::ZeroDenominator),
+                (0, _) => Err(ThresholdError::Zero),
+                (n, d) if n > d => Err(ThresholdError::AboveOne),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Whether the policy is safe with the given number of `participants`.
+    ///
+    /// Tolerating `f` faulty participants takes at least `3f + 1` of them, and a fraction
+    /// takes at least one participant to be a fraction of.
+    pub fn validate_for(&self, participants: usize) -> Result<(), ThresholdError> {
+        self.validate()?;
+
+        match *self {
+            ThresholdPolicy::Faults { faults } if participants <= faults.saturating_mul(3) => {
+                Err(ThresholdError::TooManyFaults)
+            }
+            ThresholdPolicy::Fraction { .. } | ThresholdPolicy::Stake { .. }
+                if participants == 0 =>
+            {
+                Err(ThresholdError::NoParticipants)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of the `participants` that should agree, never less than one.
+    ///
+    /// Every participant holds the same stake unless told otherwise. Fails rather than
+    /// lowering the threshold if the participants are too few for the policy.
+    pub fn threshold(&self, participants: usize) -> Result<usize, ThresholdError> {
+        self.validate_for(participants)?;
+
+        let threshold = match *self {
+            ThresholdPolicy::Absolute { count } => count,
+            ThresholdPolicy::Faults { faults } => participants - faults,
+            ThresholdPolicy::Fraction {
+                numerator,
+                denominator,
+            }
+            | ThresholdPolicy::Stake {
+                numerator,
+                denominator,
+            } => fraction(participants as u64, numerator, denominator) as usize,
+        };
+
+        Ok(threshold.max(1))
+    }
+
+    /// Stake that should agree out of the `total` stake of the participants, never less
+    /// than one. `None` for the policies counting participants regardless of their stake.
+    pub fn stake_threshold(&self, total: u64) -> Option<u64> {
+        match *self {
+            ThresholdPolicy::Stake {
+                numerator,
+                denominator,
+            } => Some(fraction(total, numerator, denominator).max(1)),
+            _ => None,
+        }
+    }
+}
+
+/// `numerator / denominator` of the `total`, rounded up.
+fn fraction(total: u64, numerator: u64, denominator: u64) -> u64 {
+    let product = u128::from(total) * u128::from(numerator);
+
+    product.div_ceil(u128::from(denominator.max(1))) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_thresholds() {
+        let two_thirds = ThresholdPolicy::default();
+        let thresholds = (1..=7).map(|n| two_thirds.threshold(n).unwrap());
+        let thresholds = thresholds.collect::<Vec<_>>();
+        assert_eq!(thresholds, vec![1, 2, 2, 3, 4, 4, 5]);
+
+        // Exact where floats would round 2/3 of 3 million down
+        assert_eq!(two_thirds.threshold(3_000_000), Ok(2_000_000));
+        assert_eq!(fraction(u64::MAX, 2, 2), u64::MAX);
+
+        let majority = ThresholdPolicy::Fraction {
+            numerator: 1,
+            denominator: 2,
+        };
+        assert_eq!(majority.threshold(4), Ok(2));
+        assert_eq!(majority.threshold(5), Ok(3));
+
+        let faults = ThresholdPolicy::Faults { faults: 2 };
+        assert_eq!(faults.threshold(7), Ok(5));
+        assert_eq!(faults.threshold(6), Err(ThresholdError::TooManyFaults));
+        assert_eq!(faults.threshold(2), Err(ThresholdError::TooManyFaults));
+
+        let absolute = ThresholdPolicy::Absolute { count: 3 };
+        assert_eq!(absolute.threshold(2), Ok(3));
+        assert_eq!(absolute.threshold(100), Ok(3));
+        assert_eq!(absolute.stake_threshold(100), None);
+
+        let stake = ThresholdPolicy::Stake {
+            numerator: 2,
+            denominator: 3,
+        };
+        assert_eq!(stake.threshold(7), Ok(5));
+        assert_eq!(stake.stake_threshold(1_000), Some(667));
+        assert_eq!(stake.stake_threshold(0), Some(1));
+    }
+
+    #[test]
+    pub fn test_invalid_policies() {
+        let fraction = |numerator, denominator| ThresholdPolicy::Fraction {
+            numerator,
+            denominator,
+        };
+
+        assert_eq!(fraction(2, 3).validate(), Ok(()));
+        assert_eq!(fraction(1, 1).validate(), Ok(()));
+        assert_eq!(
+            fraction(2, 0).validate(),
+            Err(ThresholdError::ZeroDenominator)
+        );
+        assert_eq!(fraction(0, 3).validate(), Err(ThresholdError::Zero));
+        assert_eq!(fraction(4, 3).validate(), Err(ThresholdError::AboveOne));
+        assert_eq!(
+            ThresholdPolicy::Absolute { count: 0 }.validate(),
+            Err(ThresholdError::Zero)
+        );
+        assert_eq!(ThresholdPolicy::Faults { faults: 0 }.validate(), Ok(()));
+
+        let faults = ThresholdPolicy::Faults { faults: 1 };
+        assert_eq!(faults.validate_for(4), Ok(()));
+        assert_eq!(faults.validate_for(3), Err(ThresholdError::TooManyFaults));
+        assert_eq!(
+            fraction(2, 0).validate_for(3),
+            Err(ThresholdError::ZeroDenominator)
+        );
+
+        // Nobody to count doesn't make the threshold a single participant
+        assert_eq!(
+            fraction(2, 3).threshold(0),
+            Err(ThresholdError::NoParticipants)
+        );
+        let stake = ThresholdPolicy::Stake {
+            numerator: 2,
+            denominator: 3,
+        };
+        assert_eq!(stake.threshold(0), Err(ThresholdError::NoParticipants));
+        assert_eq!(ThresholdPolicy::Absolute { count: 2 }.threshold(0), Ok(2));
+    }
+}
@@ -0,0 +1,1149 @@
+//! Everything needed to check a finished round without taking part in it.
+//!
+//! `verify_round` repeats the round from its transcript the way the participants did:
+//! commitments are checked to be signed by their senders and to reach the threshold,
+//! revealed values are matched against them and checked to cover every one of them, the
+//! seed is derived from the reveals, the VDF proof is checked for that seed, the VDF result is checked to be the only
+//! one backed by a quorum of signed results and the randomness is derived from it. Nothing
+//! the transcript claims to be the outcome is trusted along the way.
+//!
+//! Neither is the threshold the transcript declares: keys, members, threshold policy and
+//! stake of the participants come from the verifier as `RoundRules`, and a transcript
+//! declaring a lower threshold than they give fails.
+//!
+//! Reveals aren't signed in the transcript: a value hashing to a signed commitment could only
+//! have come from the participant that committed to it.
+//!
+//! Transcripts are exported either as a wire frame or as a JSON document with binary fields
+//! in hex, both carry enough to tell what they are. `RoundTranscript::import` reads either.
+
+use digest::Digest;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use crate::identity::{self, KeyRegistry, Signed};
+use crate::quorum;
+use crate::threshold::{ThresholdError, ThresholdPolicy};
+use crate::verifier::VdfScheme;
+use crate::wire::{self, get_list, get_option, put_list, put_option};
+use crate::wire::{Reader, WireError, WireId, WireMessage, Writer};
+use crate::{DelayFunction, DelayParameters, SeedCommitment, Unicorn, UnicornState, VdfResult};
+
+/// Names the JSON document as a transcript.
+const JSON_FORMAT: &str = "unicorn-round-transcript";
+
+/// Version of the JSON document produced by this implementation.
+const JSON_VERSION: u32 = 1;
+
+/// Digest with a name a transcript can refer to it by.
+pub trait NamedDigest: Digest {
+    const NAME: &'static str;
+}
+
+impl NamedDigest for sha2::Sha256 {
+    const NAME: &'static str = "sha256";
+}
+
+impl NamedDigest for sha2::Sha512 {
+    const NAME: &'static str = "sha512";
+}
+
+/// Finished round as seen by one of its participants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundTranscript<I> {
+    pub round: u64,
+
+    /// Randomness of the previous round mixed into the seed of this one.
+    pub previous_randomness: Option<Vec<u8>>,
+
+    /// Signed commitments of the participants, sorted by participant.
+    pub commitments: Vec<SignedValue<I>>,
+
+    /// Values revealed for the commitments, sorted by participant.
+    pub reveals: Vec<(I, Vec<u8>)>,
+
+    pub seed: Vec<u8>,
+
+    /// Delay function the round was run with.
+    pub parameters: DelayParameters,
+
+    /// Every VDF result received with its signature, sorted by participant.
+    pub vdf_results: Vec<SignedVdfResult<I>>,
+
+    /// Output of the delay function together with its proof, agreed on by the quorum.
+    pub vdf_result: Vec<u8>,
+
+    /// Participants, or their stake if `weights` are given, that should agree on the result
+    /// as the participant saw it. Checked against `RoundRules` rather than trusted.
+    pub threshold: u64,
+
+    /// Stake of the participants, sorted by participant. `None` if votes aren't weighed.
+    pub weights: Option<Vec<(I, u64)>>,
+
+    pub randomness: Vec<u8>,
+
+    /// Name of the digest the seed and the randomness are derived with, see `NamedDigest`.
+    pub digest: String,
+}
+
+/// Value of a participant with its signature over `identity::signed_bytes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedValue<I> {
+    pub id: I,
+    pub value: Vec<u8>,
+    /// `None` if the round accepted unsigned messages.
+    pub signature: Option<Vec<u8>>,
+}
+
+/// VDF result of a participant with its signature over `identity::signed_bytes`.
+///
+/// The result is signed together with the seed it was calculated for, that is the seed
+/// derived from the reveals rather than the one the transcript claims.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedVdfResult<I> {
+    pub id: I,
+    /// Scheme the result was calculated with, `None` if the sender didn't tell.
+    pub scheme: Option<VdfScheme>,
+    pub value: Vec<u8>,
+    /// `None` if the round accepted unsigned messages.
+    pub signature: Option<Vec<u8>>,
+}
+
+/// What the verifier knows about the round regardless of its transcript.
+#[derive(Debug, Clone)]
+pub struct RoundRules<I: Hash + Eq> {
+    keys: KeyRegistry<I>,
+    members: Option<BTreeSet<I>>,
+    policy: ThresholdPolicy,
+    weights: Option<BTreeMap<I, u64>>,
+}
+
+impl<I: Hash + Eq + Ord + Clone> RoundRules<I> {
+    /// Round of everyone with a key in `keys`, under the default threshold policy.
+    pub fn new(keys: KeyRegistry<I>) -> Self {
+        RoundRules {
+            keys,
+            members: None,
+            policy: ThresholdPolicy::default(),
+            weights: None,
+        }
+    }
+
+    /// Restricts the round to the given participants.
+    pub fn with_members<M: IntoIterator<Item = I>>(mut self, members: M) -> Self {
+        self.members = Some(members.into_iter().collect());
+        self
+    }
+
+    pub fn with_policy(mut self, policy: ThresholdPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Stake of every participant, participants missing from the table hold no stake.
+    pub fn with_weights<W: IntoIterator<Item = (I, u64)>>(mut self, weights: W) -> Self {
+        self.weights = Some(weights.into_iter().collect());
+        self
+    }
+
+    /// Stake of the participant counted towards the threshold, none for non-members.
+    pub fn stake(&self, id: &I) -> u64 {
+        if self.members.as_ref().is_some_and(|m| !m.contains(id)) {
+            return 0;
+        }
+
+        match (&self.policy, &self.weights) {
+            (ThresholdPolicy::Stake { .. }, Some(weights)) => weights.get(id).copied().unwrap_or(0),
+            _ => 1,
+        }
+    }
+
+    /// Participants, or their stake, that should agree on every step, decided exactly like
+    /// `Unicorn` does it.
+    pub fn threshold(&self) -> Result<u64, ThresholdError> {
+        if let (ThresholdPolicy::Stake { .. }, Some(weights)) = (&self.policy, &self.weights) {
+            let total = match &self.members {
+                Some(members) => members
+                    .iter()
+                    .map(|id| self.stake(id))
+                    .fold(0, u64::saturating_add),
+                None => weights.values().copied().fold(0, u64::saturating_add),
+            };
+            if let Some(threshold) = self.policy.stake_threshold(total) {
+                return Ok(threshold);
+            }
+        }
+
+        let participants = match &self.members {
+            Some(members) => members.len(),
+            None => self.keys.len(),
+        };
+
+        self.policy.threshold(participants).map(|t| t as u64)
+    }
+}
+
+impl<I, C, R, D, F> Unicorn<I, C, R, D, F>
+where
+    I: Hash + Eq + Ord + Clone,
+    C: SeedCommitment<I>,
+    R: VdfResult<I>,
+    D: NamedDigest,
+    F: DelayFunction,
+{
+    /// Transcript of the finalized round, `None` if it isn't finalized yet.
+    pub fn transcript(&self, round: u64) -> Option<RoundTranscript<I>> {
+        if self.state != UnicornState::RandomnessReady {
+            return None;
+        }
+
+        let mut commitments = self
+            .seed_commitments
+            .iter()
+            .map(|(id, commitment)| SignedValue {
+                id: id.clone(),
+                value: commitment.value(),
+                signature: commitment.signature(),
+            })
+            .collect::<Vec<_>>();
+        commitments.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+
+        let mut reveals = self.reveals.clone().into_iter().collect::<Vec<_>>();
+        reveals.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut vdf_results = self
+            .vdf_results
+            .iter()
+            .map(|(id, result)| SignedVdfResult {
+                id: id.clone(),
+                scheme: result.scheme(),
+                value: result.value(),
+                signature: result.signature(),
+            })
+            .collect::<Vec<_>>();
+        vdf_results.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+
+        let threshold = self
+            .stake_threshold()
+            .or_else(|| self.threshold().ok().map(|t| t as u64))?;
+        let weights = self
+            .stake_threshold()
+            .and(self.weights())
+            .map(|weights| weights.iter().map(|(id, w)| (id.clone(), *w)).collect());
+
+        Some(RoundTranscript {
+            round,
+            previous_randomness: self.previous_randomness.clone(),
+            commitments,
+            reveals,
+            seed: self.seed.clone()?,
+            parameters: self.delay.parameters(),
+            vdf_results,
+            vdf_result: self.quorum.as_ref()?.value.clone(),
+            threshold,
+            weights,
+            randomness: self.randomness.clone()?,
+            digest: D::NAME.into(),
+        })
+    }
+}
+
+/// Outcome of every check of a transcript, see `verify_round`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundReport<I> {
+    pub round: u64,
+
+    /// Transcript names the delay function and the digest the round was checked with.
+    pub parameters_match: bool,
+    pub digest_matches: bool,
+
+    /// Threshold given by the `RoundRules`, `None` if their policy can't be met safely
+    /// by their participants.
+    pub threshold: Option<u64>,
+
+    /// Transcript declares at least the threshold given by the `RoundRules`.
+    pub threshold_sufficient: bool,
+
+    /// Participants that sent more than one commitment, reveal or VDF result.
+    pub duplicates: Vec<I>,
+
+    /// Participants whose commitment isn't signed with their key.
+    pub invalid_signatures: Vec<I>,
+
+    /// Signed commitments reach the threshold.
+    pub commitments_reached: bool,
+
+    /// Participants that revealed a value without committing to it first.
+    pub uncommitted_reveals: Vec<I>,
+
+    /// Participants whose revealed value doesn't match their commitment.
+    pub mismatched_reveals: Vec<I>,
+
+    /// Every commitment has a value revealed for it.
+    pub reveals_complete: bool,
+
+    /// Seed derived from the revealed values.
+    pub seed: Vec<u8>,
+    pub seed_matches: bool,
+
+    /// VDF result is a valid proof for the derived seed.
+    pub vdf_valid: bool,
+
+    /// Participants whose VDF result for the derived seed isn't signed with their key.
+    pub invalid_vdf_signatures: Vec<I>,
+
+    /// VDF result is the only one received signed from enough participants to reach
+    /// the threshold.
+    pub quorum_reached: bool,
+
+    /// Randomness derived from the VDF output.
+    pub randomness: Vec<u8>,
+    pub randomness_matches: bool,
+}
+
+impl<I> RoundReport<I> {
+    /// Every check succeeded.
+    pub fn passed(&self) -> bool {
+        self.parameters_match
+            && self.digest_matches
+            && self.threshold_sufficient
+            && self.duplicates.is_empty()
+            && self.invalid_signatures.is_empty()
+            && self.commitments_reached
+            && self.uncommitted_reveals.is_empty()
+            && self.mismatched_reveals.is_empty()
+            && self.reveals_complete
+            && self.seed_matches
+            && self.vdf_valid
+            && self.invalid_vdf_signatures.is_empty()
+            && self.quorum_reached
+            && self.randomness_matches
+    }
+}
+
+/// Checks the round of the `transcript` with the `delay` function and the digest `D`
+/// the participants used, under the `rules` of the round.
+pub fn verify_round<I, D, F>(
+    transcript: &RoundTranscript<I>,
+    delay: &F,
+    rules: &RoundRules<I>,
+) -> RoundReport<I>
+where
+    I: WireId + Hash + Eq + Ord + Clone,
+    D: NamedDigest,
+    F: DelayFunction,
+{
+    let round = transcript.round;
+    let keys = &rules.keys;
+    let threshold = rules.threshold().ok();
+    let mut duplicates = vec![];
+
+    let mut invalid_signatures = vec![];
+    let mut commitments = BTreeMap::new();
+    for commitment in transcript.commitments.iter() {
+        let message = SignedMessage::new(
+            identity::COMMITMENT,
+            &commitment.id,
+            &commitment.value,
+            &commitment.signature,
+            round,
+            &[],
+        );
+        if keys.verify(&commitment.id, &message).is_err() {
+            invalid_signatures.push(commitment.id.clone());
+        }
+
+        if commitments
+            .insert(commitment.id.clone(), &commitment.value)
+            .is_some()
+        {
+            duplicates.push(commitment.id.clone());
+        }
+    }
+
+    let mut uncommitted_reveals = vec![];
+    let mut mismatched_reveals = vec![];
+    let mut reveals = BTreeMap::new();
+    for (id, value) in transcript.reveals.iter() {
+        match commitments.get(id) {
+            None => uncommitted_reveals.push(id.clone()),
+            Some(commitment) if **commitment != hash::<D>(value) => {
+                mismatched_reveals.push(id.clone())
+            }
+            Some(_) => {}
+        }
+
+        if reveals.insert(id.clone(), value).is_some() {
+            duplicates.push(id.clone());
+        }
+    }
+
+    // Threshold is counted exactly like `Unicorn` does it, but with the stake and the policy
+    // of the rules rather than the ones the transcript declares
+    let stake = |id: &I| rules.stake(id);
+    let reached = |ids: &mut dyn Iterator<Item = &I>| match threshold {
+        Some(threshold) => ids.map(stake).fold(0, u64::saturating_add) >= threshold,
+        None => false,
+    };
+
+    // Only the signed commitments count towards the threshold
+    let mut signed = commitments
+        .keys()
+        .filter(|id| !invalid_signatures.contains(id));
+    let commitments_reached = reached(&mut signed);
+
+    // Seed is derived exactly like `Unicorn` does it: only once every commitment is revealed
+    let reveals_complete = commitments
+        .keys()
+        .all(|id| reveals.contains_key(id) && !mismatched_reveals.contains(id));
+
+    // Seed is derived exactly like `Unicorn` does it: revealed values in order of participants
+    let mut seed = transcript.previous_randomness.clone().unwrap_or_default();
+    seed.extend(reveals.values().flat_map(|value| value.to_vec()));
+    let seed = hash::<D>(&seed);
+
+    // Only results signed for the derived seed vote, a participant votes once
+    let mut invalid_vdf_signatures = vec![];
+    let mut votes = BTreeMap::new();
+    for result in transcript.vdf_results.iter() {
+        let message = match result.scheme {
+            Some(scheme) => SignedMessage::new(
+                identity::VDF_RESULT,
+                &result.id,
+                &result.value,
+                &result.signature,
+                round,
+                &[&[scheme.id()], &seed],
+            ),
+            None => SignedMessage::new(
+                identity::VDF_RESULT,
+                &result.id,
+                &result.value,
+                &result.signature,
+                round,
+                &[&seed],
+            ),
+        };
+        if keys.verify(&result.id, &message).is_err() {
+            invalid_vdf_signatures.push(result.id.clone());
+        } else if votes.contains_key(&result.id) {
+            duplicates.push(result.id.clone());
+        } else {
+            votes.insert(result.id.clone(), result.value.clone());
+        }
+    }
+
+    // Quorum is picked exactly like `Unicorn` does it, so another value reaching the threshold
+    // is caught as well
+    let quorum_reached = threshold.is_some_and(|threshold| {
+        quorum::select_weighted(votes, stake, threshold)
+            .is_ok_and(|quorum| quorum.value == transcript.vdf_result)
+    });
+
+    let randomness = hash::<D>(&transcript.vdf_result);
+
+    RoundReport {
+        round: transcript.round,
+        parameters_match: delay.parameters() == transcript.parameters,
+        digest_matches: transcript.digest == D::NAME,
+        threshold,
+        threshold_sufficient: threshold.is_some_and(|threshold| transcript.threshold >= threshold),
+        duplicates,
+        invalid_signatures,
+        commitments_reached,
+        uncommitted_reveals,
+        mismatched_reveals,
+        reveals_complete,
+        seed_matches: seed == transcript.seed,
+        vdf_valid: delay.verify(&seed, &transcript.vdf_result),
+        invalid_vdf_signatures,
+        quorum_reached,
+        seed,
+        randomness_matches: randomness == transcript.randomness,
+        randomness,
+    }
+}
+
+/// Message of the transcript as its sender signed it.
+struct SignedMessage {
+    bytes: Vec<u8>,
+    signature: Option<Vec<u8>>,
+}
+
+impl SignedMessage {
+    /// The `value` is signed after the other `parts`.
+    fn new<I: WireId>(
+        kind: &[u8],
+        id: &I,
+        value: &[u8],
+        signature: &Option<Vec<u8>>,
+        round: u64,
+        parts: &[&[u8]],
+    ) -> Self {
+        let mut parts = parts.to_vec();
+        parts.push(value);
+
+        SignedMessage {
+            bytes: identity::signed_bytes(kind, id, round, &parts),
+            signature: signature.clone(),
+        }
+    }
+}
+
+impl Signed for SignedMessage {
+    fn signed_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+
+    fn signature(&self) -> Option<Vec<u8>> {
+        self.signature.clone()
+    }
+}
+
+fn hash<D: Digest>(bytes: &[u8]) -> Vec<u8> {
+    let mut hash = D::new();
+    hash.input(bytes);
+    hash.result().to_vec()
+}
+
+/// How a transcript is exported.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+    /// JSON document meant to be read by people and other tools.
+    Json,
+    /// Wire frame, several times smaller than the JSON document.
+    Binary,
+}
+
+impl TranscriptFormat {
+    /// Extension of the files transcripts are exported to.
+    pub fn extension(self) -> &'static str {
+        match self {
+            TranscriptFormat::Json => "json",
+            TranscriptFormat::Binary => "bin",
+        }
+    }
+}
+
+impl fmt::Display for TranscriptFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranscriptFormat::Json => write!(f, "json"),
+            TranscriptFormat::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+impl FromStr for TranscriptFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(TranscriptFormat::Json),
+            "binary" => Ok(TranscriptFormat::Binary),
+            _ => Err(format!("unknown transcript format {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TranscriptError {
+    Json(serde_json::Error),
+    /// Binary field of the JSON document isn't valid hex.
+    Hex(hex::FromHexError),
+    /// JSON document isn't a transcript or has a version this implementation can't read.
+    UnknownFormat,
+    Wire(WireError),
+}
+
+impl From<serde_json::Error> for TranscriptError {
+    fn from(e: serde_json::Error) -> Self {
+        TranscriptError::Json(e)
+    }
+}
+
+impl From<hex::FromHexError> for TranscriptError {
+    fn from(e: hex::FromHexError) -> Self {
+        TranscriptError::Hex(e)
+    }
+}
+
+impl From<WireError> for TranscriptError {
+    fn from(e: WireError) -> Self {
+        TranscriptError::Wire(e)
+    }
+}
+
+impl<I> RoundTranscript<I>
+where
+    I: WireId + Serialize + DeserializeOwned + Clone,
+{
+    pub fn export(&self, format: TranscriptFormat) -> Vec<u8> {
+        match format {
+            TranscriptFormat::Json => self.to_json().into_bytes(),
+            TranscriptFormat::Binary => wire::encode(self),
+        }
+    }
+
+    /// Reads a transcript exported in any format.
+    pub fn import(bytes: &[u8]) -> Result<Self, TranscriptError> {
+        if bytes.starts_with(&wire::MAGIC) {
+            return Ok(wire::decode(bytes)?);
+        }
+
+        Self::from_json(bytes)
+    }
+
+    pub fn to_json(&self) -> String {
+        let hex_list = |values: &[(I, Vec<u8>)]| {
+            values
+                .iter()
+                .map(|(id, value)| JsonValue {
+                    id: id.clone(),
+                    value: hex::encode(value),
+                })
+                .collect()
+        };
+
+        let document = JsonTranscript {
+            format: JSON_FORMAT.into(),
+            version: JSON_VERSION,
+            round: self.round,
+            digest: self.digest.clone(),
+            previous_randomness: self.previous_randomness.as_ref().map(hex::encode),
+            commitments: self.commitments.iter().map(JsonSigned::new).collect(),
+            reveals: hex_list(&self.reveals),
+            seed: hex::encode(&self.seed),
+            parameters: JsonParameters {
+                name: self.parameters.name.clone(),
+                difficulty: self.parameters.difficulty,
+                size_bits: self.parameters.size_bits,
+            },
+            vdf_results: self.vdf_results.iter().map(JsonVdfResult::new).collect(),
+            vdf_result: hex::encode(&self.vdf_result),
+            threshold: self.threshold,
+            weights: self.weights.as_ref().map(|weights| {
+                weights
+                    .iter()
+                    .map(|(id, stake)| JsonStake {
+                        id: id.clone(),
+                        stake: *stake,
+                    })
+                    .collect()
+            }),
+            randomness: hex::encode(&self.randomness),
+        };
+
+        // Nothing in the document can fail to serialize
+        serde_json::to_string_pretty(&document).expect("transcript serializes to JSON")
+    }
+
+    pub fn from_json(json: &[u8]) -> Result<Self, TranscriptError> {
+        let document: JsonTranscript<I> = serde_json::from_slice(json)?;
+        if document.format != JSON_FORMAT || document.version != JSON_VERSION {
+            return Err(TranscriptError::UnknownFormat);
+        }
+
+        let hex_list = |values: Vec<JsonValue<I>>| {
+            values
+                .into_iter()
+                .map(|v| Ok((v.id, hex::decode(v.value)?)))
+                .collect::<Result<Vec<_>, TranscriptError>>()
+        };
+
+        Ok(RoundTranscript {
+            round: document.round,
+            previous_randomness: document.previous_randomness.map(hex::decode).transpose()?,
+            commitments: document
+                .commitments
+                .into_iter()
+                .map(JsonSigned::decode)
+                .collect::<Result<_, _>>()?,
+            reveals: hex_list(document.reveals)?,
+            seed: hex::decode(document.seed)?,
+            parameters: DelayParameters {
+                name: document.parameters.name,
+                difficulty: document.parameters.difficulty,
+                size_bits: document.parameters.size_bits,
+            },
+            vdf_results: document
+                .vdf_results
+                .into_iter()
+                .map(JsonVdfResult::decode)
+                .collect::<Result<_, _>>()?,
+            vdf_result: hex::decode(document.vdf_result)?,
+            threshold: document.threshold,
+            weights: document
+                .weights
+                .map(|weights| weights.into_iter().map(|w| (w.id, w.stake)).collect()),
+            randomness: hex::decode(document.randomness)?,
+            digest: document.digest,
+        })
+    }
+}
+
+/// Transcript as a JSON document, binary fields are hex-encoded.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonTranscript<I> {
+    format: String,
+    version: u32,
+    round: u64,
+    digest: String,
+    previous_randomness: Option<String>,
+    commitments: Vec<JsonSigned<I>>,
+    reveals: Vec<JsonValue<I>>,
+    seed: String,
+    parameters: JsonParameters,
+    vdf_results: Vec<JsonVdfResult<I>>,
+    vdf_result: String,
+    threshold: u64,
+    weights: Option<Vec<JsonStake<I>>>,
+    randomness: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonValue<I> {
+    id: I,
+    value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonSigned<I> {
+    id: I,
+    value: String,
+    signature: Option<String>,
+}
+
+impl<I: Clone> JsonSigned<I> {
+    fn new(signed: &SignedValue<I>) -> Self {
+        JsonSigned {
+            id: signed.id.clone(),
+            value: hex::encode(&signed.value),
+            signature: signed.signature.as_ref().map(hex::encode),
+        }
+    }
+
+    fn decode(self) -> Result<SignedValue<I>, TranscriptError> {
+        Ok(SignedValue {
+            id: self.id,
+            value: hex::decode(self.value)?,
+            signature: self.signature.map(hex::decode).transpose()?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonVdfResult<I> {
+    id: I,
+    scheme: Option<VdfScheme>,
+    value: String,
+    signature: Option<String>,
+}
+
+impl<I: Clone> JsonVdfResult<I> {
+    fn new(result: &SignedVdfResult<I>) -> Self {
+        JsonVdfResult {
+            id: result.id.clone(),
+            scheme: result.scheme,
+            value: hex::encode(&result.value),
+            signature: result.signature.as_ref().map(hex::encode),
+        }
+    }
+
+    fn decode(self) -> Result<SignedVdfResult<I>, TranscriptError> {
+        Ok(SignedVdfResult {
+            id: self.id,
+            scheme: self.scheme,
+            value: hex::decode(self.value)?,
+            signature: self.signature.map(hex::decode).transpose()?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonStake<I> {
+    id: I,
+    stake: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonParameters {
+    name: String,
+    difficulty: u64,
+    size_bits: u16,
+}
+
+impl<I: WireId> WireMessage for RoundTranscript<I> {
+    const KIND: u8 = 0x12;
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.put_u64(self.round);
+        put_option(writer, &self.previous_randomness, |r, w| w.put_bytes(r));
+        put_list(writer, &self.commitments, put_signed);
+        put_list(writer, &self.reveals, |(id, value), w| {
+            id.put(w);
+            w.put_bytes(value);
+        });
+        writer.put_bytes(&self.seed);
+        writer.put_bytes(self.parameters.name.as_bytes());
+        writer.put_u64(self.parameters.difficulty);
+        writer.put_fixed(&self.parameters.size_bits.to_be_bytes());
+        put_list(writer, &self.vdf_results, put_vdf_result);
+        writer.put_bytes(&self.vdf_result);
+        writer.put_u64(self.threshold);
+        put_option(writer, &self.weights, |weights, w| {
+            put_list(w, weights, |(id, stake), w| {
+                id.put(w);
+                w.put_u64(*stake);
+            })
+        });
+        writer.put_bytes(&self.randomness);
+        writer.put_bytes(self.digest.as_bytes());
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+        let text = |bytes| String::from_utf8(bytes).map_err(|_| WireError::InvalidText);
+
+        Ok(RoundTranscript {
+            round: reader.get_u64()?,
+            previous_randomness: get_option(reader, |r| r.get_bytes())?,
+            commitments: get_list(reader, get_signed)?,
+            reveals: get_list(reader, |r| Ok((I::get(r)?, r.get_bytes()?)))?,
+            seed: reader.get_bytes()?,
+            parameters: DelayParameters {
+                name: text(reader.get_bytes()?)?,
+                difficulty: reader.get_u64()?,
+                size_bits: u16::from_be_bytes(reader.get_fixed()?),
+            },
+            vdf_results: get_list(reader, get_vdf_result)?,
+            vdf_result: reader.get_bytes()?,
+            threshold: reader.get_u64()?,
+            weights: get_option(reader, |r| get_list(r, |r| Ok((I::get(r)?, r.get_u64()?))))?,
+            randomness: reader.get_bytes()?,
+            digest: text(reader.get_bytes()?)?,
+        })
+    }
+}
+
+fn put_signed<I: WireId>(signed: &SignedValue<I>, writer: &mut Writer) {
+    signed.id.put(writer);
+    writer.put_bytes(&signed.value);
+    put_option(writer, &signed.signature, |s, w| w.put_bytes(s));
+}
+
+fn get_signed<I: WireId>(reader: &mut Reader) -> Result<SignedValue<I>, WireError> {
+    Ok(SignedValue {
+        id: I::get(reader)?,
+        value: reader.get_bytes()?,
+        signature: get_option(reader, |r| r.get_bytes())?,
+    })
+}
+
+fn put_vdf_result<I: WireId>(result: &SignedVdfResult<I>, writer: &mut Writer) {
+    result.id.put(writer);
+    put_option(writer, &result.scheme, |s, w| w.put_u8(s.id()));
+    writer.put_bytes(&result.value);
+    put_option(writer, &result.signature, |s, w| w.put_bytes(s));
+}
+
+fn get_vdf_result<I: WireId>(reader: &mut Reader) -> Result<SignedVdfResult<I>, WireError> {
+    Ok(SignedVdfResult {
+        id: I::get(reader)?,
+        scheme: get_option(reader, |r| {
+            let scheme = r.get_u8()?;
+            VdfScheme::from_id(scheme).ok_or(WireError::UnknownTag {
+                kind: RoundTranscript::<I>::KIND,
+                tag: scheme,
+            })
+        })?,
+        value: reader.get_bytes()?,
+        signature: get_option(reader, |r| r.get_bytes())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::{Signer, SigningKey};
+    use crate::test_utils::*;
+    use crate::wire;
+    use crate::{IteratedHash, PietrzakVerifier};
+    use sha2::Sha256;
+
+    fn key(id: u64) -> SigningKey {
+        SigningKey::from_bytes(&[id as u8 + 1; 32])
+    }
+
+    /// Four participants, three of them should agree under the default policy.
+    fn rules() -> RoundRules<u64> {
+        let mut keys = KeyRegistry::new();
+        for id in 0..4 {
+            keys.register(id, key(id).verifying_key());
+        }
+        RoundRules::new(keys)
+    }
+
+    /// Signs the commitments and the VDF results the way participants sign their messages.
+    fn sign(transcript: &mut RoundTranscript<u64>) {
+        let round = transcript.round;
+        for commitment in transcript.commitments.iter_mut() {
+            let bytes = identity::signed_bytes(
+                identity::COMMITMENT,
+                &commitment.id,
+                round,
+                &[&commitment.value],
+            );
+            commitment.signature = Some(key(commitment.id).sign(&bytes).to_bytes().to_vec());
+        }
+
+        for result in transcript.vdf_results.iter_mut() {
+            let seed = &transcript.seed;
+            let bytes = match result.scheme {
+                Some(scheme) => identity::signed_bytes(
+                    identity::VDF_RESULT,
+                    &result.id,
+                    round,
+                    &[&[scheme.id()], seed, &result.value],
+                ),
+                None => identity::signed_bytes(
+                    identity::VDF_RESULT,
+                    &result.id,
+                    round,
+                    &[seed, &result.value],
+                ),
+            };
+            result.signature = Some(key(result.id).sign(&bytes).to_bytes().to_vec());
+        }
+    }
+
+    fn transcript() -> RoundTranscript<u64> {
+        let reveals = (0..3)
+            .map(|id| SimpleSeedReveal {
+                id,
+                value: vec![id as u8; 3],
+            })
+            .collect();
+
+        let mut unicorn = new_unicorn(3).with_previous_randomness(vec![7; 32]);
+        assert_eq!(unicorn.transcript(5), None);
+
+        seed_unicorn_with(&mut unicorn, reveals).unwrap();
+        unicorn.finalize_seed().unwrap();
+        solve_unicorn_with(&mut unicorn, &[0, 1, 2]).unwrap();
+
+        // Messages of the unit tests aren't signed
+        let mut transcript = unicorn.transcript(5).unwrap();
+        assert!(transcript.commitments.iter().all(|c| c.signature.is_none()));
+        assert!(transcript.vdf_results.iter().all(|r| r.signature.is_none()));
+        sign(&mut transcript);
+        assert_eq!(transcript.vdf_results.len(), 3);
+        assert_eq!(
+            (transcript.threshold, transcript.digest.as_str()),
+            (3, "sha256")
+        );
+
+        wire::decode(&wire::encode(&transcript)).unwrap()
+    }
+
+    fn verify(transcript: &RoundTranscript<u64>) -> RoundReport<u64> {
+        verify_with(transcript, &rules())
+    }
+
+    fn verify_with(transcript: &RoundTranscript<u64>, rules: &RoundRules<u64>) -> RoundReport<u64> {
+        let delay = PietrzakVerifier::new(VDF_PARAMS, VDF_DIFFICULTY);
+        verify_round::<_, Sha256, _>(transcript, &delay, rules)
+    }
+
+    #[test]
+    pub fn test_verify_round() {
+        let transcript = transcript();
+        let report = verify(&transcript);
+
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(report.round, 5);
+        assert_eq!(report.threshold, Some(3));
+        assert_eq!(report.seed, transcript.seed);
+        assert_eq!(report.randomness, transcript.randomness);
+
+        // Round can't be checked with another delay function
+        let report = verify_round::<_, Sha256, _>(&transcript, &IteratedHash::new(1_000), &rules());
+        assert!(!report.parameters_match && !report.vdf_valid);
+    }
+
+    #[test]
+    pub fn test_tampered_transcript() {
+        let mut transcript = transcript();
+        transcript.reveals[1].1 = vec![9; 3];
+        transcript.reveals.push((3, vec![3; 3]));
+
+        // Seed doesn't follow from the reveals anymore, nor does the proof fit it
+        let report = verify(&transcript);
+        assert_eq!(report.mismatched_reveals, vec![1]);
+        assert_eq!(report.uncommitted_reveals, vec![3]);
+        assert!(!report.seed_matches && !report.vdf_valid);
+        assert!(report.randomness_matches);
+
+        let mut transcript = self::transcript();
+        transcript.randomness = vec![0; 32];
+        let report = verify(&transcript);
+        assert!(report.seed_matches && report.vdf_valid);
+        assert!(!report.randomness_matches && !report.passed());
+
+        let mut transcript = self::transcript();
+        transcript.vdf_result[0] ^= 0xff;
+        assert!(!verify(&transcript).vdf_valid);
+
+        // Result nobody but one participant sent isn't backed by the quorum
+        let mut transcript = self::transcript();
+        transcript.vdf_results.truncate(1);
+        let report = verify(&transcript);
+        assert!(report.vdf_valid && !report.quorum_reached);
+
+        let mut transcript = self::transcript();
+        transcript.digest = "sha512".into();
+        assert!(!verify(&transcript).digest_matches);
+    }
+
+    #[test]
+    pub fn test_forged_transcript() {
+        // Nobody committed nor revealed anything, only the previous randomness makes the seed
+        let mut transcript = transcript();
+        transcript.commitments.clear();
+        transcript.reveals.clear();
+        let report = verify(&transcript);
+        assert!(!report.commitments_reached && !report.passed());
+
+        // Seed can't be derived from a part of the reveals
+        let mut transcript = self::transcript();
+        transcript.reveals.pop();
+        assert!(!verify(&transcript).reveals_complete);
+
+        // Value can't be revealed twice to change the seed
+        let mut transcript = self::transcript();
+        let reveal = transcript.reveals[0].clone();
+        transcript.reveals.insert(0, reveal);
+        let report = verify(&transcript);
+        assert_eq!(report.duplicates, vec![0]);
+        assert!(report.mismatched_reveals.is_empty() && !report.passed());
+
+        // Commitment and reveal of a participant can't be made up without its key
+        let mut transcript = self::transcript();
+        transcript.commitments[2].value = hash::<Sha256>(&[9; 3]);
+        transcript.reveals[2].1 = vec![9; 3];
+        transcript.commitments[1].signature = None;
+        let report = verify(&transcript);
+        assert_eq!(report.invalid_signatures, vec![1, 2]);
+        assert!(report.mismatched_reveals.is_empty());
+        assert!(!report.commitments_reached && !report.passed());
+
+        // Nor can a signature of another round be reused
+        let mut transcript = self::transcript();
+        transcript.round += 1;
+        assert_eq!(verify(&transcript).invalid_signatures, vec![0, 1, 2]);
+
+        // Result can't be voted for on behalf of a participant, nor for another seed
+        let mut transcript = self::transcript();
+        transcript.vdf_results[0].signature = None;
+        transcript.vdf_results[1].id = 3;
+        let report = verify(&transcript);
+        assert_eq!(report.invalid_vdf_signatures, vec![0, 3]);
+        assert!(report.vdf_valid && !report.quorum_reached);
+
+        let mut transcript = self::transcript();
+        transcript.previous_randomness = None;
+        let report = verify(&transcript);
+        assert_eq!(report.invalid_vdf_signatures, vec![0, 1, 2]);
+    }
+
+    #[test]
+    pub fn test_declared_threshold() {
+        // Single result is enough for the threshold the transcript declares, not for the rules
+        let mut transcript = transcript();
+        transcript.threshold = 1;
+        transcript.vdf_results.truncate(1);
+        let report = verify(&transcript);
+        assert_eq!(report.threshold, Some(3));
+        assert!(!report.threshold_sufficient && !report.quorum_reached);
+        assert!(report.commitments_reached && report.vdf_valid);
+
+        // Everyone of the four should have agreed
+        let transcript = self::transcript();
+        let rules = rules().with_policy(ThresholdPolicy::Faults { faults: 0 });
+        let report = verify_with(&transcript, &rules);
+        assert_eq!(report.threshold, Some(4));
+        assert!(!report.threshold_sufficient && !report.commitments_reached);
+
+        // Everyone of the three members did agree
+        let report = verify_with(&transcript, &rules.with_members(vec![0, 1, 2]));
+        assert!(report.passed(), "{:?}", report);
+
+        // Policy the participants can't meet fails every transcript
+        let rules = self::rules().with_policy(ThresholdPolicy::Faults { faults: 1 });
+        let report = verify_with(&transcript, &rules.with_members(vec![0, 1, 2]));
+        assert_eq!(report.threshold, None);
+        assert!(!report.threshold_sufficient && !report.quorum_reached);
+    }
+
+    #[test]
+    pub fn test_weighted_threshold() {
+        let stake = ThresholdPolicy::Stake {
+            numerator: 2,
+            denominator: 3,
+        };
+        let rules = rules()
+            .with_policy(stake)
+            .with_weights(vec![(0, 5), (1, 1), (2, 1), (3, 1)]);
+        assert_eq!(rules.threshold(), Ok(6));
+
+        // Transcript weighing the votes of the participants differently doesn't matter
+        let mut transcript = transcript();
+        transcript.threshold = 6;
+        transcript.weights = Some(vec![(1, 5), (2, 5)]);
+        assert!(verify_with(&transcript, &rules).passed());
+
+        // Participants 1 and 2 hold too little stake on their own
+        transcript.vdf_results.remove(0);
+        let report = verify_with(&transcript, &rules);
+        assert!(report.threshold_sufficient && !report.quorum_reached);
+    }
+
+    #[test]
+    pub fn test_export_formats() {
+        let mut transcript = transcript();
+        transcript.weights = Some(vec![(0, 5), (1, 1), (2, 1)]);
+        transcript.threshold = 6;
+
+        for format in [TranscriptFormat::Json, TranscriptFormat::Binary].iter() {
+            let exported = transcript.export(*format);
+            assert_eq!(RoundTranscript::import(&exported).unwrap(), transcript);
+        }
+
+        // Compact binary is at least half the size of the hex in JSON
+        let json = transcript.to_json();
+        assert!(2 * transcript.export(TranscriptFormat::Binary).len() < json.len());
+        assert!(verify(&RoundTranscript::from_json(json.as_bytes()).unwrap()).passed());
+
+        let other = json.replace("unicorn-round-transcript", "something-else");
+        assert!(matches!(
+            RoundTranscript::<u64>::import(other.as_bytes()),
+            Err(TranscriptError::UnknownFormat)
+        ));
+        let damaged = json.replacen(&hex::encode(&transcript.seed), "zz", 1);
+        assert!(matches!(
+            RoundTranscript::<u64>::import(damaged.as_bytes()),
+            Err(TranscriptError::Hex(_))
+        ));
+    }
+}
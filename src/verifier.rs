@@ -0,0 +1,474 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::panic;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use vdf::{PietrzakVDFParams, VDFParams, WesolowskiVDFParams, VDF};
+
+use crate::delay::{DelayError, DelayFunction, DelayParameters, DelayState};
+
+/// Checks VDF results received from the participants.
+pub trait VdfVerifier {
+    /// Returns `true` if `value` is a valid VDF output with proof for the `seed`.
+    fn verify(&self, seed: &[u8], value: &[u8]) -> bool;
+
+    /// Scheme of the results this verifier accepts, `None` if it isn't tied to one.
+    fn scheme(&self) -> Option<VdfScheme> {
+        None
+    }
+}
+
+/// VDF construction from the `vdf` crate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VdfScheme {
+    Pietrzak,
+    /// Much smaller proofs than Pietrzak's, but slower to verify.
+    Wesolowski,
+}
+
+impl VdfScheme {
+    /// Identifies the scheme in encoded messages.
+    pub fn id(self) -> u8 {
+        match self {
+            VdfScheme::Pietrzak => 1,
+            VdfScheme::Wesolowski => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(VdfScheme::Pietrzak),
+            2 => Some(VdfScheme::Wesolowski),
+            _ => None,
+        }
+    }
+
+    /// Calculates VDF of the `seed` together with its proof.
+    pub fn solve(
+        self,
+        int_size_bits: u16,
+        seed: &[u8],
+        difficulty: u64,
+    ) -> Result<Vec<u8>, DelayError> {
+        let result = match self {
+            VdfScheme::Pietrzak => PietrzakVDFParams(int_size_bits)
+                .new()
+                .solve(seed, difficulty),
+            VdfScheme::Wesolowski => WesolowskiVDFParams(int_size_bits)
+                .new()
+                .solve(seed, difficulty),
+        };
+
+        result.map_err(|_| DelayError::InvalidDifficulty(difficulty))
+    }
+
+    /// Fails if the scheme can't be calculated with the `difficulty`, e.g. Pietrzak's
+    /// construction only takes even difficulties of at least 66.
+    pub fn check_difficulty(self, int_size_bits: u16, difficulty: u64) -> Result<(), DelayError> {
+        let checked = match self {
+            VdfScheme::Pietrzak => PietrzakVDFParams(int_size_bits)
+                .new()
+                .check_difficulty(difficulty),
+            VdfScheme::Wesolowski => WesolowskiVDFParams(int_size_bits)
+                .new()
+                .check_difficulty(difficulty),
+        };
+
+        checked.map_err(|_| DelayError::InvalidDifficulty(difficulty))
+    }
+
+    fn parameters(self, int_size_bits: u16, difficulty: u64) -> DelayParameters {
+        DelayParameters {
+            name: self.to_string(),
+            difficulty,
+            size_bits: int_size_bits,
+        }
+    }
+}
+
+impl fmt::Display for VdfScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VdfScheme::Pietrzak => write!(f, "pietrzak"),
+            VdfScheme::Wesolowski => write!(f, "wesolowski"),
+        }
+    }
+}
+
+impl FromStr for VdfScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pietrzak" => Ok(VdfScheme::Pietrzak),
+            "wesolowski" => Ok(VdfScheme::Wesolowski),
+            _ => Err(format!("unknown VDF scheme {}", s)),
+        }
+    }
+}
+
+/// `vdf` crate asserts on some malformed proofs instead of returning an error,
+/// treat such panics as failed verification.
+fn verify_with<V: VDF + panic::RefUnwindSafe>(
+    vdf: V,
+    seed: &[u8],
+    difficulty: u64,
+    value: &[u8],
+) -> bool {
+    panic::catch_unwind(|| vdf.verify(seed, difficulty, value).is_ok()).unwrap_or(false)
+}
+
+/// Verifier for the Pietrzak VDF from the `vdf` crate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PietrzakVerifier {
+    /// Size of the class group discriminant in bits.
+    pub int_size_bits: u16,
+
+    /// Number of sequential squarings the VDF was evaluated with.
+    pub difficulty: u64,
+}
+
+impl PietrzakVerifier {
+    pub fn new(int_size_bits: u16, difficulty: u64) -> Self {
+        PietrzakVerifier {
+            int_size_bits,
+            difficulty,
+        }
+    }
+}
+
+impl VdfVerifier for PietrzakVerifier {
+    fn verify(&self, seed: &[u8], value: &[u8]) -> bool {
+        let vdf = PietrzakVDFParams(self.int_size_bits).new();
+
+        verify_with(vdf, seed, self.difficulty, value)
+    }
+
+    fn scheme(&self) -> Option<VdfScheme> {
+        Some(VdfScheme::Pietrzak)
+    }
+}
+
+impl DelayFunction for PietrzakVerifier {
+    fn solve(&self, seed: &[u8]) -> Result<Vec<u8>, DelayError> {
+        VdfScheme::Pietrzak.solve(self.int_size_bits, seed, self.difficulty)
+    }
+
+    fn parameters(&self) -> DelayParameters {
+        VdfScheme::Pietrzak.parameters(self.int_size_bits, self.difficulty)
+    }
+
+    /// Unknown, the `vdf` crate doesn't tell how its proofs are laid out.
+    fn proof_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Verifier for the Wesolowski VDF from the `vdf` crate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WesolowskiVerifier {
+    /// Size of the class group discriminant in bits.
+    pub int_size_bits: u16,
+
+    /// Number of sequential squarings the VDF was evaluated with.
+    pub difficulty: u64,
+}
+
+impl WesolowskiVerifier {
+    pub fn new(int_size_bits: u16, difficulty: u64) -> Self {
+        WesolowskiVerifier {
+            int_size_bits,
+            difficulty,
+        }
+    }
+}
+
+impl VdfVerifier for WesolowskiVerifier {
+    fn verify(&self, seed: &[u8], value: &[u8]) -> bool {
+        let vdf = WesolowskiVDFParams(self.int_size_bits).new();
+
+        verify_with(vdf, seed, self.difficulty, value)
+    }
+
+    fn scheme(&self) -> Option<VdfScheme> {
+        Some(VdfScheme::Wesolowski)
+    }
+}
+
+impl DelayFunction for WesolowskiVerifier {
+    fn solve(&self, seed: &[u8]) -> Result<Vec<u8>, DelayError> {
+        VdfScheme::Wesolowski.solve(self.int_size_bits, seed, self.difficulty)
+    }
+
+    fn parameters(&self) -> DelayParameters {
+        VdfScheme::Wesolowski.parameters(self.int_size_bits, self.difficulty)
+    }
+
+    /// Unknown, the `vdf` crate doesn't tell how its proofs are laid out.
+    fn proof_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Verifier for the scheme chosen at runtime.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SchemeVerifier {
+    pub scheme: VdfScheme,
+
+    /// Size of the class group discriminant in bits.
+    pub int_size_bits: u16,
+
+    /// Number of sequential squarings the VDF was evaluated with.
+    pub difficulty: u64,
+}
+
+impl SchemeVerifier {
+    pub fn new(scheme: VdfScheme, int_size_bits: u16, difficulty: u64) -> Self {
+        SchemeVerifier {
+            scheme,
+            int_size_bits,
+            difficulty,
+        }
+    }
+}
+
+impl VdfVerifier for SchemeVerifier {
+    fn verify(&self, seed: &[u8], value: &[u8]) -> bool {
+        match self.scheme {
+            VdfScheme::Pietrzak => {
+                PietrzakVerifier::new(self.int_size_bits, self.difficulty).verify(seed, value)
+            }
+            VdfScheme::Wesolowski => {
+                WesolowskiVerifier::new(self.int_size_bits, self.difficulty).verify(seed, value)
+            }
+        }
+    }
+
+    fn scheme(&self) -> Option<VdfScheme> {
+        Some(self.scheme)
+    }
+}
+
+impl DelayFunction for SchemeVerifier {
+    fn solve(&self, seed: &[u8]) -> Result<Vec<u8>, DelayError> {
+        self.scheme.solve(self.int_size_bits, seed, self.difficulty)
+    }
+
+    fn parameters(&self) -> DelayParameters {
+        self.scheme.parameters(self.int_size_bits, self.difficulty)
+    }
+
+    /// Unknown, the `vdf` crate doesn't tell how its proofs are laid out.
+    fn proof_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Results remembered by a `CachedVerifier` by default, a handful of rounds worth of them.
+pub const DEFAULT_CACHE_CAPACITY: usize = 64;
+
+/// Valid results by seed and value, with the moment they were last looked up.
+#[derive(Debug, Default)]
+struct Cache {
+    results: HashMap<(Vec<u8>, Vec<u8>), u64>,
+    clock: u64,
+}
+
+impl Cache {
+    fn contains(&mut self, key: &(Vec<u8>, Vec<u8>)) -> bool {
+        self.clock += 1;
+        match self.results.get_mut(key) {
+            Some(used) => {
+                *used = self.clock;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remembers the result, forgetting the least recently used one if there are too many.
+    fn insert(&mut self, key: (Vec<u8>, Vec<u8>), capacity: usize) {
+        if self.results.len() >= capacity && !self.results.contains_key(&key) {
+            let oldest = self
+                .results
+                .iter()
+                .min_by_key(|(_, used)| **used)
+                .map(|(key, _)| key.clone());
+
+            if let Some(oldest) = oldest {
+                self.results.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.results.insert(key, self.clock);
+    }
+}
+
+/// Remembers valid results of another verifier, so every distinct result is verified only once.
+///
+/// Clones share the cache, which lets peers of a single process skip re-verifying results
+/// their neighbours already checked. Invalid results aren't remembered, nor are more than
+/// `capacity` valid ones, so bogus proofs sent by others can't grow the cache.
+#[derive(Debug, Clone)]
+pub struct CachedVerifier<V> {
+    inner: V,
+    cache: Arc<Mutex<Cache>>,
+    capacity: usize,
+}
+
+impl<V> CachedVerifier<V> {
+    pub fn new(inner: V) -> Self {
+        CachedVerifier {
+            inner,
+            cache: Arc::default(),
+            capacity: DEFAULT_CACHE_CAPACITY,
+        }
+    }
+
+    /// Remembers at most `capacity` results instead of `DEFAULT_CACHE_CAPACITY`.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+}
+
+impl<V: VdfVerifier> VdfVerifier for CachedVerifier<V> {
+    fn verify(&self, seed: &[u8], value: &[u8]) -> bool {
+        let key = (seed.to_vec(), value.to_vec());
+        if self.cache.lock().unwrap().contains(&key) {
+            return true;
+        }
+
+        // Don't hold the lock during verification, other threads may check different results
+        let valid = self.inner.verify(seed, value);
+        if valid {
+            self.cache.lock().unwrap().insert(key, self.capacity);
+        }
+
+        valid
+    }
+
+    fn scheme(&self) -> Option<VdfScheme> {
+        self.inner.scheme()
+    }
+}
+
+impl<V: DelayFunction> DelayFunction for CachedVerifier<V> {
+    fn solve(&self, seed: &[u8]) -> Result<Vec<u8>, DelayError> {
+        self.inner.solve(seed)
+    }
+
+    fn solve_with_progress(
+        &self,
+        seed: &[u8],
+        progress: &dyn Fn(u64) -> bool,
+    ) -> Result<Vec<u8>, DelayError> {
+        self.inner.solve_with_progress(seed, progress)
+    }
+
+    fn resume(
+        &self,
+        seed: &[u8],
+        from: Option<&DelayState>,
+        progress: &dyn Fn(u64) -> bool,
+        save: &dyn Fn(DelayState),
+    ) -> Result<Vec<u8>, DelayError> {
+        self.inner.resume(seed, from, progress, save)
+    }
+
+    fn parameters(&self) -> DelayParameters {
+        self.inner.parameters()
+    }
+
+    fn proof_size(&self) -> Option<usize> {
+        self.inner.proof_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Accepts non-empty values and counts the calls, clones share the counter.
+    #[derive(Clone, Default)]
+    struct Counting {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl VdfVerifier for Counting {
+        fn verify(&self, _seed: &[u8], value: &[u8]) -> bool {
+            self.calls.set(self.calls.get() + 1);
+            !value.is_empty()
+        }
+    }
+
+    #[test]
+    pub fn test_cached_verifier() {
+        let verifier = CachedVerifier::new(Counting::default());
+        let shared = verifier.clone();
+
+        assert!(verifier.verify(b"seed", b"value"));
+        assert!(shared.verify(b"seed", b"value"));
+        assert_eq!(verifier.inner.calls.get(), 1);
+
+        // Invalid results are checked every time, they take no room in the cache
+        assert!(!verifier.verify(b"seed", b""));
+        assert!(!shared.verify(b"seed", b""));
+        assert_eq!(verifier.inner.calls.get(), 3);
+        assert_eq!(verifier.cache.lock().unwrap().results.len(), 1);
+    }
+
+    #[test]
+    pub fn test_cache_capacity() {
+        let verifier = CachedVerifier::new(Counting::default()).with_capacity(2);
+
+        verifier.verify(b"seed", b"a");
+        verifier.verify(b"seed", b"b");
+        verifier.verify(b"seed", b"a");
+
+        // "b" is the least recently used one
+        verifier.verify(b"seed", b"c");
+        assert_eq!(verifier.cache.lock().unwrap().results.len(), 2);
+        assert_eq!(verifier.inner.calls.get(), 3);
+
+        verifier.verify(b"seed", b"a");
+        assert_eq!(verifier.inner.calls.get(), 3);
+        verifier.verify(b"seed", b"b");
+        assert_eq!(verifier.inner.calls.get(), 4);
+    }
+
+    #[test]
+    pub fn test_schemes() {
+        let seed = b"seed";
+
+        for scheme in [VdfScheme::Pietrzak, VdfScheme::Wesolowski].iter() {
+            let value = scheme.solve(512, seed, 100).unwrap();
+            let verifier = SchemeVerifier::new(*scheme, 512, 100);
+
+            assert!(verifier.verify(seed, &value));
+            assert_eq!(scheme.to_string().parse(), Ok(*scheme));
+            assert_eq!(VdfScheme::from_id(scheme.id()), Some(*scheme));
+        }
+
+        // Wesolowski proof is a single group element, Pietrzak's grows with the difficulty
+        let pietrzak = VdfScheme::Pietrzak.solve(512, seed, 1000).unwrap();
+        let wesolowski = VdfScheme::Wesolowski.solve(512, seed, 1000).unwrap();
+        assert!(wesolowski.len() < pietrzak.len());
+
+        assert!(VdfScheme::Pietrzak.check_difficulty(512, 1000).is_ok());
+        assert!(VdfScheme::Pietrzak.check_difficulty(512, 1001).is_err());
+        assert!(VdfScheme::Pietrzak.check_difficulty(512, 64).is_err());
+        assert!(VdfScheme::Wesolowski.check_difficulty(512, 1001).is_ok());
+
+        // Proof of one scheme doesn't pass verification of the other
+        assert!(!WesolowskiVerifier::new(512, 1000).verify(seed, &pietrzak));
+        assert!(!PietrzakVerifier::new(512, 1000).verify(seed, &wesolowski));
+    }
+}
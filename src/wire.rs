@@ -0,0 +1,438 @@
+//! Canonical binary encoding of protocol messages.
+//!
+//! Every message travels in a frame:
+//!
+//! ```text
+//! magic "UNCN" | version: u8 | kind: u8 | payload length: u32 | payload
+//! ```
+//!
+//! Integers are big-endian, variable-length byte strings are prefixed with their `u32` length.
+//! Decoders reject trailing bytes, so every message has exactly one encoding.
+
+/// Marks the beginning of every frame.
+pub const MAGIC: [u8; 4] = *b"UNCN";
+
+/// Version of the encoding produced by this implementation.
+pub const VERSION: u8 = 3;
+
+/// Size of the frame header preceding the payload.
+pub const HEADER_LEN: usize = 10;
+
+/// Frames with bigger payloads are rejected without buffering them.
+pub const MAX_PAYLOAD_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WireError {
+    /// Frame doesn't start with `MAGIC`.
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// Frame carries a different kind of message than the one being decoded.
+    UnexpectedKind(u8),
+    /// Input ended in the middle of a frame or a field.
+    Truncated,
+    /// Payload has bytes left after the last field.
+    TrailingBytes,
+    PayloadTooLarge,
+    /// Message of the `kind` holds a tag this implementation doesn't know, e.g. a VDF scheme.
+    UnknownTag {
+        kind: u8,
+        tag: u8,
+    },
+    /// Optional field is marked neither present nor absent.
+    InvalidOption(u8),
+    /// Text field isn't valid UTF-8.
+    InvalidText,
+}
+
+/// Complete frame found at the beginning of a stream.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame<'a> {
+    /// Type of the message in the frame.
+    pub kind: u8,
+    pub payload: &'a [u8],
+    /// Total length of the frame including the header.
+    pub len: usize,
+}
+
+/// Message that can be carried in a frame.
+pub trait WireMessage: Sized {
+    /// Identifies the type of the message in the frame header.
+    const KIND: u8;
+
+    fn encode_payload(&self, writer: &mut Writer);
+    fn decode_payload(reader: &mut Reader) -> Result<Self, WireError>;
+}
+
+/// Participant ID that can be a field of a payload.
+pub trait WireId: Sized {
+    fn put(&self, writer: &mut Writer);
+    fn get(reader: &mut Reader) -> Result<Self, WireError>;
+}
+
+impl WireId for u32 {
+    fn put(&self, writer: &mut Writer) {
+        writer.put_u32(*self);
+    }
+
+    fn get(reader: &mut Reader) -> Result<Self, WireError> {
+        reader.get_u32()
+    }
+}
+
+impl WireId for u64 {
+    fn put(&self, writer: &mut Writer) {
+        writer.put_u64(*self);
+    }
+
+    fn get(reader: &mut Reader) -> Result<Self, WireError> {
+        reader.get_u64()
+    }
+}
+
+/// Appends fields to the payload being encoded.
+#[derive(Debug, Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer { bytes: vec![] }
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Writes byte string of a length known to the decoder, without a prefix.
+    pub fn put_fixed(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Writes length-prefixed byte string.
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_u32(bytes.len() as u32);
+        self.put_fixed(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads fields of the payload being decoded.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        if self.bytes.len() < len {
+            return Err(WireError::Truncated);
+        }
+
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+
+        Ok(head)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, WireError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, WireError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn get_fixed<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+
+        Ok(bytes)
+    }
+
+    pub fn get_bytes(&mut self) -> Result<Vec<u8>, WireError> {
+        let len = self.get_u32()? as usize;
+
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Makes sure the whole input was consumed.
+    pub fn finish(self) -> Result<(), WireError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(WireError::TrailingBytes)
+        }
+    }
+}
+
+/// Writes optional field, prefixed with 1 if present and 0 if not.
+pub fn put_option<T, F: Fn(&T, &mut Writer)>(writer: &mut Writer, value: &Option<T>, put: F) {
+    match value {
+        Some(value) => {
+            writer.put_u8(1);
+            put(value, writer);
+        }
+        None => writer.put_u8(0),
+    }
+}
+
+pub fn get_option<T, F>(reader: &mut Reader, get: F) -> Result<Option<T>, WireError>
+where
+    F: Fn(&mut Reader) -> Result<T, WireError>,
+{
+    match reader.get_u8()? {
+        0 => Ok(None),
+        1 => get(reader).map(Some),
+        flag => Err(WireError::InvalidOption(flag)),
+    }
+}
+
+/// Writes list of fields prefixed with their `u32` count.
+pub fn put_list<T, F: Fn(&T, &mut Writer)>(writer: &mut Writer, items: &[T], put: F) {
+    writer.put_u32(items.len() as u32);
+    for item in items {
+        put(item, writer);
+    }
+}
+
+pub fn get_list<T, F>(reader: &mut Reader, get: F) -> Result<Vec<T>, WireError>
+where
+    F: Fn(&mut Reader) -> Result<T, WireError>,
+{
+    // Length isn't trusted to preallocate, a bogus one runs out of input instead
+    let len = reader.get_u32()?;
+    (0..len).map(|_| get(reader)).collect()
+}
+
+/// Encodes the message into a complete frame.
+pub fn encode<M: WireMessage>(msg: &M) -> Vec<u8> {
+    encode_with_version(msg, VERSION)
+}
+
+/// Encodes the message into a complete frame of another `version` than the one of
+/// the protocol, for files versioned on their own.
+pub fn encode_with_version<M: WireMessage>(msg: &M, version: u8) -> Vec<u8> {
+    let mut payload = Writer::new();
+    msg.encode_payload(&mut payload);
+    let payload = payload.into_bytes();
+
+    let mut frame = Writer::new();
+    frame.put_fixed(&MAGIC);
+    frame.put_u8(version);
+    frame.put_u8(M::KIND);
+    frame.put_bytes(&payload);
+
+    frame.into_bytes()
+}
+
+/// Splits the first complete frame off the `bytes` received from a stream.
+///
+/// Returns `None` if more bytes are needed to complete the frame.
+pub fn split_frame(bytes: &[u8]) -> Result<Option<Frame<'_>>, WireError> {
+    split_frame_with_version(bytes, VERSION)
+}
+
+/// Splits the first complete frame of the given `version` off the `bytes`,
+/// see `encode_with_version`.
+pub fn split_frame_with_version(bytes: &[u8], version: u8) -> Result<Option<Frame<'_>>, WireError> {
+    if bytes.len() < HEADER_LEN {
+        // Fail early on garbage instead of waiting for the complete header
+        let magic_len = bytes.len().min(MAGIC.len());
+        if bytes[..magic_len] != MAGIC[..magic_len] {
+            return Err(WireError::BadMagic);
+        }
+
+        return Ok(None);
+    }
+
+    let mut header = Reader::new(&bytes[..HEADER_LEN]);
+    if header.get_fixed::<4>()? != MAGIC {
+        return Err(WireError::BadMagic);
+    }
+
+    match header.get_u8()? {
+        v if v == version => {}
+        other => return Err(WireError::UnsupportedVersion(other)),
+    }
+
+    let kind = header.get_u8()?;
+    let len = header.get_u32()? as usize;
+    if len > MAX_PAYLOAD_LEN {
+        return Err(WireError::PayloadTooLarge);
+    }
+
+    if bytes.len() < HEADER_LEN + len {
+        return Ok(None);
+    }
+
+    Ok(Some(Frame {
+        kind,
+        payload: &bytes[HEADER_LEN..HEADER_LEN + len],
+        len: HEADER_LEN + len,
+    }))
+}
+
+/// Decodes the payload of a frame of the given kind.
+pub fn decode_payload<M: WireMessage>(kind: u8, payload: &[u8]) -> Result<M, WireError> {
+    if kind != M::KIND {
+        return Err(WireError::UnexpectedKind(kind));
+    }
+
+    let mut reader = Reader::new(payload);
+    let msg = M::decode_payload(&mut reader)?;
+    reader.finish()?;
+
+    Ok(msg)
+}
+
+/// Decodes a message from exactly one complete frame.
+pub fn decode<M: WireMessage>(frame: &[u8]) -> Result<M, WireError> {
+    match split_frame(frame)? {
+        Some(f) if f.len == frame.len() => decode_payload(f.kind, f.payload),
+        Some(_) => Err(WireError::TrailingBytes),
+        None => Err(WireError::Truncated),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Ping {
+        nonce: u64,
+        tag: [u8; 2],
+        data: Vec<u8>,
+    }
+
+    impl WireMessage for Ping {
+        const KIND: u8 = 0xff;
+
+        fn encode_payload(&self, writer: &mut Writer) {
+            writer.put_u64(self.nonce);
+            writer.put_fixed(&self.tag);
+            writer.put_bytes(&self.data);
+        }
+
+        fn decode_payload(reader: &mut Reader) -> Result<Self, WireError> {
+            Ok(Ping {
+                nonce: reader.get_u64()?,
+                tag: reader.get_fixed()?,
+                data: reader.get_bytes()?,
+            })
+        }
+    }
+
+    fn ping() -> Ping {
+        Ping {
+            nonce: 0x0102030405060708,
+            tag: [0xaa, 0xbb],
+            data: vec![1, 2, 3],
+        }
+    }
+
+    #[test]
+    pub fn test_frame_layout() {
+        assert_eq!(
+            hex::encode(encode(&ping())),
+            concat!(
+                "554e434e",
+                "03",
+                "ff",
+                "00000011", // header
+                "0102030405060708",
+                "aabb",
+                "00000003",
+                "010203" // payload
+            )
+        );
+        assert_eq!(decode::<Ping>(&encode(&ping())), Ok(ping()));
+    }
+
+    #[test]
+    pub fn test_split_stream() {
+        let mut stream = encode(&ping());
+        stream.extend(encode(&ping()));
+
+        // Incomplete frame needs more bytes
+        assert_eq!(split_frame(&stream[..5]), Ok(None));
+        assert_eq!(split_frame(&stream[..HEADER_LEN + 1]), Ok(None));
+
+        let frame = split_frame(&stream).unwrap().unwrap();
+        assert_eq!(frame.kind, Ping::KIND);
+        assert_eq!(
+            decode_payload::<Ping>(frame.kind, frame.payload),
+            Ok(ping())
+        );
+        assert_eq!(decode::<Ping>(&stream[frame.len..]), Ok(ping()));
+    }
+
+    #[test]
+    pub fn test_malformed_frames() {
+        let frame = encode(&ping());
+
+        let mut bad_magic = frame.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(decode::<Ping>(&bad_magic), Err(WireError::BadMagic));
+        assert_eq!(split_frame(b"XY"), Err(WireError::BadMagic));
+
+        let mut bad_version = frame.clone();
+        bad_version[4] = VERSION + 1;
+        assert_eq!(
+            decode::<Ping>(&bad_version),
+            Err(WireError::UnsupportedVersion(VERSION + 1))
+        );
+
+        let mut bad_kind = frame.clone();
+        bad_kind[5] = 0x01;
+        assert_eq!(
+            decode::<Ping>(&bad_kind),
+            Err(WireError::UnexpectedKind(0x01))
+        );
+
+        let mut huge = frame.clone();
+        huge[6..10].copy_from_slice(&(MAX_PAYLOAD_LEN as u32 + 1).to_be_bytes());
+        assert_eq!(decode::<Ping>(&huge), Err(WireError::PayloadTooLarge));
+
+        assert_eq!(
+            decode::<Ping>(&frame[..frame.len() - 1]),
+            Err(WireError::Truncated)
+        );
+
+        let mut trailing = frame.clone();
+        trailing.push(0);
+        assert_eq!(decode::<Ping>(&trailing), Err(WireError::TrailingBytes));
+
+        // Length of the inner field points past the end of the payload
+        let mut bad_field = frame;
+        let len = bad_field.len();
+        bad_field[len - 4] = 0xff;
+        assert_eq!(decode::<Ping>(&bad_field), Err(WireError::Truncated));
+    }
+}
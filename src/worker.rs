@@ -0,0 +1,166 @@
+//! Pool of threads calculating VDF, so peers keep handling messages in the meantime.
+//!
+//! A cancelled calculation frees its worker only as soon as the delay function checks for
+//! cancellation. `IteratedHash` does so every 65536 steps, but the schemes of the `vdf`
+//! crate only check before they start: once started, they keep their worker busy until they
+//! finish, and the result is thrown away.
+
+use actix::prelude::*;
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use unicorn_p2p_rng::{DelayError, DelayFunction, DelayState};
+
+/// VDF calculation shared by the peer that requested it and the worker performing it.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Steps of the delay function performed so far.
+    steps: Arc<AtomicU64>,
+    /// Latest intermediate state the calculation can be resumed from.
+    saved: Arc<Mutex<Option<DelayState>>>,
+    cancelled: Arc<AtomicBool>,
+    pub started: Instant,
+}
+
+impl Job {
+    pub fn new() -> Self {
+        Job {
+            steps: Arc::default(),
+            saved: Arc::default(),
+            cancelled: Arc::default(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps.load(Ordering::Relaxed)
+    }
+
+    pub fn saved(&self) -> Option<DelayState> {
+        self.saved.lock().unwrap().clone()
+    }
+
+    /// Stops the calculation as soon as the delay function allows, or before it starts.
+    ///
+    /// See the module documentation for the functions that can't be stopped midway.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether both handles refer to the same calculation.
+    pub fn is(&self, other: &Job) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+
+    fn progress(&self, steps: u64) -> bool {
+        self.steps.store(steps, Ordering::Relaxed);
+
+        !self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn save(&self, state: DelayState) {
+        *self.saved.lock().unwrap() = Some(state);
+    }
+}
+
+/// Calculate `delay` function for the `seed`.
+pub struct Solve<F> {
+    pub delay: F,
+    pub seed: Vec<u8>,
+    /// State saved by an earlier calculation for the same seed to continue from.
+    pub from: Option<DelayState>,
+    pub job: Job,
+}
+
+impl<F: 'static> Message for Solve<F> {
+    type Result = Result<Vec<u8>, DelayError>;
+}
+
+/// One of the threads of the pool, see `start`.
+pub struct VdfWorker<F> {
+    // Workers don't own a delay function, they get one with every job
+    delay: PhantomData<fn() -> F>,
+}
+
+impl<F: 'static> Actor for VdfWorker<F> {
+    type Context = SyncContext<Self>;
+}
+
+impl<F: DelayFunction + Send + 'static> Handler<Solve<F>> for VdfWorker<F> {
+    type Result = Result<Vec<u8>, DelayError>;
+
+    fn handle(&mut self, msg: Solve<F>, _: &mut Self::Context) -> Self::Result {
+        let job = msg.job;
+
+        msg.delay.resume(
+            &msg.seed,
+            msg.from.as_ref(),
+            &|steps| job.progress(steps),
+            &|state| job.save(state),
+        )
+    }
+}
+
+/// Starts a pool of `threads` workers, or of one worker per CPU core if not given.
+pub fn start<F: DelayFunction + Send + 'static>(threads: Option<usize>) -> Addr<VdfWorker<F>> {
+    let threads = threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+
+    SyncArbiter::start(threads, || VdfWorker { delay: PhantomData })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unicorn_p2p_rng::IteratedHash;
+
+    #[test]
+    pub fn test_solve_and_cancel() {
+        let mut system = System::new("test");
+        let workers = start(Some(2));
+        let delay = IteratedHash::new(1_000);
+
+        let job = Job::new();
+        let solve = Solve {
+            delay,
+            seed: b"seed".to_vec(),
+            from: None,
+            job: job.clone(),
+        };
+        let value = system.block_on(workers.send(solve)).unwrap();
+        assert_eq!(value, delay.solve(b"seed"));
+        assert_eq!(job.steps(), 1_000);
+        assert!(job.is(&job.clone()) && !job.is(&Job::new()));
+
+        // Calculation picks up from the state the previous one saved
+        let delay = IteratedHash::new(200_000);
+        let saved = DelayState {
+            steps: 100_000,
+            state: IteratedHash::new(100_000).solve(b"seed").unwrap(),
+        };
+        let job = Job::new();
+        let solve = Solve {
+            delay,
+            seed: b"seed".to_vec(),
+            from: Some(saved),
+            job: job.clone(),
+        };
+        let value = system.block_on(workers.send(solve)).unwrap();
+        assert_eq!(value, delay.solve(b"seed"));
+        assert_eq!(job.saved().map(|saved| saved.steps), Some(196_608));
+
+        let job = Job::new();
+        job.cancel();
+        let solve = Solve {
+            delay: IteratedHash::new(1_000),
+            seed: b"seed".to_vec(),
+            from: None,
+            job,
+        };
+        let value = system.block_on(workers.send(solve)).unwrap();
+        assert_eq!(value, Err(DelayError::Cancelled));
+    }
+}
@@ -0,0 +1,190 @@
+//! Runs several nodes as separate processes connected over loopback TCP
+//! and checks that they agree on the same random number, round after round.
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ed25519_dalek::SigningKey;
+use unicorn_p2p_rng::History;
+
+const NUM_NODES: usize = 4;
+
+/// Gives up if nodes haven't agreed in time.
+const DEADLINE: Duration = Duration::from_secs(120);
+
+/// Kills the node when the test finishes, even if it panics.
+struct Node(Child);
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+pub fn test_nodes_agree_over_tcp() {
+    let dir = env::temp_dir().join(format!("unicorn-tcp-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let first = run_nodes(&dir, 0);
+
+    // Nodes started once again carry on after the round they stored
+    let second = run_nodes(&dir, 1);
+    assert_ne!(first, second);
+
+    let history = dir.join("node-0").join("history");
+    let records = History::<u32>::open(&history).unwrap().records().unwrap();
+    assert_eq!(
+        records.iter().map(|r| r.round).collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert_eq!(hex::encode(&records[0].randomness), first);
+    assert_eq!(hex::encode(&records[1].randomness), second);
+    assert_eq!(
+        records[1].previous_randomness.as_ref(),
+        Some(&records[0].randomness)
+    );
+    assert!(records[1].commitments.len() >= NUM_NODES - 1);
+
+    // Anyone knowing the public keys of the nodes can repeat every round from its transcript
+    for (round, randomness) in [first, second].iter().enumerate() {
+        let transcript = dir.join("node-0").join(format!("round-{}.json", round));
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_unicorn-p2p-rng"));
+        cmd.arg("verify")
+            .arg("--transcript")
+            .arg(&transcript)
+            .args(["--difficulty", "1000"]);
+        for id in 0..NUM_NODES {
+            cmd.arg("--signer").arg(format!(
+                "{}@{}",
+                id,
+                hex::encode(key(id).verifying_key().to_bytes())
+            ));
+        }
+        let output = cmd.output().unwrap();
+        let report = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", report);
+        assert!(report.contains(randomness), "{}", report);
+        assert!(report.contains("threshold: ok"), "{}", report);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Key the node signs its messages with.
+fn key(id: usize) -> SigningKey {
+    SigningKey::from_bytes(&[id as u8 + 1; 32])
+}
+
+/// Runs the nodes until they agree on the `round`, returns the randomness they agreed on.
+///
+/// Every node stores its rounds, exports their transcripts and saves its checkpoint to
+/// a directory of its own in the `dir`.
+fn run_nodes(dir: &Path, round: u64) -> String {
+    // Let OS pick free ports, then release them for the nodes
+    let addrs = (0..NUM_NODES)
+        .map(|_| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    let keys = (0..NUM_NODES).map(key).collect::<Vec<_>>();
+
+    let (tx, rx) = mpsc::channel();
+    let mut nodes = vec![];
+
+    for id in 0..NUM_NODES {
+        let node_dir = dir.join(format!("node-{}", id));
+        fs::create_dir_all(&node_dir).unwrap();
+
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_unicorn-p2p-rng"));
+        cmd.arg("node")
+            .args(["--id", &id.to_string()])
+            .args(["--listen", &addrs[id].to_string()])
+            .args(["--key", &hex::encode(keys[id].to_bytes())])
+            .args(["--difficulty", "1000"])
+            .arg("--history")
+            .arg(node_dir.join("history"))
+            .arg("--transcripts")
+            .arg(&node_dir)
+            .arg("--checkpoint")
+            .arg(node_dir.join("checkpoint"));
+
+        for other in (0..NUM_NODES).filter(|&other| other != id) {
+            cmd.arg("--peer").arg(format!(
+                "{}@{}@{}",
+                other,
+                addrs[other],
+                hex::encode(keys[other].verifying_key().to_bytes())
+            ));
+        }
+
+        let mut child = cmd.stdout(Stdio::piped()).spawn().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        nodes.push(Node(child));
+
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => return,
+                };
+
+                if line.starts_with("[SUCCESS]") || line.starts_with("[FAILURE]") {
+                    let _ = tx.send((id, line));
+                }
+            }
+        });
+    }
+
+    let started = Instant::now();
+    let mut agreed = vec![None; NUM_NODES];
+
+    while agreed.iter().any(Option::is_none) {
+        let timeout = DEADLINE
+            .checked_sub(started.elapsed())
+            .expect("nodes didn't agree in time");
+        let (id, line) = rx
+            .recv_timeout(timeout)
+            .expect("nodes didn't agree in time");
+
+        assert!(line.starts_with("[SUCCESS]"), "node #{}: {}", id, line);
+
+        let randomness = line
+            .split("agreed on: ")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap()
+            .to_string();
+        agreed[id] = Some(randomness);
+    }
+
+    assert!(
+        agreed.windows(2).all(|pair| pair[0] == pair[1]),
+        "nodes disagree: {:?}",
+        agreed
+    );
+
+    // Round is stored and exported right after it's reported, its checkpoint is gone then
+    for id in 0..NUM_NODES {
+        let node_dir = dir.join(format!("node-{}", id));
+        let transcript = node_dir.join(format!("round-{}.json", round));
+        while !transcript.exists() || node_dir.join("checkpoint").exists() {
+            assert!(started.elapsed() < DEADLINE, "round wasn't stored in time");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+    drop(nodes);
+
+    agreed[0].take().unwrap()
+}